@@ -0,0 +1,55 @@
+#![feature(test)]
+
+extern crate test;
+
+use bytes::Bytes;
+use test::Bencher;
+use ts_tracker::{
+    constants,
+    models::tracker::{AnnounceResponse, ResponsePeerList, TrackerError},
+};
+use ts_utils::bencode::{self, Bencode};
+
+fn create_announce_response() -> AnnounceResponse {
+    AnnounceResponse {
+        complete: 10,
+        crypto_flags: Some(Bytes::from(vec![0; 50])),
+        incomplete: 20,
+        interval: 1800,
+        min_interval: 900,
+        peers: Some(ResponsePeerList::Compact(vec![0; 50 * 6])),
+        peers6: None,
+        tracker_id: Some("ts".to_string()),
+        warning_message: None,
+    }
+}
+
+#[bench]
+fn announce_response_sorted(b: &mut Bencher) {
+    let response = create_announce_response();
+    b.iter(|| bencode::encode(&response).unwrap());
+}
+
+#[bench]
+fn announce_response_unsorted(b: &mut Bencher) {
+    let response = create_announce_response();
+    b.iter(|| response.bencode().unwrap());
+}
+
+#[bench]
+fn tracker_error_static(b: &mut Bencher) {
+    let err = TrackerError::from(constants::TRACKER_ERROR_BLOCKED_INFOHASH);
+    b.iter(|| err.static_bytes().unwrap());
+}
+
+#[bench]
+fn tracker_error_dynamic(b: &mut Bencher) {
+    let err = TrackerError::from(constants::TRACKER_ERROR_BLOCKED_INFOHASH);
+    b.iter(|| err.bencode().unwrap());
+}
+
+#[bench]
+fn announce_response_pooled(b: &mut Bencher) {
+    let response = create_announce_response();
+    b.iter(|| response.bencode_pooled().unwrap());
+}
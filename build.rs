@@ -0,0 +1,37 @@
+use std::{path::Path, process::Command};
+
+/// Exposes the git hash of the build as `TS_GIT_HASH`, or `unknown` outside of a git checkout.
+fn main() {
+    let git_hash = git(&["rev-parse", "--short", "HEAD"]).unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=TS_GIT_HASH={}", git_hash);
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // HEAD only changes on a checkout, a commit moves the branch it points at instead.
+    // Cargo reruns the script on every build for a missing file, so only existing ones are
+    // watched.
+    if let Some(git_dir) = git(&["rev-parse", "--git-dir"]) {
+        let head_ref = git(&["symbolic-ref", "-q", "HEAD"]);
+        let files = ["HEAD", "packed-refs"]
+            .into_iter()
+            .chain(head_ref.as_deref());
+
+        for file in files {
+            let path = Path::new(&git_dir).join(file);
+            if path.exists() {
+                println!("cargo:rerun-if-changed={}", path.display());
+            }
+        }
+    }
+}
+
+/// Runs git with `args`, and returns its trimmed output if it succeeded.
+fn git(args: &[&str]) -> Option<String> {
+    Command::new("git")
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|output| output.trim().to_string())
+}
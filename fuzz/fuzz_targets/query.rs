@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ts_tracker::models::tracker::{parse_announce, parse_scrape};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_announce(data);
+    let _ = parse_scrape(data);
+});
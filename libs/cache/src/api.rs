@@ -1,5 +1,13 @@
 use async_trait::async_trait;
-use std::{borrow::Cow, hash::Hash, sync::Arc, time::Duration};
+use std::{
+    borrow::Cow,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use crate::inner::CacheInner;
 
@@ -41,6 +49,15 @@ where
         self.inner.set(key, value).await
     }
 
+    /// Applies `update` to the cached value for key, if any, keeping its expiry.
+    /// Lets a write to the source be reflected without discarding the entry.
+    pub async fn update<F>(&self, key: K, update: F)
+    where
+        F: FnOnce(&mut V) + Send + 'static,
+    {
+        self.inner.update(key, Box::new(update)).await
+    }
+
     /// Discards any cached value for key.
     pub async fn invalidate(&self, key: CacheKey<'_, K>) -> Option<V> {
         self.inner.remove(key).await
@@ -58,6 +75,39 @@ where
     pub async fn invalidate_all(&self) {
         self.inner.clear().await
     }
+
+    /// Returns the lookup counters of the cache.
+    pub fn stats(&self) -> Arc<CacheStats> {
+        self.inner.stats.clone()
+    }
+}
+
+/// Counters of the lookups made by `Cache::get`.
+#[derive(Debug, Default)]
+pub struct CacheStats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CacheStats {
+    /// The number of lookups answered with a fresh cached value.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// The number of lookups answered with an expired or stale value, or that had to wait for
+    /// a load.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
 }
 
 /// An async trait for loading values into the cache.
@@ -112,6 +162,8 @@ pub struct Builder {
     pub(crate) max_capacity: usize,
     pub(crate) expiry: Duration,
     pub(crate) policy: Policy,
+    pub(crate) serve_stale: bool,
+    pub(crate) stale_max: Option<Duration>,
 }
 
 impl Default for Builder {
@@ -120,6 +172,8 @@ impl Default for Builder {
             max_capacity: 1_024,
             expiry: Duration::from_secs(10),
             policy: Policy::RefreshAfterAccess,
+            serve_stale: false,
+            stale_max: None,
         }
     }
 }
@@ -143,6 +197,20 @@ impl Builder {
         self
     }
 
+    /// Keeps serving the last loaded value of an entry when a refresh yields none,
+    /// instead of caching the missing value. Failed initial loads are retried on next access.
+    pub fn serve_stale(mut self, serve_stale: bool) -> Self {
+        self.serve_stale = serve_stale;
+        self
+    }
+
+    /// Bounds how long past its expiry a value is served by `serve_stale`, after which a
+    /// failed refresh drops it. Unbounded by default.
+    pub fn stale_max(mut self, stale_max: Duration) -> Self {
+        self.stale_max = Some(stale_max);
+        self
+    }
+
     /// Builds a new `Cache` instance using the provided loader and builder settings.
     pub fn build<K, V>(self, loader: Arc<dyn CacheLoader<Key = K, Value = V>>) -> Cache<K, V>
     where
@@ -161,3 +229,155 @@ pub enum Policy {
     /// Specifies that expired cache entries should be refreshed before they are accessed.
     RefreshBeforeAccess,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    struct DoubleLoader;
+
+    #[async_trait]
+    impl CacheLoader for DoubleLoader {
+        type Key = u32;
+        type Value = u32;
+
+        async fn load(&self, key: &Self::Key) -> Option<Self::Value> {
+            Some(key * 2)
+        }
+
+        async fn load_all(&self, keys: &[Self::Key]) -> Vec<(Self::Key, Self::Value)> {
+            keys.iter().map(|key| (*key, key * 2)).collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stats_hit_and_miss() {
+        let cache = Cache::builder()
+            .expiry(Duration::from_secs(60))
+            .build(Arc::new(DoubleLoader));
+        let stats = cache.stats();
+
+        assert_eq!(cache.get(1u32.into()).await, Some(2));
+        assert_eq!((stats.hits(), stats.misses()), (0, 1));
+
+        assert_eq!(cache.get(1u32.into()).await, Some(2));
+        assert_eq!((stats.hits(), stats.misses()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_stats_expired_is_miss() {
+        let cache = Cache::builder()
+            .expiry(Duration::from_millis(10))
+            .build(Arc::new(DoubleLoader));
+        let stats = cache.stats();
+
+        assert_eq!(cache.get(1u32.into()).await, Some(2));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        // The expired value is served while it refreshes.
+        assert_eq!(cache.get(1u32.into()).await, Some(2));
+        assert_eq!((stats.hits(), stats.misses()), (0, 2));
+    }
+
+    #[tokio::test]
+    async fn test_update() {
+        let cache = Cache::builder()
+            .expiry(Duration::from_secs(60))
+            .build(Arc::new(DoubleLoader));
+
+        assert_eq!(cache.get(1u32.into()).await, Some(2));
+        cache.update(1, |value| *value += 1).await;
+        assert_eq!(cache.get(1u32.into()).await, Some(3));
+
+        // An absent key is left to the loader.
+        cache.update(2, |value| *value += 1).await;
+        assert_eq!(cache.get(2u32.into()).await, Some(4));
+    }
+
+    /// Doubles keys until `failing` is set, then yields no value.
+    #[derive(Default)]
+    struct FlakyLoader {
+        failing: AtomicBool,
+    }
+
+    #[async_trait]
+    impl CacheLoader for FlakyLoader {
+        type Key = u32;
+        type Value = u32;
+
+        async fn load(&self, key: &Self::Key) -> Option<Self::Value> {
+            match self.failing.load(Ordering::Relaxed) {
+                true => None,
+                false => Some(key * 2),
+            }
+        }
+
+        async fn load_all(&self, keys: &[Self::Key]) -> Vec<(Self::Key, Self::Value)> {
+            keys.iter().map(|key| (*key, key * 2)).collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serve_stale_on_failed_refresh() {
+        let loader = Arc::new(FlakyLoader::default());
+        let cache = Cache::builder()
+            .expiry(Duration::from_millis(10))
+            .policy(Policy::RefreshBeforeAccess)
+            .serve_stale(true)
+            .build(loader.clone());
+
+        assert_eq!(cache.get(1u32.into()).await, Some(2));
+
+        loader.failing.store(true, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(cache.get(1u32.into()).await, Some(2));
+
+        // A failed initial load is not cached.
+        assert_eq!(cache.get(2u32.into()).await, None);
+        loader.failing.store(false, Ordering::Relaxed);
+        assert_eq!(cache.get(2u32.into()).await, Some(4));
+    }
+
+    #[tokio::test]
+    async fn test_stale_max() {
+        let loader = Arc::new(FlakyLoader::default());
+        let cache = Cache::builder()
+            .expiry(Duration::from_millis(10))
+            .policy(Policy::RefreshBeforeAccess)
+            .serve_stale(true)
+            .stale_max(Duration::from_millis(100))
+            .build(loader.clone());
+
+        assert_eq!(cache.get(1u32.into()).await, Some(2));
+
+        // Refreshes keep failing, the stale value is served until `stale_max` past its expiry.
+        loader.failing.store(true, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(cache.get(1u32.into()).await, Some(2));
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(cache.get(1u32.into()).await, Some(2));
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(cache.get(1u32.into()).await, None);
+
+        loader.failing.store(false, Ordering::Relaxed);
+        assert_eq!(cache.get(1u32.into()).await, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_failed_refresh_without_serve_stale() {
+        let loader = Arc::new(FlakyLoader::default());
+        let cache = Cache::builder()
+            .expiry(Duration::from_millis(10))
+            .policy(Policy::RefreshBeforeAccess)
+            .build(loader.clone());
+
+        assert_eq!(cache.get(1u32.into()).await, Some(2));
+
+        loader.failing.store(true, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(cache.get(1u32.into()).await, None);
+    }
+}
@@ -2,14 +2,15 @@ use std::hash::Hash;
 use std::{fmt, sync::Arc};
 use tokio::sync::{mpsc, oneshot};
 
-use crate::api::CacheKey;
+use crate::api::{CacheKey, CacheStats};
 use crate::{
-    internals::{CacheInternal, Message},
+    internals::{CacheInternal, Message, Updater},
     Builder, CacheLoader,
 };
 
 pub(crate) struct CacheInner<K: Clone, V> {
     tx: Arc<mpsc::Sender<Message<K, V>>>,
+    pub(crate) stats: Arc<CacheStats>,
 }
 
 impl<K, V> CacheInner<K, V>
@@ -21,13 +22,12 @@ where
         let (tx, rx) = mpsc::channel(128);
         let tx = Arc::new(tx);
         let weak_tx = Arc::downgrade(&tx);
+        let stats = Arc::new(CacheStats::default());
 
-        tokio::spawn(async move {
-            let internal = CacheInternal::new(statics, loader);
-            internal.run(rx, weak_tx).await
-        });
+        let internal = CacheInternal::new(statics, loader, stats.clone());
+        tokio::spawn(async move { internal.run(rx, weak_tx).await });
 
-        Self { tx }
+        Self { tx, stats }
     }
 
     pub(crate) async fn get(&self, key: CacheKey<'_, K>) -> Option<V> {
@@ -64,6 +64,10 @@ where
         let _ = self.tx.send(Message::Set(key, value)).await;
     }
 
+    pub(crate) async fn update(&self, key: K, update: Updater<V>) {
+        let _ = self.tx.send(Message::Update(key, update)).await;
+    }
+
     pub(crate) async fn remove(&self, key: CacheKey<'_, K>) -> Option<V> {
         let (tx, rx) = oneshot::channel();
         if self
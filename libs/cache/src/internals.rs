@@ -5,7 +5,7 @@ use std::time::Duration;
 use std::{collections::HashMap, time::Instant};
 use tokio::sync::{mpsc, oneshot, watch};
 
-use crate::api::CacheLoader;
+use crate::api::{CacheLoader, CacheStats};
 use crate::{Builder, Policy};
 
 type SendValueTx<V> = oneshot::Sender<Option<V>>;
@@ -15,11 +15,13 @@ type WeakCacheTx<K, V> = Weak<mpsc::Sender<Message<K, V>>>;
 type WatchTx<V> = watch::Sender<Option<V>>;
 type WatchRx<V> = watch::Receiver<Option<V>>;
 type Loader<K, V> = Arc<dyn CacheLoader<Key = K, Value = V>>;
+pub(crate) type Updater<V> = Box<dyn FnOnce(&mut V) + Send>;
 
 pub(crate) enum Message<K, V> {
     Get((K, SendValueTx<V>)),
     GetAll((Vec<K>, SendMultipleValueTx<K, V>)),
     Set(K, V),
+    Update(K, Updater<V>),
     Remove((K, SendValueTx<V>)),
     RemoveAll(Vec<K>),
     Clear,
@@ -30,6 +32,7 @@ pub(crate) struct CacheInternal<K, V> {
     map: HashMap<K, CacheEntry<V>, RandomState>,
     loader: Loader<K, V>,
     statics: Builder,
+    stats: Arc<CacheStats>,
 }
 
 impl<K, V> CacheInternal<K, V>
@@ -37,11 +40,16 @@ where
     K: Eq + Hash + Clone + Send + Sync + 'static,
     V: Send + Sync + Clone + 'static,
 {
-    pub(crate) fn new(statics: Builder, loader: Arc<dyn CacheLoader<Key = K, Value = V>>) -> Self {
+    pub(crate) fn new(
+        statics: Builder,
+        loader: Arc<dyn CacheLoader<Key = K, Value = V>>,
+        stats: Arc<CacheStats>,
+    ) -> Self {
         Self {
             map: HashMap::with_hasher(RandomState::default()),
             loader,
             statics,
+            stats,
         }
     }
 
@@ -50,6 +58,12 @@ where
             .insert(key, CacheEntry::new_with_expiry(value, self.statics.expiry));
     }
 
+    fn insert_stale(&mut self, key: K, value: Option<V>, stale_since: Option<Instant>) {
+        let mut entry = CacheEntry::new_with_expiry(value, self.statics.expiry);
+        entry.stale_since = stale_since;
+        self.map.insert(key, entry);
+    }
+
     pub(crate) async fn run(
         mut self: CacheInternal<K, V>,
         mut rx: CacheRx<K, V>,
@@ -60,10 +74,20 @@ where
                 Message::Get((key, tx)) => match self.map.get_mut(&key) {
                     Some(entry) => {
                         if entry.is_loading() {
+                            self.stats.record_miss();
                             entry.wait_for_value(tx);
                             continue;
                         }
 
+                        // An expired or stale value, even if served, is no hit.
+                        match entry.stale_since.is_none()
+                            && !entry.is_refreshing()
+                            && !entry.is_expired()
+                        {
+                            true => self.stats.record_hit(),
+                            false => self.stats.record_miss(),
+                        }
+
                         Self::get_or_load_entry(
                             entry,
                             key,
@@ -74,6 +98,7 @@ where
                         );
                     }
                     None => {
+                        self.stats.record_miss();
                         let mut entry = CacheEntry::default();
                         let watch_tx = entry.init_watch_and_wait(tx);
 
@@ -93,6 +118,13 @@ where
                     let _ = self.insert(key, Some(value));
                 }
 
+                // A load in flight still replaces the value once done.
+                Message::Update(key, update) => {
+                    if let Some(value) = self.map.get_mut(&key).and_then(|e| e.value.as_mut()) {
+                        update(value);
+                    }
+                }
+
                 Message::Remove((key, tx)) => {
                     let value = self.map.remove(&key);
                     let _ = tx.send(value.and_then(|v| v.value));
@@ -108,12 +140,31 @@ where
                     self.map.clear();
                 }
 
-                Message::Load(key, value, tx) => match self.map.contains_key(&key) {
-                    true => {
+                Message::Load(key, value, tx) => match self.map.get(&key) {
+                    Some(entry) if value.is_none() && self.statics.serve_stale => {
+                        // The value is stale since the expiry of its last successful load.
+                        let stale_since = entry.stale_since.or(entry.expire_at);
+                        let stale = match (stale_since, self.statics.stale_max) {
+                            (Some(since), Some(max)) if since.elapsed() > max => None,
+                            _ => entry.value.clone(),
+                        };
+
+                        let _ = tx.send(stale.clone());
+
+                        // Keep the last known value until the next expiry, or drop the entry
+                        // so that the next access retries the load.
+                        match stale {
+                            Some(_) => self.insert_stale(key, stale, stale_since),
+                            None => {
+                                self.map.remove(&key);
+                            }
+                        }
+                    }
+                    Some(_) => {
                         let _ = tx.send(value.clone());
                         let _ = self.insert(key, value);
                     }
-                    false => {
+                    None => {
                         let _ = tx.send(None);
                     }
                 },
@@ -180,6 +231,8 @@ where
 struct CacheEntry<V> {
     value: Option<V>,
     expire_at: Option<Instant>,
+    /// When the value became stale, if it is served past a failed refresh.
+    stale_since: Option<Instant>,
     watch_rx: Option<WatchRx<V>>,
     state: CacheEntryState,
 }
@@ -189,6 +242,7 @@ impl<V: Clone + Send + Sync + 'static> CacheEntry<V> {
         Self {
             value,
             expire_at: Some(expire_at),
+            stale_since: None,
             watch_rx: None,
             state: CacheEntryState::Valid,
         }
@@ -211,13 +265,15 @@ impl<V: Clone + Send + Sync + 'static> CacheEntry<V> {
     }
 
     fn is_refreshing(&self) -> bool {
-        debug_assert!(self.watch_rx.is_some());
-        self.state == CacheEntryState::Refreshing
+        let refreshing = self.state == CacheEntryState::Refreshing;
+        debug_assert!(!refreshing || self.watch_rx.is_some());
+        refreshing
     }
 
     fn is_loading(&self) -> bool {
-        debug_assert!(self.watch_rx.is_some());
-        self.state == CacheEntryState::Loading
+        let loading = self.state == CacheEntryState::Loading;
+        debug_assert!(!loading || self.watch_rx.is_some());
+        loading
     }
 
     fn load<K: Send + Sync + Clone + 'static>(
@@ -288,6 +344,7 @@ impl<V> Default for CacheEntry<V> {
         Self {
             value: None,
             expire_at: None,
+            stale_since: None,
             watch_rx: None,
             state: CacheEntryState::Init,
         }
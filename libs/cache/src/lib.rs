@@ -2,4 +2,4 @@ mod api;
 mod inner;
 mod internals;
 
-pub use api::{Builder, Cache, CacheKey, CacheLoader, Policy};
+pub use api::{Builder, Cache, CacheKey, CacheLoader, CacheStats, Policy};
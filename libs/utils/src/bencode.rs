@@ -1,6 +1,7 @@
 use bytes::{BufMut, Bytes, BytesMut};
 use serde::ser;
 use serde::Serialize;
+use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::error::Error as StdError;
 use std::fmt;
@@ -15,6 +16,14 @@ const TOKEN_LIST: u8 = b'l';
 const TOKEN_INT: u8 = b'i';
 const TOKEN_LEN: u8 = b':';
 
+/// The size of the allocations the pooled buffer is grown by, so that many outputs share one.
+const POOL_CHUNK_SIZE: usize = 64 * 1024;
+
+thread_local! {
+    /// The buffer that the pooled serializers of this thread split their output from.
+    static POOL: RefCell<BytesMut> = RefCell::new(BytesMut::new());
+}
+
 #[derive(Default, Debug)]
 /// Bencode Serializer.
 pub struct Serializer {
@@ -39,6 +48,24 @@ impl Serializer {
         }
     }
 
+    /// Creates a serializer writing into this thread's pooled buffer, to be finished with
+    /// `finalize_pooled`.
+    ///
+    /// The outputs are split from shared allocations, which the buffer reclaims once every
+    /// output split from them is dropped, instead of allocating for each output.
+    #[must_use]
+    pub fn pooled(capacity: usize) -> Self {
+        let mut output = POOL.with(|pool| std::mem::take(&mut *pool.borrow_mut()));
+        if output.capacity() < capacity {
+            output.reserve(capacity.max(POOL_CHUNK_SIZE));
+        }
+
+        Self {
+            output,
+            is_sorted: false,
+        }
+    }
+
     fn push_slice<T: AsRef<[u8]>>(&mut self, token: T) {
         self.output.extend_from_slice(token.as_ref());
     }
@@ -78,6 +105,14 @@ impl Serializer {
     pub fn finalize(self) -> Bytes {
         self.output.freeze()
     }
+
+    /// Splits off the output of a serializer created with `pooled`, and returns the rest of
+    /// the buffer to the pool.
+    pub fn finalize_pooled(mut self) -> Bytes {
+        let bytes = self.output.split().freeze();
+        POOL.with(|pool| *pool.borrow_mut() = self.output);
+        bytes
+    }
 }
 
 impl<'a> serde::Serializer for &'a mut Serializer {
@@ -131,11 +166,11 @@ impl<'a> serde::Serializer for &'a mut Serializer {
     }
 
     fn serialize_f32(self, _value: f32) -> Result<Self::Ok> {
-        Err(Error::Custom("Cannot serialize f32".to_string()))
+        Err(Error::UnsupportedType("f32"))
     }
 
     fn serialize_f64(self, _value: f64) -> Result<Self::Ok> {
-        Err(Error::Custom("Cannot serialize f64".to_string()))
+        Err(Error::UnsupportedType("f64"))
     }
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok> {
@@ -469,7 +504,10 @@ impl<'a> ser::SerializeMap for SortedMapSerializer<'a> {
     {
         match self.cur_key.take() {
             Some(key) => {
-                let value = self.serialize(value)?;
+                let value = self
+                    .serialize(value)
+                    .map_err(|err| err.at_key(strip_len_prefix(&key)))?;
+
                 if !value.is_empty() {
                     self.push_entry(key, value);
                 }
@@ -491,7 +529,14 @@ impl<'a> ser::SerializeMap for SortedMapSerializer<'a> {
             ));
         }
 
-        let value = self.serialize(value)?;
+        let value = match self.serialize(value) {
+            Ok(value) => value,
+            Err(err) => {
+                let key = self.serialize(key)?;
+                return Err(err.at_key(strip_len_prefix(&key)));
+            }
+        };
+
         if !value.is_empty() {
             key.serialize(&mut *self.ser)?;
             self.ser.push_slice(value);
@@ -597,7 +642,7 @@ impl<'a> ser::SerializeMap for UnSortedMapSerializer<'a> {
     {
         match self.cur_key.take() {
             Some(key) => {
-                let value = self.serialize(value)?;
+                let value = self.serialize(value).map_err(|err| err.at_key(&key))?;
                 if !value.is_empty() {
                     self.dict.insert(key, value);
                 }
@@ -645,10 +690,42 @@ pub enum Error {
     /// Error that occurs if methods on MapSerializer are called out of order
     MapSerializationCallOrder,
 
+    /// Error that occurs if a value has no bencode representation, such as a float.
+    UnsupportedType(&'static str),
+
+    /// Error that occurred while serializing the value of a dictionary key.
+    /// The path lists the keys from the outermost dictionary, separated by `.`.
+    Field { path: String, cause: Box<Error> },
+
     /// Catchall for any other kind of error.
     Custom(String),
 }
 
+impl Error {
+    /// Adds the dictionary key whose value failed to serialize to the error.
+    fn at_key(self, key: &[u8]) -> Self {
+        let key = String::from_utf8_lossy(key);
+        match self {
+            Error::Field { path, cause } => Error::Field {
+                path: format!("{}.{}", key, path),
+                cause,
+            },
+            err => Error::Field {
+                path: key.into_owned(),
+                cause: Box::new(err),
+            },
+        }
+    }
+}
+
+/// Strips the length prefix from an encoded byte string, e.g. `3:foo` becomes `foo`.
+fn strip_len_prefix(encoded: &[u8]) -> &[u8] {
+    match encoded.iter().position(|b| *b == TOKEN_LEN) {
+        Some(colon) => &encoded[colon + 1..],
+        None => encoded,
+    }
+}
+
 impl ser::Error for Error {
     fn custom<T: fmt::Display>(msg: T) -> Self {
         Error::Custom(msg.to_string())
@@ -659,6 +736,7 @@ impl StdError for Error {
     fn source(&self) -> Option<&(dyn StdError + 'static)> {
         match *self {
             Error::IoError(ref error) => Some(error),
+            Error::Field { ref cause, .. } => Some(cause.as_ref()),
             _ => None,
         }
     }
@@ -668,6 +746,13 @@ impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let message = match *self {
             Error::IoError(ref error) => return error.fmt(f),
+            Error::UnsupportedType(ty) => {
+                return write!(f, "floats are not supported by bencode, found `{}`", ty);
+            }
+            Error::Field {
+                ref path,
+                ref cause,
+            } => return write!(f, "failed to serialize field `{}`: {}", path, cause),
             Error::Custom(ref s) => s,
             Error::ArbitraryMapKeysUnsupported => {
                 "Maps with key types that do not serialize to byte strings are unsupported"
@@ -719,6 +804,18 @@ pub trait Bencode: Serialize {
         self.serialize(&mut serializer)?;
         Ok(serializer.finalize())
     }
+
+    /// Serializes the implementor like `bencode`, writing into this thread's pooled buffer
+    /// instead of a fresh allocation.
+    fn bencode_pooled(&self) -> Result<Bytes> {
+        let mut serializer = Serializer::pooled(self.capacity() * 2);
+        serializer.is_sorted = !self.requires_sort();
+        let result = self.serialize(&mut serializer);
+
+        // The buffer goes back to the pool even if serialization failed midway.
+        let bytes = serializer.finalize_pooled();
+        result.map(|_| bytes)
+    }
 }
 
 #[macro_export]
@@ -777,4 +874,42 @@ mod tests {
         let encoded = encode_with_capacity(&response, 100).unwrap();
         assert_eq!(encoded, expected_output);
     }
+
+    #[test]
+    fn test_bencode_pooled() {
+        impl Bencode for Response {}
+
+        let response = Response::default();
+        let first = response.bencode_pooled().unwrap();
+        let second = response.bencode_pooled().unwrap();
+
+        assert_eq!(first, response.bencode().unwrap());
+        assert_eq!(second, first);
+
+        // Both outputs were split from the same allocation.
+        assert_eq!(second.as_ptr(), first.as_ptr().wrapping_add(first.len()));
+    }
+
+    #[test]
+    fn test_encode_float_error() {
+        #[derive(Serialize)]
+        struct Stats {
+            ratio: f64,
+        }
+
+        #[derive(Serialize)]
+        struct Response {
+            stats: Stats,
+        }
+
+        let response = Response {
+            stats: Stats { ratio: 0.5 },
+        };
+
+        let err = encode(&response).unwrap_err();
+        assert!(matches!(err, Error::Field { ref path, .. } if path == "stats.ratio"));
+        let message = err.to_string();
+        assert!(message.starts_with("failed to serialize field `stats.ratio`"));
+        assert!(message.contains("floats are not supported by bencode"));
+    }
 }
@@ -0,0 +1,29 @@
+/// Compares two byte strings in a time independent of where they differ, for comparing
+/// secrets such as API keys. Only the lengths may be told apart by timing.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let diff = a
+        .iter()
+        .zip(b.iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b));
+
+    std::hint::black_box(diff) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ct_eq() {
+        assert!(ct_eq(b"", b""));
+        assert!(ct_eq(b"secret", b"secret"));
+        assert!(!ct_eq(b"secret", b"secreT"));
+        assert!(!ct_eq(b"secret", b"Secret"));
+        assert!(!ct_eq(b"secret", b"secret!"));
+        assert!(!ct_eq(b"secret", b""));
+    }
+}
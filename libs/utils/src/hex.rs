@@ -9,6 +9,7 @@ pub fn encode(bin: &[u8]) -> String {
 }
 
 /// Decodes a hexadecimal string into a binary vector.
+/// Both uppercase and lowercase hex digits are accepted, in any mix.
 pub fn decode(hex: &str) -> Result<Vec<u8>, hex::FromHexError> {
     hex::decode(hex)
 }
@@ -45,5 +46,23 @@ mod tests {
         // Test decoding with an invalid hexadecimal string
         let invalid_input = "4865&6c6c6f";
         assert!(decode(invalid_input).is_err());
+
+        assert_eq!(
+            decode(invalid_input),
+            Err(FromHexError::InvalidHexCharacter { c: '&', index: 4 })
+        );
+        assert_eq!(decode("48656c6c6"), Err(FromHexError::OddLength));
+    }
+
+    #[test]
+    fn test_decode_case_insensitive() {
+        let lowercase = "2a7b9e1f5c8d3a6b0f2e4c5a9b7d1e3a6c8b5d99";
+        let uppercase = "2A7B9E1F5C8D3A6B0F2E4C5A9B7D1E3A6C8B5D99";
+        let mixed_case = "2a7B9e1F5c8D3a6B0f2E4c5A9b7D1e3A6c8B5d99";
+
+        let expected = decode(lowercase).unwrap();
+        assert_eq!(expected.len(), 20);
+        assert_eq!(decode(uppercase).unwrap(), expected);
+        assert_eq!(decode(mixed_case).unwrap(), expected);
     }
 }
@@ -1,4 +1,5 @@
 pub mod bencode;
+pub mod cmp;
 pub mod hex;
 pub mod number;
 pub mod query;
@@ -1,3 +1,45 @@
+use std::{error::Error, fmt};
+
+/// The error returned by the bounded number parsers.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ParseBoundedError {
+    /// The value is not a decimal number.
+    Invalid,
+    /// The value is larger than the allowed maximum.
+    OutOfRange,
+}
+
+impl fmt::Display for ParseBoundedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseBoundedError::Invalid => f.write_str("invalid number"),
+            ParseBoundedError::OutOfRange => f.write_str("number out of range"),
+        }
+    }
+}
+
+impl Error for ParseBoundedError {}
+
+/// Parses a decimal `u64`, rejecting values above `max`.
+pub fn parse_bounded_u64(value: &str, max: u64) -> Result<u64, ParseBoundedError> {
+    // The standard parser also accepts a leading `+`.
+    if !value.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(ParseBoundedError::Invalid);
+    }
+
+    match value.parse::<u64>() {
+        Ok(value) if value <= max => Ok(value),
+        Ok(_) => Err(ParseBoundedError::OutOfRange),
+        Err(_) if value.is_empty() => Err(ParseBoundedError::Invalid),
+        Err(_) => Err(ParseBoundedError::OutOfRange),
+    }
+}
+
+/// Parses a decimal `u32`, rejecting values above `max`.
+pub fn parse_bounded_u32(value: &str, max: u32) -> Result<u32, ParseBoundedError> {
+    parse_bounded_u64(value, max as u64).map(|value| value as u32)
+}
+
 /// Tries to convert the first 4 bytes of a byte slice to a little-endian u32 value.
 ///
 /// # Arguments
@@ -35,4 +77,39 @@ mod tests {
         let bytes_empty: [u8; 0] = [];
         assert_eq!(try_convert_bytes_to_u32(&bytes_empty), None);
     }
+
+    #[test]
+    fn test_parse_bounded() {
+        assert_eq!(parse_bounded_u64("0", 100), Ok(0));
+        assert_eq!(parse_bounded_u64("42", 100), Ok(42));
+        assert_eq!(parse_bounded_u64("100", 100), Ok(100));
+        assert_eq!(
+            parse_bounded_u64("101", 100),
+            Err(ParseBoundedError::OutOfRange)
+        );
+
+        let max = u64::MAX.to_string();
+        assert_eq!(parse_bounded_u64(&max, u64::MAX), Ok(u64::MAX));
+        assert_eq!(
+            parse_bounded_u64("18446744073709551616", u64::MAX),
+            Err(ParseBoundedError::OutOfRange)
+        );
+
+        assert_eq!(parse_bounded_u32("4294967295", u32::MAX), Ok(u32::MAX));
+        assert_eq!(
+            parse_bounded_u32("4294967296", u32::MAX),
+            Err(ParseBoundedError::OutOfRange)
+        );
+        assert_eq!(
+            parse_bounded_u32("51", 50),
+            Err(ParseBoundedError::OutOfRange)
+        );
+
+        for invalid in ["", "-1", "+1", "1.5", "abc"] {
+            assert_eq!(
+                parse_bounded_u64(invalid, 100),
+                Err(ParseBoundedError::Invalid)
+            );
+        }
+    }
 }
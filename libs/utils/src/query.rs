@@ -10,7 +10,18 @@ pub use serde::de::value::Error;
 
 /// Create and return an instance of `UrlEncodedParse` with the provided byte slice as the `input` field.
 pub fn parse(input: &[u8]) -> UrlEncodedParse<'_> {
-    UrlEncodedParse { input }
+    parse_with_binary_keys(input, &[])
+}
+
+/// Like `parse`, but the values of `binary_keys` are only percent-decoded.
+///
+/// A `+` stands for a space in form-encoded text, but in a binary value, such as an
+/// infohash, a client that failed to escape it meant the byte `0x2B` itself.
+pub fn parse_with_binary_keys<'a>(
+    input: &'a [u8],
+    binary_keys: &'a [&'a str],
+) -> UrlEncodedParse<'a> {
+    UrlEncodedParse { input, binary_keys }
 }
 
 /// Deserializes a `application/x-www-form-urlencoded` value from a `&[u8]`.
@@ -35,6 +46,21 @@ where
     T::deserialize(Deserializer::new(parse(input)))
 }
 
+/// Deserializes a `application/x-www-form-urlencoded` value from a `&[u8]`, keeping the `+`
+/// in the values of `binary_keys`. See `parse_with_binary_keys`.
+pub fn from_bytes_with_binary_keys<'de, T>(
+    input: &'de [u8],
+    binary_keys: &'de [&'de str],
+) -> Result<T, Error>
+where
+    T: de::Deserialize<'de>,
+{
+    T::deserialize(Deserializer::new(parse_with_binary_keys(
+        input,
+        binary_keys,
+    )))
+}
+
 /// A deserializer for the `application/x-www-form-urlencoded` format.
 pub struct Deserializer<'de> {
     inner: MapDeserializer<'de, PartIterator<'de>, Error>,
@@ -324,6 +350,7 @@ impl<'de> de::VariantAccess<'de> for UnitOnlyVariantAccess {
 #[derive(Copy, Clone)]
 pub struct UrlEncodedParse<'a> {
     input: &'a [u8],
+    binary_keys: &'a [&'a str],
 }
 
 impl<'a> Iterator for UrlEncodedParse<'a> {
@@ -347,7 +374,13 @@ impl<'a> Iterator for UrlEncodedParse<'a> {
             let name = split2.next().unwrap();
             let value = split2.next().unwrap_or(&[][..]);
 
-            return Some((decode(name), decode(value)));
+            let name = decode(name);
+            let value = match self.binary_keys.iter().any(|key| key.as_bytes() == &*name) {
+                true => percent_decode(value).into(),
+                false => decode(value),
+            };
+
+            return Some((name, value));
         }
     }
 }
@@ -477,4 +510,23 @@ mod tests {
         let req = result.unwrap();
         assert_eq!(req, expected_req);
     }
+
+    #[test]
+    fn test_binary_keys_keep_plus() {
+        let input = b"info_hash=ab+cd%2B&event=stopped+now";
+
+        let lenient: Vec<(String, Vec<u8>)> = parse(input)
+            .map(|(k, v)| (decode_utf8_lossy(k).into_owned(), v.into_owned()))
+            .collect();
+        assert_eq!(lenient[0].1, b"ab cd+");
+
+        let strict: Vec<(String, Vec<u8>)> = parse_with_binary_keys(input, &["info_hash"])
+            .map(|(k, v)| (decode_utf8_lossy(k).into_owned(), v.into_owned()))
+            .collect();
+        assert_eq!(strict[0].1, b"ab+cd+");
+
+        // Text values are decoded as form-encoded either way.
+        assert_eq!(lenient[1].1, b"stopped now");
+        assert_eq!(strict[1].1, b"stopped now");
+    }
 }
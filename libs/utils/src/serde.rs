@@ -1,7 +1,10 @@
+use serde::de::{self, Visitor};
 use serde::Deserialize;
 use serde::Deserializer;
 use serde::Serializer;
-use std::time::Duration;
+use std::{fmt, time::Duration};
+
+use crate::number::{parse_bounded_u64, ParseBoundedError};
 
 /// Custom deserialization function for converting a boolean to an integer
 pub fn deserialize_u8_to_bool<'de, D>(deserializer: D) -> Result<bool, D::Error>
@@ -61,13 +64,50 @@ where
     deserialize_option_string(deserializer).map(|s| s.map(|s| s.to_lowercase()))
 }
 
+/// Deserializes a `u64` given as a number or a decimal string, rejecting values above `max`.
+pub fn deserialize_bounded_u64<'de, D>(deserializer: D, max: u64) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(BoundedVisitor(max))
+}
+
+/// Like `deserialize_bounded_u64`, for a `u32`.
+pub fn deserialize_bounded_u32<'de, D>(deserializer: D, max: u32) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_bounded_u64(deserializer, max as u64).map(|value| value as u32)
+}
+
+struct BoundedVisitor(u64);
+
+impl<'de> Visitor<'de> for BoundedVisitor {
+    type Value = u64;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an unsigned integer up to {}", self.0)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<u64, E> {
+        match value <= self.0 {
+            true => Ok(value),
+            false => Err(E::custom(ParseBoundedError::OutOfRange)),
+        }
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<u64, E> {
+        parse_bounded_u64(value, self.0).map_err(E::custom)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
 
     use super::*;
     use serde::Serialize;
-    use serde_test::{assert_de_tokens, assert_ser_tokens, Token};
+    use serde_test::{assert_de_tokens, assert_de_tokens_error, assert_ser_tokens, Token};
 
     #[test]
     fn test_deserialize_u8_to_bool() {
@@ -169,4 +209,23 @@ mod tests {
 
         assert_de_tokens(&Struct { val: None }, &[Token::None]);
     }
+
+    #[test]
+    fn test_deserialize_bounded_u64() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        #[serde(transparent)]
+        struct Struct {
+            #[serde(deserialize_with = "deserialize_up_to_100")]
+            val: u64,
+        }
+
+        fn deserialize_up_to_100<'de, D: Deserializer<'de>>(d: D) -> Result<u64, D::Error> {
+            deserialize_bounded_u64(d, 100)
+        }
+
+        assert_de_tokens(&Struct { val: 100 }, &[Token::Str("100")]);
+        assert_de_tokens(&Struct { val: 100 }, &[Token::U64(100)]);
+        assert_de_tokens_error::<Struct>(&[Token::Str("101")], "number out of range");
+        assert_de_tokens_error::<Struct>(&[Token::U64(101)], "number out of range");
+    }
 }
@@ -3,7 +3,7 @@ use std::hash::Hash;
 use std::str::FromStr;
 use std::{
     fmt, fs,
-    io::{self, BufRead},
+    io::{self, BufRead, Write},
     ops::{Deref, DerefMut},
 };
 
@@ -64,6 +64,8 @@ where
     /// Each line in the file is treated as an item, and items are parsed from string representations
     /// using the `FromStr` trait implementation for the item type.
     ///
+    /// A line that fails to parse is reported as an `InvalidData` error along with its number.
+    ///
     /// # Arguments
     ///
     /// * `path` - The file path from which to load items.
@@ -75,20 +77,50 @@ where
         let file = fs::File::open(path)?;
         let reader = io::BufReader::new(file);
 
-        for line in reader.lines() {
-            let line = line.unwrap_or_default();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
             let line = line.trim();
 
             if line.is_empty() {
                 continue;
             }
 
-            let item = line.parse::<T>().unwrap();
+            let item = line.parse::<T>().map_err(|err| {
+                let msg = format!("invalid item on line {}: {:?}", number + 1, err);
+                io::Error::new(io::ErrorKind::InvalidData, msg)
+            })?;
             self.insert(item);
         }
 
         Ok(())
     }
+
+    /// Saves the items of the `Set` to a file, one item per line.
+    ///
+    /// Items are written using their `Display` implementation, so the file can be read back
+    /// with `load_from_file`. The items are first written to a temporary file next to `path`,
+    /// which is then renamed over it, so readers never observe a partially written file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file path to which to save items.
+    pub fn save_to_file(&self, path: &str) -> io::Result<()>
+    where
+        T: fmt::Display,
+    {
+        let tmp_path = format!("{}.tmp", path);
+        let file = fs::File::create(&tmp_path)?;
+        let mut writer = io::BufWriter::new(file);
+
+        for item in self.iter() {
+            writeln!(writer, "{}", item)?;
+        }
+
+        let file = writer.into_inner().map_err(|err| err.into_error())?;
+        file.sync_all()?;
+
+        fs::rename(&tmp_path, path)
+    }
 }
 
 impl<T> From<Vec<T>> for Set<T>
@@ -99,3 +131,39 @@ where
         Self(vec.into_iter().collect())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_to_file_round_trip() {
+        let path = std::env::temp_dir().join(format!("ts_set_{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "1\n2\n").unwrap();
+
+        let mut set: Set<u32> = Set::from_file(path).unwrap();
+        set.insert(3);
+        set.save_to_file(path).unwrap();
+
+        let set: Set<u32> = Set::from_file(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(set.len(), 3);
+        assert!(set.contains(&3));
+        assert!(fs::metadata(format!("{}.tmp", path)).is_err());
+    }
+
+    #[test]
+    fn test_load_from_file_invalid_item() {
+        let path = std::env::temp_dir().join(format!("ts_set_invalid_{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "1\n\nlots\n").unwrap();
+
+        let err = Set::<u32>::from_file(path).unwrap_err();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("invalid item on line 3"));
+    }
+}
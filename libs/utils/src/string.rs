@@ -1,11 +1,21 @@
-/// Extracts the first value from a separator-separated list of values.
+/// Extracts the first value from a separator-separated list of values, without the
+/// surrounding whitespace.
 pub fn get_first_value(input: &str, separator: char) -> &str {
     match input.splitn(2, separator).next() {
-        Some(value) => value,
+        Some(value) => value.trim(),
         None => "",
     }
 }
 
+/// Extracts the `n`th value, counting from the right and starting at 0, from a
+/// separator-separated list of values, without the surrounding whitespace.
+///
+/// Proxies append to lists such as `X-Forwarded-For`, so the value added `n` hops before
+/// the last one is the `n`th from the right.
+pub fn get_nth_value(input: &str, separator: char, n: usize) -> Option<&str> {
+    input.rsplit(separator).nth(n).map(str::trim)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -15,5 +25,23 @@ mod tests {
         assert_eq!(get_first_value("a,b,c", ','), "a");
         assert_eq!(get_first_value("a", ','), "a");
         assert_eq!(get_first_value("", ','), "");
+        assert_eq!(get_first_value(" 1.2.3.4 , 5.6.7.8", ','), "1.2.3.4");
+        assert_eq!(get_first_value("\t1.2.3.4 ", ','), "1.2.3.4");
+        assert_eq!(get_first_value(",b", ','), "");
+        assert_eq!(get_first_value(" ,b", ','), "");
+    }
+
+    #[test]
+    fn test_get_nth_value() {
+        let input = " 1.2.3.4 , 5.6.7.8,9.9.9.9 ";
+        assert_eq!(get_nth_value(input, ',', 0), Some("9.9.9.9"));
+        assert_eq!(get_nth_value(input, ',', 1), Some("5.6.7.8"));
+        assert_eq!(get_nth_value(input, ',', 2), Some("1.2.3.4"));
+        assert_eq!(get_nth_value(input, ',', 3), None);
+
+        assert_eq!(get_nth_value("a,,b", ',', 1), Some(""));
+        assert_eq!(get_nth_value("a,b,", ',', 0), Some(""));
+        assert_eq!(get_nth_value("", ',', 0), Some(""));
+        assert_eq!(get_nth_value("", ',', 1), None);
     }
 }
@@ -1,14 +1,20 @@
-use std::sync::Arc;
+use log::error;
+use std::{error::Error, sync::Arc};
 
 use crate::{
     config::TSConfig,
-    servers::{HttpServer, State},
+    servers::{HttpServer, State, UdpServer},
     signals::StopSignalRx,
     worker::Worker,
 };
 use tokio::task::JoinHandle;
 
-pub fn start(config: TSConfig, stop_signal_rx: StopSignalRx) -> Vec<JoinHandle<()>> {
+/// Starts the worker and the servers, returning the state they share along with their jobs.
+/// Fails if a server can't bind its socket.
+pub fn start(
+    config: TSConfig,
+    stop_signal_rx: StopSignalRx,
+) -> Result<(State, Vec<JoinHandle<()>>), Box<dyn Error + Send + Sync>> {
     let mut jobs: Vec<JoinHandle<()>> = Vec::new();
     let config = Arc::new(config);
 
@@ -17,12 +23,17 @@ pub fn start(config: TSConfig, stop_signal_rx: StopSignalRx) -> Vec<JoinHandle<(
 
     jobs.push(worker_job);
 
-    let state = State::new(Arc::new(worker), config);
-    let http_server_job = start_http_server(state, stop_signal_rx.clone());
+    let state = State::new(Arc::new(worker), config.clone());
+    if config.udp_enabled() {
+        let udp_server = UdpServer::bind(state.clone())?;
+        let udp_server_job = start_udp_server(udp_server, stop_signal_rx.clone());
+        jobs.push(udp_server_job);
+    }
 
+    let http_server_job = start_http_server(state.clone(), stop_signal_rx.clone());
     jobs.push(http_server_job);
 
-    return jobs;
+    return Ok((state, jobs));
 }
 
 fn start_worker(worker: &mut Worker) -> JoinHandle<()> {
@@ -38,3 +49,11 @@ fn start_http_server(state: State, stop_signal_recv: StopSignalRx) -> JoinHandle
             .expect("Failed to start http server.");
     })
 }
+
+fn start_udp_server(udp_server: UdpServer, stop_signal_recv: StopSignalRx) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Err(err) = udp_server.start(stop_signal_recv).await {
+            error!("udp server error: {}", err);
+        }
+    })
+}
@@ -1,13 +1,29 @@
-use std::time::Duration;
+use std::{net::IpAddr, time::Duration};
 
 use config::{Config, ConfigError, Environment, File};
+use log::LevelFilter;
 use serde::{Deserialize, Serialize};
 use ts_utils::{
     serde::{deserialize_header_name, deserialize_option_string, deserialize_secs_to_duration},
     Set,
 };
 
-use crate::models::common::InfoHash;
+use crate::models::{
+    common::{InfoHash, IntervalDuration},
+    tracker::{AnnounceEvent, FullScrapeFormat},
+};
+
+/// The shortest announce or scrape interval, in seconds, clients can be asked to keep.
+const MIN_INTERVAL: IntervalDuration = 30;
+
+/// The smallest read buffer hyper accepts, see hyper/src/server/conn/http1.rs.
+pub const MIN_READ_BUFFER_SIZE: usize = 8192;
+
+/// The most headers hyper parses in a request, see hyper/src/proto/h1/role.rs.
+pub const MAX_HEADERS: usize = 100;
+
+/// The query keys with binary values, decoded without `+` substitution if strict.
+const BINARY_QUERY_KEYS: &[&str] = &["info_hash", "peer_id"];
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 #[serde(rename_all = "lowercase")]
@@ -18,6 +34,59 @@ pub enum StorageType {
     Redis,
 }
 
+impl StorageType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StorageType::Memory => "memory",
+            StorageType::Redis => "redis",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerSelection {
+    /// Peers are selected starting from a rotating offset into the swarm.
+    #[default]
+    Random,
+    /// Peers are always selected in insertion order, starting from the first peer.
+    Stable,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DisabledProtocolStatus {
+    /// A bencoded failure reason with a `200 OK`, which clients show to their users.
+    #[default]
+    Bencode,
+    /// A plain `403 Forbidden`.
+    Forbidden,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CompactOnlyMode {
+    /// A non-compact announce is answered with a compact peer list.
+    #[default]
+    Coerce,
+    /// A non-compact announce is rejected with a tracker error.
+    Reject,
+}
+
+/// How the `ip` param of an announce is used. It is only honored for a client connected from a
+/// loopback, private or link-local address.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IpParamMode {
+    /// The `ip` is ignored, a peer is stored under the address it connected from.
+    #[default]
+    Ignore,
+    /// A peer is stored under the `ip` it declared, in the swarm of that IP family.
+    Accept,
+    /// Like `Accept`, but an `ip` of another family than the connection is rejected.
+    Strict,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct MemoryStorageConfig {
     /// The number of shards to use for the in-memory storage.
@@ -42,6 +111,9 @@ pub struct RedisStorageConfig {
     /// The maximum number of seconds to keep an idle connection open.
     #[serde(deserialize_with = "deserialize_secs_to_duration")]
     pub idle_connection_time: Duration,
+
+    /// The maximum number of torrents queried in a single pipeline by a multi-scrape.
+    pub pipeline_batch_size: usize,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -56,6 +128,10 @@ pub struct StorageConfig {
 pub struct ServerConfig {
     pub http: HttpServerConfig,
     pub udp: UdpServerConfig,
+
+    #[serde(deserialize_with = "deserialize_option_string")]
+    /// The tracker id sent in announce responses (optional).
+    pub tracker_id: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -77,9 +153,19 @@ pub struct HttpServerConfig {
     /// The duration of time for an idle keep-alive connection before it's closed.
     pub keep_alive_idle_time: Duration,
 
-    /// The maximum buffer size for reading incoming request data.
+    /// The maximum number of requests served on a keep-alive connection, or `0` for no limit.
+    pub max_requests_per_connection: usize,
+
+    /// The maximum buffer size for reading incoming request data, at least
+    /// `MIN_READ_BUFFER_SIZE`.
     pub max_read_buffer_size: usize,
 
+    /// The size of the chunks a streamed response body, such as a full scrape, is sent in.
+    pub stream_chunk_size: usize,
+
+    /// Determines whether announce and scrape responses are serialized into pooled buffers.
+    pub pool_response_buffers: bool,
+
     #[serde(deserialize_with = "deserialize_secs_to_duration")]
     /// The maximum allowed duration for processing an incoming request.
     pub request_timeout: Duration,
@@ -88,6 +174,20 @@ pub struct HttpServerConfig {
     /// The header name used to forward IP address information (optional).
     pub ip_forward_header_name: Option<String>,
 
+    /// Rejects requests without a valid IP in the `ip_forward_header_name` header, instead of
+    /// falling back to the address of the connection.
+    pub require_forward_header: bool,
+
+    /// Keeps a literal `+` in the `info_hash` and `peer_id` query values, rather than decoding
+    /// it to a space. They are binary, and a `+` in them is an unescaped `0x2B`.
+    pub strict_binary_params: bool,
+
+    /// The addresses of the proxies whose `X-Forwarded-Proto` header is honored.
+    pub trusted_proxies: Vec<IpAddr>,
+
+    /// How announces and scrapes are answered when disabled by the tracker config.
+    pub disabled_protocol_status: DisabledProtocolStatus,
+
     #[serde(deserialize_with = "deserialize_option_string")]
     /// The API key used for performing tracker API calls (optional).
     pub api_key: Option<String>,
@@ -98,6 +198,16 @@ pub struct HttpServerConfig {
     /// The size of the connection backlog for incoming requests.
     pub connection_backlog_size: usize,
 
+    /// Determines whether to set `SO_REUSEPORT` on the listening socket (unix only).
+    pub reuse_port: bool,
+
+    /// The maximum number of headers in a request, at most `MAX_HEADERS`.
+    pub max_headers: usize,
+
+    /// The maximum size, in bytes, of the request line and headers of a request, at least
+    /// `MIN_READ_BUFFER_SIZE`. Hyper reads them into its read buffer, which is capped to it.
+    pub max_header_size: usize,
+
     /// The maximum number of concurrent requests the server can handle.
     pub max_concurrent_requests: usize,
 }
@@ -105,6 +215,9 @@ pub struct HttpServerConfig {
 #[derive(Debug, Deserialize, Serialize, Clone)]
 /// Configuration options for a UDP server.
 pub struct UdpServerConfig {
+    /// Determines whether the UDP server is started.
+    pub enabled: bool,
+
     /// The port number on which the UDP server will listen for incoming messages.
     pub port: u16,
 
@@ -114,6 +227,12 @@ pub struct UdpServerConfig {
     #[serde(deserialize_with = "deserialize_option_string")]
     /// An optional secret key used for authentication and security.
     pub secret_key: Option<String>,
+
+    /// The size of the socket receive buffer (`SO_RCVBUF`) in bytes, or `0` for the OS default.
+    pub recv_buffer_size: usize,
+
+    /// The number of tasks receiving and answering datagrams on the socket.
+    pub workers: usize,
 }
 
 /// Configuration options for a BitTorrent tracker.
@@ -123,13 +242,13 @@ pub struct TrackerConfig {
     pub auto_register_torrent: bool,
 
     /// The interval at which clients should announce their status to the tracker.
-    pub announce_interval: u32,
+    pub announce_interval: IntervalDuration,
 
     /// The minimum interval allowed between client announces.
-    pub min_announce_interval: u32,
+    pub min_announce_interval: IntervalDuration,
 
     /// The interval at which clients should scrape the tracker for information.
-    pub scrape_interval: u32,
+    pub scrape_interval: IntervalDuration,
 
     /// The maximum number of peers to include in a response to an announce request.
     pub max_numwant: u32,
@@ -137,6 +256,59 @@ pub struct TrackerConfig {
     /// The default number of peers to include in a response to an announce request.
     pub default_numwant: u32,
 
+    /// The default number of peers to include in a response to a `started` announce.
+    pub started_numwant: u32,
+
+    /// The default number of peers to include in a response to a periodic announce.
+    pub periodic_numwant: u32,
+
+    /// The order in which peers are selected for a response to an announce request.
+    pub peer_selection: PeerSelection,
+
+    /// Determines whether an announce response skips peers sharing the address and port of
+    /// a peer already in it, or of the requester.
+    pub dedup_peer_endpoints: bool,
+
+    /// Determines whether announce responses include peers of both IP families.
+    pub return_both_ip_families: bool,
+
+    /// Determines whether the `complete` and `incomplete` counts of an announce response are
+    /// raised to at least the number of peers in it.
+    pub clamp_peer_counts: bool,
+
+    /// Determines whether an announce response without any peers carries a warning message.
+    pub warn_no_peers: bool,
+
+    /// Determines whether scrape responses report the leechers apart from partial seeds,
+    /// as the BEP 21 `downloaders` count.
+    pub report_downloaders: bool,
+
+    /// Determines whether scrape responses list their files in request order instead of the
+    /// sorted order of a bencoded dictionary.
+    pub scrape_request_order: bool,
+
+    /// Determines whether a `completed` event is accepted from a peer that is not in the swarm,
+    /// e.g. one that never sent `started`. Such a peer is then added as a seeder.
+    pub lenient_completed: bool,
+
+    /// Determines whether peers are identified by their peer id alone, without the `key`, so
+    /// a peer that starts or stops sending a key updates its swarm entry rather than adding one.
+    /// The key is then stored with the entry, and announces with a different one are refused.
+    pub peer_id_only: bool,
+
+    /// Determines whether announces without a `key`, or with an all-zero one, are rejected.
+    /// Meant for private trackers, where the key protects a peer whose IP address changes.
+    pub require_key: bool,
+
+    /// Determines whether only compact peer lists are served, to save bandwidth.
+    pub compact_only: bool,
+
+    /// How an announce asking for a non-compact peer list is answered if `compact_only` is set.
+    pub compact_only_mode: CompactOnlyMode,
+
+    /// Whether the `ip` declared in an announce replaces the address of the connection.
+    pub ip_param_mode: IpParamMode,
+
     /// The period, measured in seconds since the last announce request, for considering a peer as active.
     #[serde(deserialize_with = "deserialize_secs_to_duration")]
     pub peer_idle_time: Duration,
@@ -148,9 +320,29 @@ pub struct TrackerConfig {
     #[serde(deserialize_with = "deserialize_secs_to_duration")]
     pub full_scrape_cache_ttl: Duration,
 
+    /// The default output format of a full scrape.
+    pub full_scrape_format: FullScrapeFormat,
+
+    /// The maximum number of full scrapes refreshed at a time, across all formats and listeners.
+    pub max_full_scrape_refreshes: usize,
+
     /// The maximum number of torrents to scrape in a single request.
     pub max_multi_scrape_count: u32,
 
+    /// The duration of time for which the stats of a single scraped torrent are cached.
+    #[serde(deserialize_with = "deserialize_secs_to_duration")]
+    pub scrape_cache_ttl: Duration,
+
+    /// The maximum number of worker tasks that can execute concurrently.
+    pub max_concurrent_tasks: usize,
+
+    /// The maximum number of tasks queued for the worker, past which they are rejected.
+    pub max_queued_tasks: usize,
+
+    /// The maximum number of scrapes reading the storage at once, so that their load on it is
+    /// bounded apart from that of announces.
+    pub max_concurrent_scrapes: usize,
+
     /// Determines whether UDP announce requests are allowed.
     pub allow_udp_announce: bool,
 
@@ -175,6 +367,9 @@ pub struct TSConfig {
     pub storage: StorageConfig,
     /// The log level to control the verbosity of log messages.
     pub log_level: String,
+    /// Per-module log levels, like `ts_tracker=debug,ts_pool=info`. Other modules log at
+    /// `log_level`.
+    pub log_filters: String,
 
     #[serde(deserialize_with = "deserialize_option_string")]
     infohash_blocklist_file: Option<String>,
@@ -186,9 +381,15 @@ pub struct TSConfig {
 impl TSConfig {
     /// Attempt to load the configuration from the environment.
     pub fn new() -> Result<Self, ConfigError> {
+        Self::load(None)
+    }
+
+    /// Loads the configuration like `new`, with the settings of `file`, if given,
+    /// taking precedence over every other file.
+    pub fn load(file: Option<&str>) -> Result<Self, ConfigError> {
         let run_mode = std::env::var("RUN_MODE").unwrap_or_else(|_| "development".into());
 
-        let s = Config::builder()
+        let mut builder = Config::builder()
             // Add in a default configuration file
             .add_source(File::with_name("conf/default").required(true))
 
@@ -201,8 +402,13 @@ impl TSConfig {
 
             // Add in a local configuration file
             // This file shouldn't be checked in to git
-            .add_source(File::with_name("conf/local").required(false))
+            .add_source(File::with_name("conf/local").required(false));
+
+        if let Some(file) = file {
+            builder = builder.add_source(File::with_name(file).required(true));
+        }
 
+        let s = builder
             // Add in settings from the environment (with a prefix of TS)
             // Eg.. `TS_LOG_LEVEL=debug ./target/app` would set the `log_level` key
             .add_source(Environment::with_prefix("ts"))
@@ -212,15 +418,138 @@ impl TSConfig {
 
         // Load the infohash blocklist if a file path is specified
         if let Some(ref file_path) = config.infohash_blocklist_file {
-            config
-                .infohash_blocklist
-                .load(&file_path)
-                .expect("failed to load infohash blocklist");
+            config.infohash_blocklist.load(file_path).map_err(|err| {
+                let msg = format!("failed to load infohash blocklist {}: {}", file_path, err);
+                ConfigError::Message(msg)
+            })?;
         }
 
         Ok(config)
     }
 
+    /// Checks for settings that are well-formed on their own but can't work as configured.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut errors: Vec<String> = Vec::new();
+        let tracker = &self.tracker;
+
+        let intervals = [
+            (tracker.announce_interval, "announce_interval"),
+            (tracker.min_announce_interval, "min_announce_interval"),
+            (tracker.scrape_interval, "scrape_interval"),
+        ];
+
+        for (interval, name) in intervals {
+            if interval < MIN_INTERVAL {
+                errors.push(format!(
+                    "tracker.{} is below {} seconds",
+                    name, MIN_INTERVAL
+                ));
+            }
+        }
+
+        if tracker.min_announce_interval > tracker.announce_interval {
+            errors.push(
+                "tracker.min_announce_interval is larger than tracker.announce_interval".into(),
+            );
+        }
+
+        if tracker.default_numwant > tracker.max_numwant {
+            errors.push("tracker.default_numwant is larger than tracker.max_numwant".into());
+        }
+
+        if tracker.started_numwant > tracker.max_numwant {
+            errors.push("tracker.started_numwant is larger than tracker.max_numwant".into());
+        }
+
+        if tracker.periodic_numwant > tracker.max_numwant {
+            errors.push("tracker.periodic_numwant is larger than tracker.max_numwant".into());
+        }
+
+        if self.server.http.max_read_buffer_size < MIN_READ_BUFFER_SIZE {
+            errors.push(format!(
+                "server.http.max_read_buffer_size is below {} bytes",
+                MIN_READ_BUFFER_SIZE
+            ));
+        }
+
+        if self.server.http.max_headers > MAX_HEADERS {
+            errors.push(format!("server.http.max_headers is above {}", MAX_HEADERS));
+        }
+
+        if self.server.http.max_header_size < MIN_READ_BUFFER_SIZE {
+            errors.push(format!(
+                "server.http.max_header_size is below {} bytes",
+                MIN_READ_BUFFER_SIZE
+            ));
+        }
+
+        if self.server.http.stream_chunk_size == 0 {
+            errors.push("server.http.stream_chunk_size must be at least 1".into());
+        }
+
+        if tracker.max_concurrent_tasks == 0 {
+            errors.push("tracker.max_concurrent_tasks must be at least 1".into());
+        }
+
+        if tracker.max_queued_tasks == 0 {
+            errors.push("tracker.max_queued_tasks must be at least 1".into());
+        }
+
+        if tracker.max_concurrent_scrapes == 0 {
+            errors.push("tracker.max_concurrent_scrapes must be at least 1".into());
+        }
+
+        if tracker.max_full_scrape_refreshes == 0 {
+            errors.push("tracker.max_full_scrape_refreshes must be at least 1".into());
+        }
+
+        let http = &self.server.http;
+        if http.require_forward_header && http.ip_forward_header_name.is_none() {
+            errors
+                .push("server.http.require_forward_header requires ip_forward_header_name".into());
+        }
+
+        if http.host.parse::<IpAddr>().is_err() {
+            errors.push("server.http.host is not an IP address".into());
+        }
+
+        if self.udp_enabled() && self.udp_host().parse::<IpAddr>().is_err() {
+            errors.push("server.udp.host is not an IP address".into());
+        }
+
+        if self.log_filters().is_err() {
+            errors.push("log_filters must be a comma separated list of module=level".into());
+        }
+
+        if self.server.udp.workers == 0 {
+            errors.push("server.udp.workers must be at least 1".into());
+        }
+
+        let storage = &self.storage;
+        match storage.name {
+            StorageType::Memory if storage.memory.is_none() => {
+                errors.push("storage.memory is required by the memory storage".into())
+            }
+            StorageType::Redis if storage.redis.is_none() => {
+                errors.push("storage.redis is required by the redis storage".into())
+            }
+            _ => {}
+        }
+
+        if storage
+            .redis
+            .as_ref()
+            .is_some_and(|v| v.pipeline_batch_size == 0)
+        {
+            errors.push("storage.redis.pipeline_batch_size must be at least 1".into());
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(ConfigError::Message(errors.join("; "))),
+        }
+    }
+
     pub fn http_request_timeout(&self) -> Duration {
         self.server.http.request_timeout
     }
@@ -229,6 +558,14 @@ impl TSConfig {
         self.server.http.max_read_buffer_size
     }
 
+    pub fn stream_chunk_size(&self) -> usize {
+        self.server.http.stream_chunk_size
+    }
+
+    pub fn pool_response_buffers(&self) -> bool {
+        self.server.http.pool_response_buffers
+    }
+
     pub fn http_log_request(&self) -> bool {
         self.server.http.log_request
     }
@@ -245,6 +582,10 @@ impl TSConfig {
         self.server.http.host.as_ref()
     }
 
+    pub fn udp_enabled(&self) -> bool {
+        self.server.udp.enabled
+    }
+
     pub fn udp_port(&self) -> u16 {
         self.server.udp.port
     }
@@ -253,10 +594,46 @@ impl TSConfig {
         self.server.udp.host.as_ref()
     }
 
+    pub fn udp_secret_key(&self) -> Option<&String> {
+        self.server.udp.secret_key.as_ref()
+    }
+
+    pub fn udp_recv_buffer_size(&self) -> usize {
+        self.server.udp.recv_buffer_size
+    }
+
+    pub fn udp_workers(&self) -> usize {
+        self.server.udp.workers
+    }
+
     pub fn ip_forward_header_name(&self) -> Option<&String> {
         self.server.http.ip_forward_header_name.as_ref()
     }
 
+    pub fn require_forward_header(&self) -> bool {
+        self.server.http.require_forward_header
+    }
+
+    /// The query keys whose values are decoded as binary, per `strict_binary_params`.
+    pub fn binary_query_keys(&self) -> &'static [&'static str] {
+        match self.server.http.strict_binary_params {
+            true => BINARY_QUERY_KEYS,
+            false => &[],
+        }
+    }
+
+    pub fn trusted_proxies(&self) -> &[IpAddr] {
+        self.server.http.trusted_proxies.as_ref()
+    }
+
+    pub fn disabled_protocol_status(&self) -> DisabledProtocolStatus {
+        self.server.http.disabled_protocol_status
+    }
+
+    pub fn tracker_id(&self) -> Option<&String> {
+        self.server.tracker_id.as_ref()
+    }
+
     pub fn api_key(&self) -> Option<&String> {
         self.server.http.api_key.as_ref()
     }
@@ -269,6 +646,10 @@ impl TSConfig {
         self.server.http.keep_alive_idle_time
     }
 
+    pub fn max_requests_per_connection(&self) -> usize {
+        self.server.http.max_requests_per_connection
+    }
+
     pub fn max_open_connections(&self) -> usize {
         self.server.http.max_concurrent_requests
     }
@@ -277,6 +658,18 @@ impl TSConfig {
         self.server.http.connection_backlog_size as i32
     }
 
+    pub fn http_reuse_port(&self) -> bool {
+        self.server.http.reuse_port
+    }
+
+    pub fn http_max_headers(&self) -> usize {
+        self.server.http.max_headers
+    }
+
+    pub fn http_max_header_size(&self) -> usize {
+        self.server.http.max_header_size
+    }
+
     pub fn max_numwant(&self) -> u32 {
         self.tracker.max_numwant
     }
@@ -285,15 +678,84 @@ impl TSConfig {
         self.tracker.default_numwant
     }
 
-    pub fn announce_interval(&self) -> u32 {
+    pub fn started_numwant(&self) -> u32 {
+        self.tracker.started_numwant
+    }
+
+    pub fn periodic_numwant(&self) -> u32 {
+        self.tracker.periodic_numwant
+    }
+
+    /// Returns the number of peers for a request without a `numwant`, which depends on its event.
+    pub fn numwant_for_event(&self, event: Option<AnnounceEvent>) -> u32 {
+        match event {
+            Some(AnnounceEvent::Started) => self.started_numwant(),
+            None | Some(AnnounceEvent::None) => self.periodic_numwant(),
+            _ => self.default_numwant(),
+        }
+    }
+
+    pub fn peer_selection(&self) -> PeerSelection {
+        self.tracker.peer_selection
+    }
+
+    pub fn dedup_peer_endpoints(&self) -> bool {
+        self.tracker.dedup_peer_endpoints
+    }
+
+    pub fn return_both_ip_families(&self) -> bool {
+        self.tracker.return_both_ip_families
+    }
+
+    pub fn clamp_peer_counts(&self) -> bool {
+        self.tracker.clamp_peer_counts
+    }
+
+    pub fn warn_no_peers(&self) -> bool {
+        self.tracker.warn_no_peers
+    }
+
+    pub fn report_downloaders(&self) -> bool {
+        self.tracker.report_downloaders
+    }
+
+    pub fn scrape_request_order(&self) -> bool {
+        self.tracker.scrape_request_order
+    }
+
+    pub fn lenient_completed(&self) -> bool {
+        self.tracker.lenient_completed
+    }
+
+    pub fn peer_id_only(&self) -> bool {
+        self.tracker.peer_id_only
+    }
+
+    pub fn require_key(&self) -> bool {
+        self.tracker.require_key
+    }
+
+    pub fn compact_only(&self) -> bool {
+        self.tracker.compact_only
+    }
+
+    pub fn compact_only_mode(&self) -> CompactOnlyMode {
+        self.tracker.compact_only_mode
+    }
+
+    pub fn ip_param_mode(&self) -> IpParamMode {
+        self.tracker.ip_param_mode
+    }
+
+    pub fn announce_interval(&self) -> IntervalDuration {
         self.tracker.announce_interval
     }
 
-    pub fn min_announce_interval(&self) -> u32 {
+    pub fn min_announce_interval(&self) -> IntervalDuration {
         self.tracker.min_announce_interval
     }
 
-    pub fn scrape_interval(&self) -> u32 {
+    pub fn scrape_interval(&self) -> IntervalDuration {
         self.tracker.scrape_interval
     }
 
@@ -321,6 +783,25 @@ impl TSConfig {
         self.log_level.as_ref()
     }
 
+    /// Parses `log_filters` into the level of each module it names.
+    pub fn log_filters(&self) -> Result<Vec<(&str, LevelFilter)>, ConfigError> {
+        self.log_filters
+            .split(',')
+            .map(str::trim)
+            .filter(|directive| !directive.is_empty())
+            .map(|directive| {
+                let (module, level) = directive.split_once('=').unwrap_or((directive, ""));
+                match (module.trim(), level.trim().parse()) {
+                    (module, Ok(level)) if !module.is_empty() => Ok((module, level)),
+                    _ => Err(ConfigError::Message(format!(
+                        "invalid log filter {}",
+                        directive
+                    ))),
+                }
+            })
+            .collect()
+    }
+
     pub fn max_multi_scrape_count(&self) -> u32 {
         self.tracker.max_multi_scrape_count
     }
@@ -328,20 +809,169 @@ impl TSConfig {
     pub fn full_scrape_cache_ttl(&self) -> Duration {
         self.tracker.full_scrape_cache_ttl
     }
+
+    pub fn max_full_scrape_refreshes(&self) -> usize {
+        self.tracker.max_full_scrape_refreshes
+    }
+
+    pub fn full_scrape_format(&self) -> FullScrapeFormat {
+        self.tracker.full_scrape_format
+    }
+
+    pub fn scrape_cache_ttl(&self) -> Duration {
+        self.tracker.scrape_cache_ttl
+    }
+
+    pub fn max_concurrent_tasks(&self) -> usize {
+        self.tracker.max_concurrent_tasks
+    }
+
+    pub fn max_queued_tasks(&self) -> usize {
+        self.tracker.max_queued_tasks
+    }
+
+    pub fn max_concurrent_scrapes(&self) -> usize {
+        self.tracker.max_concurrent_scrapes
+    }
 }
 
 #[derive(Debug, Default, Clone)]
-pub struct InfoHashBlockList(Set<InfoHash>);
+pub struct InfoHashBlockList {
+    set: Set<InfoHash>,
+
+    /// The file the blocklist was loaded from, used to persist runtime changes.
+    file_path: Option<String>,
+}
 
 impl InfoHashBlockList {
     fn load(&mut self, file_path: &str) -> std::io::Result<()> {
-        self.0.load_from_file(file_path)
+        self.set.load_from_file(file_path)?;
+        self.file_path = Some(file_path.to_owned());
+        Ok(())
+    }
+
+    /// Adds an infohash to the blocklist, saving it to the blocklist file if `persist` is set.
+    /// Returns `true` if the infohash was not already blocked.
+    pub fn insert(&mut self, info_hash: InfoHash, persist: bool) -> std::io::Result<bool> {
+        let inserted = self.set.insert(info_hash);
+        if inserted && persist {
+            self.save()?;
+        }
+
+        Ok(inserted)
+    }
+
+    /// Removes an infohash from the blocklist, saving it to the blocklist file if `persist` is set.
+    /// Returns `true` if the infohash was blocked.
+    pub fn remove(&mut self, info_hash: &InfoHash, persist: bool) -> std::io::Result<bool> {
+        let removed = self.set.remove(info_hash);
+        if removed && persist {
+            self.save()?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Writes the blocklist back to the file it was loaded from, if any.
+    pub fn save(&self) -> std::io::Result<()> {
+        match self.file_path {
+            Some(ref file_path) => self.set.save_to_file(file_path),
+            None => Ok(()),
+        }
     }
 }
 
 impl std::ops::Deref for InfoHashBlockList {
     type Target = Set<InfoHash>;
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.set
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_config(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("ts-tracker-{}.toml", name));
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_validate_default_config() {
+        let config = TSConfig::new().unwrap();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_invalid_config() {
+        let file = write_config(
+            "invalid",
+            "[tracker]\nmin_announce_interval = 3600\ndefault_numwant = 100\n",
+        );
+
+        let config = TSConfig::load(Some(&file)).unwrap();
+        let err = config.validate().unwrap_err().to_string();
+
+        assert!(err.contains("min_announce_interval"));
+        assert!(err.contains("default_numwant"));
+
+        let file = write_config("malformed", "[tracker]\nmax_numwant = \"lots\"\n");
+        assert!(TSConfig::load(Some(&file)).is_err());
+    }
+
+    #[test]
+    fn test_missing_blocklist_file() {
+        let blocklist = std::env::temp_dir().join("ts-tracker-missing-blocklist.txt");
+        let blocklist = blocklist.to_string_lossy().into_owned();
+        let file = write_config(
+            "missing-blocklist",
+            &format!("infohash_blocklist_file = {:?}\n", blocklist),
+        );
+
+        let err = TSConfig::load(Some(&file)).unwrap_err().to_string();
+        assert!(err.contains("failed to load infohash blocklist"));
+        assert!(err.contains(&blocklist));
+    }
+
+    #[test]
+    fn test_validate_max_read_buffer_size() {
+        let mut config = TSConfig::new().unwrap();
+        config.server.http.max_read_buffer_size = MIN_READ_BUFFER_SIZE - 1;
+
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("max_read_buffer_size"));
+
+        config.server.http.max_read_buffer_size = MIN_READ_BUFFER_SIZE;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_header_limits() {
+        let mut config = TSConfig::new().unwrap();
+        config.server.http.max_headers = MAX_HEADERS + 1;
+        config.server.http.max_header_size = MIN_READ_BUFFER_SIZE - 1;
+
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("max_headers"));
+        assert!(err.contains("max_header_size"));
+
+        config.server.http.max_headers = MAX_HEADERS;
+        config.server.http.max_header_size = MIN_READ_BUFFER_SIZE;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_intervals() {
+        let file = write_config("zero-interval", "[tracker]\nscrape_interval = 0\n");
+        let config = TSConfig::load(Some(&file)).unwrap();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("scrape_interval"));
+
+        let file = write_config("short-interval", "[tracker]\nmin_announce_interval = 29\n");
+        let config = TSConfig::load(Some(&file)).unwrap();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("min_announce_interval"));
     }
 }
@@ -20,6 +20,14 @@ constant_strings! {
     (UNAUTHORIZED, "Unauthorized"),
     (BAD_REQUEST, "Bad request"),
     (INTERNAL_SERVER_ERROR, "An Error Occurred, Please Try Again!"),
+    (SERVICE_UNAVAILABLE, "Service Unavailable"),
+    (REQUEST_HEADER_FIELDS_TOO_LARGE, "Request Header Fields Too Large"),
+    (PAYLOAD_TOO_LARGE, "Payload Too Large"),
+
+    // HTTP HEADERS
+    (API_KEY_HEADER, "x-api-key"),
+    (REQUEST_ID_HEADER, "x-request-id"),
+    (NUMWANT_HEADER, "x-tracker-numwant"),
 
     // TRACKER
     (TRACKER_RESPONSE_TRACKER_ID, "tracker id"),
@@ -36,6 +44,8 @@ constant_strings! {
     (TRACKER_RESPONSE_PEERS6, "peers6"),
     (TRACKER_RESPONSE_DOWNLOADED, "downloaded"),
     (TRACKER_RESPONSE_DOWNLOADERS, "downloaders"),
+    (TRACKER_RESPONSE_IPV4, "ipv4"),
+    (TRACKER_RESPONSE_IPV6, "ipv6"),
     (TRACKER_RESPONSE_CRYPTO_FLAGS, "crypto_flags"),
     (TRACKER_RESPONSE_RETRY_IN, "retry in"),
     (TRACKER_RESPONSE_PEER_ID, "peer id"),
@@ -62,4 +72,15 @@ constant_strings! {
     (TRACKER_ERROR_BLOCKED_IP, "blocked ip"),
     (TRACKER_ERROR_HTTP_SCRAPE_NOT_ALLOWED, "http scrape not allowed"),
     (TRACKER_ERROR_HTTP_ANNOUNCE_NOT_ALLOWED, "http announce not allowed"),
+    (TRACKER_ERROR_UDP_ANNOUNCE_NOT_ALLOWED, "udp announce not allowed"),
+    (TRACKER_ERROR_INVALID_CONNECTION_ID, "invalid connection id"),
+    (TRACKER_ERROR_TOO_MANY_INFOHASHES, "too many infohashes in a single scrape"),
+    (TRACKER_ERROR_MISSING_FORWARDED_IP, "missing or invalid forwarded ip"),
+    (TRACKER_ERROR_MISSING_KEY, "missing or invalid key"),
+    (TRACKER_ERROR_KEY_MISMATCH, "key does not match the one the peer announced with"),
+    (TRACKER_ERROR_IP_FAMILY_MISMATCH, "ip is not of the family of the connection"),
+
+    // TRACKER WARNINGS
+    (TRACKER_WARNING_NUMWANT_EXCEEDED, "numwant exceeds the maximum, fewer peers are returned"),
+    (TRACKER_WARNING_NO_PEERS, "no other peers available"),
 }
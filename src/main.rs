@@ -1,6 +1,9 @@
-use log::{info, LevelFilter};
-use std::str::FromStr;
-use ts_tracker::{app, config::TSConfig, signals::global_shutdown_signal};
+use log::{error, info};
+use std::net::SocketAddr;
+use ts_tracker::{
+    app, config::TSConfig, servers::self_test, signals::global_shutdown_signal,
+    utils::logger_builder,
+};
 
 #[cfg(feature = "memalloc")]
 #[global_allocator]
@@ -14,11 +17,54 @@ static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+
+    // `--check-config [FILE]` validates the configuration, with FILE on top of it, and exits.
+    if let Some(pos) = args.iter().position(|arg| arg == "--check-config") {
+        let file = args
+            .get(pos + 1)
+            .map(String::as_str)
+            .filter(|arg| !arg.starts_with('-'));
+        std::process::exit(check_config(file));
+    }
+
     let config = TSConfig::new().expect("failed to load config");
-    setup_logger(&config.log_level);
+    if let Err(err) = config.validate() {
+        eprintln!("invalid config: {}", err);
+        std::process::exit(1);
+    }
+
+    logger_builder(&config).init();
+
+    // `--selftest` checks the tracker through its own endpoints once started, exiting on failure.
+    let self_test_addr = match args.iter().any(|arg| arg == "--selftest") {
+        true => Some(SocketAddr::new(
+            config.http_host().parse().unwrap(),
+            config.http_port(),
+        )),
+        false => None,
+    };
 
     let (stop_tx, stop_rx) = tokio::sync::watch::channel(false);
-    let jobs = app::start(config, stop_rx);
+    let (state, jobs) = match app::start(config, stop_rx) {
+        Ok(started) => started,
+        Err(err) => {
+            error!("Failed to start tracker: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(addr) = self_test_addr {
+        match self_test(addr, &state.worker).await {
+            Ok(_) => info!("Self-test passed"),
+            Err(err) => {
+                error!("Self-test failed: {}", err);
+                stop_tx.send(true).expect("failed to send shutdown signal");
+                futures::future::join_all(jobs).await;
+                std::process::exit(1);
+            }
+        }
+    }
 
     tokio::select! {
         _ = global_shutdown_signal() => {
@@ -33,13 +79,20 @@ async fn main() {
     }
 }
 
-fn setup_logger(log_level: &str) {
-    let log_level = LevelFilter::from_str(log_level).unwrap();
-
-    env_logger::builder()
-        .filter_level(log_level)
-        .format_timestamp(None)
-        // .filter_module("ts_tracker", log_level)
-        // .filter_module("ts_utils", log_level)
-        .init();
+/// Loads and validates the configuration without starting any server or storage.
+/// Returns the exit code of the process.
+fn check_config(file: Option<&str>) -> i32 {
+    match TSConfig::load(file).and_then(|config| config.validate().map(|_| config)) {
+        Ok(config) => {
+            println!("config ok");
+            println!("  http: {}:{}", config.http_host(), config.http_port());
+            println!("  storage: {:?}", config.storage.name);
+            println!("  log level: {}", config.log_level);
+            0
+        }
+        Err(err) => {
+            eprintln!("invalid config: {}", err);
+            1
+        }
+    }
 }
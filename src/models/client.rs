@@ -0,0 +1,167 @@
+//! Decoding of the client name and version embedded in a peer id.
+//!
+//! Two conventions are recognized:
+//! - Azureus-style: `-XX1234-`, where `XX` identifies the client and `1234` is the version.
+//! - Shad0w-style: `S58B-----`, where the first byte identifies the client and the
+//!   following (up to five) bytes encode the version.
+//!
+//! More information: https://wiki.theory.org/BitTorrentSpecification#peer_id
+
+/// Known Azureus-style client codes.
+const AZUREUS_CLIENTS: &[(&[u8; 2], &str)] = &[
+    (b"AG", "Ares"),
+    (b"AZ", "Vuze"),
+    (b"BC", "BitComet"),
+    (b"BI", "BiglyBT"),
+    (b"BT", "BitTorrent"),
+    (b"DE", "Deluge"),
+    (b"FD", "Free Download Manager"),
+    (b"FG", "FlashGet"),
+    (b"KT", "KTorrent"),
+    (b"LT", "libtorrent"),
+    (b"lt", "rTorrent"),
+    (b"PI", "PicoTorrent"),
+    (b"qB", "qBittorrent"),
+    (b"SD", "Thunder"),
+    (b"TR", "Transmission"),
+    (b"UM", "µTorrent Mac"),
+    (b"UT", "µTorrent"),
+    (b"UW", "µTorrent Web"),
+    (b"WW", "WebTorrent"),
+    (b"XL", "Xunlei"),
+];
+
+/// Known Shad0w-style client codes.
+const SHADOW_CLIENTS: &[(u8, &str)] = &[
+    (b'A', "ABC"),
+    (b'O', "Osprey Permaseed"),
+    (b'Q', "BTQueue"),
+    (b'R', "Tribler"),
+    (b'S', "Shadow"),
+    (b'T', "BitTornado"),
+    (b'U', "UPnP NAT Bit Torrent"),
+];
+
+/// The number of bytes used by the Azureus-style prefix, e.g. `-qB4500-`.
+const AZUREUS_PREFIX_LENGTH: usize = 8;
+
+/// The maximum number of version bytes in a Shad0w-style prefix.
+const SHADOW_VERSION_LENGTH: usize = 5;
+
+/// Decodes a human readable client label such as `qBittorrent 4.5.0` from a peer id.
+///
+/// Returns `None` if the peer id does not follow a known convention or
+/// the client code is not recognized.
+pub fn decode(peer_id: &[u8]) -> Option<String> {
+    decode_azureus(peer_id).or_else(|| decode_shadow(peer_id))
+}
+
+fn decode_azureus(peer_id: &[u8]) -> Option<String> {
+    if peer_id.len() < AZUREUS_PREFIX_LENGTH || peer_id[0] != b'-' || peer_id[7] != b'-' {
+        return None;
+    }
+
+    let code = &peer_id[1..3];
+    let version = &peer_id[3..7];
+
+    if !version.iter().all(u8::is_ascii_alphanumeric) {
+        return None;
+    }
+
+    let (_, name) = AZUREUS_CLIENTS.iter().find(|(c, _)| c.as_slice() == code)?;
+
+    // The fourth version byte is usually a build number and is omitted when zero.
+    let parts = match version[3] {
+        b'0' => &version[..3],
+        _ => version,
+    };
+
+    let version = parts
+        .iter()
+        .map(|b| (*b as char).to_string())
+        .collect::<Vec<_>>()
+        .join(".");
+
+    Some(format!("{} {}", name, version))
+}
+
+fn decode_shadow(peer_id: &[u8]) -> Option<String> {
+    if peer_id.len() < SHADOW_VERSION_LENGTH + 4 || &peer_id[6..9] != b"---" {
+        return None;
+    }
+
+    let (_, name) = SHADOW_CLIENTS.iter().find(|(c, _)| *c == peer_id[0])?;
+
+    let version = &peer_id[1..=SHADOW_VERSION_LENGTH];
+    let len = version
+        .iter()
+        .position(|b| *b == b'-')
+        .unwrap_or(SHADOW_VERSION_LENGTH);
+
+    if len == 0 || !version[len..].iter().all(|b| *b == b'-') {
+        return None;
+    }
+
+    let version = version[..len]
+        .iter()
+        .map(|b| decode_shadow_digit(*b).map(|d| d.to_string()))
+        .collect::<Option<Vec<_>>>()?
+        .join(".");
+
+    Some(format!("{} {}", name, version))
+}
+
+/// Decodes a single Shad0w-style version byte: `0-9`, `A-Z` (10-35), `a-z` (36-61), `.` (62).
+fn decode_shadow_digit(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'A'..=b'Z' => Some(b - b'A' + 10),
+        b'a'..=b'z' => Some(b - b'a' + 36),
+        b'.' => Some(62),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_azureus() {
+        assert_eq!(
+            decode(b"-qB4500-k8a2Jf0sXP1c"),
+            Some("qBittorrent 4.5.0".to_string())
+        );
+
+        assert_eq!(
+            decode(b"-TR2940-5f1e9a7c3b2d"),
+            Some("Transmission 2.9.4".to_string())
+        );
+
+        assert_eq!(
+            decode(b"-UT355S-a1b2c3d4e5f6"),
+            Some("µTorrent 3.5.5.S".to_string())
+        );
+    }
+
+    #[test]
+    fn test_decode_shadow() {
+        assert_eq!(
+            decode(b"S58B-----a1b2c3d4e5f"),
+            Some("Shadow 5.8.11".to_string())
+        );
+
+        assert_eq!(
+            decode(b"T03I-----a1b2c3d4e5f"),
+            Some("BitTornado 0.3.18".to_string())
+        );
+    }
+
+    #[test]
+    fn test_decode_unrecognized() {
+        assert_eq!(decode(b"01234567890123456789"), None);
+        assert_eq!(decode(b"-ZZ1234-a1b2c3d4e5f6"), None);
+        assert_eq!(decode(b"-qB45-0-a1b2c3d4e5f6"), None);
+        assert_eq!(decode(b"S5-8B----a1b2c3d4e5f"), None);
+    }
+}
@@ -1,7 +1,11 @@
 use serde::{Deserialize, Serialize};
-use std::fmt::{self, Debug};
-use ts_utils::{hex, query};
+use std::{
+    fmt::{self, Debug},
+    net::IpAddr,
+};
+use ts_utils::{hex, query, serde::deserialize_bounded_u64};
 
+use super::client;
 use crate::constants;
 
 /// The length of the Peer ID used in the BitTorrent protocol.
@@ -17,6 +21,15 @@ pub const INFOHASH_LENGTH: usize = 20;
 #[derive(PartialEq, Eq, Hash, Clone, Default)]
 pub struct InfoHash(pub [u8; INFOHASH_LENGTH]);
 
+impl InfoHash {
+    /// Compares two infohashes in a time independent of where they differ, for use where the
+    /// infohash is kept secret, as on a private tracker. The derived `PartialEq` may stop at
+    /// the first differing byte.
+    pub fn ct_eq(&self, other: &InfoHash) -> bool {
+        ts_utils::cmp::ct_eq(&self.0, &other.0)
+    }
+}
+
 impl AsRef<[u8]> for InfoHash {
     fn as_ref(&self) -> &[u8] {
         &self.0
@@ -127,6 +140,19 @@ impl<'de> Deserialize<'de> for Port {
 #[derive(PartialEq, Default, Eq, Hash, Clone)]
 pub struct PeerId(pub [u8; PEER_ID_LENGTH]);
 
+impl PeerId {
+    /// Returns a human readable client label such as `qBittorrent 4.5.0`, if the peer id
+    /// follows the Azureus (`-XX1234-`) or Shad0w (`S58B-----`) convention.
+    pub fn client_name(&self) -> Option<String> {
+        client::decode(&self.0)
+    }
+
+    /// Returns the peer id as a hex string, which stays readable for binary peer ids.
+    pub fn to_hex(&self) -> String {
+        hex::encode(&self.0)
+    }
+}
+
 impl AsRef<[u8]> for PeerId {
     fn as_ref(&self) -> &[u8] {
         &self.0
@@ -140,9 +166,11 @@ impl fmt::Display for PeerId {
     }
 }
 
+/// Formats the peer id as hex, so that the logs of binary peer ids stay valid UTF-8
+/// without replacement characters.
 impl fmt::Debug for PeerId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Display::fmt(self, f)
+        f.write_str(&self.to_hex())
     }
 }
 
@@ -204,22 +232,40 @@ impl<'de> Deserialize<'de> for PeerKey {
         D: serde::Deserializer<'de>,
     {
         let v: Option<&str> = Option::deserialize(deserializer)?;
+        Ok(v.map(PeerKey::parse).unwrap_or_default())
+    }
+}
+
+impl PeerKey {
+    /// Parses a key sent by a client.
+    ///
+    /// A key of exactly 8 hex chars is decoded into its 4 bytes. Any other key of at least
+    /// 4 bytes is taken as raw bytes, of which the first 4 are kept. Shorter keys are ignored.
+    pub fn parse(v: &str) -> Self {
+        if v.len() == PEERKEY_LENGTH * 2 {
+            if let Ok(Ok(bytes)) = hex::decode(v).map(<[u8; PEERKEY_LENGTH]>::try_from) {
+                return PeerKey(Some(bytes));
+            }
+        }
 
-        // Try to convert the string to a [u8; 4]
-        let v: Option<[u8; PEERKEY_LENGTH]> = v.and_then(|v| {
-            hex::decode(v)
-                .ok()
-                .and_then(|v| v.try_into().ok())
-                .or_else(|| {
-                    if v.len() >= PEERKEY_LENGTH {
-                        v.as_bytes()[..PEERKEY_LENGTH].try_into().ok()
-                    } else {
-                        None
-                    }
-                })
-        });
+        PeerKey(
+            v.as_bytes()
+                .get(..PEERKEY_LENGTH)
+                .and_then(|v| v.try_into().ok()),
+        )
+    }
 
-        Ok(PeerKey(v))
+    /// Returns true if a key was sent and it is not all zeros.
+    pub fn is_usable(&self) -> bool {
+        matches!(self.0, Some(key) if key != [0; PEERKEY_LENGTH])
+    }
+}
+
+impl std::str::FromStr for PeerKey {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(PeerKey::parse(s))
     }
 }
 
@@ -253,6 +299,10 @@ impl Into<Option<u32>> for PeerKey {
     }
 }
 
+/// The largest byte count accepted in an announce. Clients count the transferred bytes in an
+/// `i64`, so anything larger is bogus.
+pub const MAX_NUM_OF_BYTES: u64 = i64::MAX as u64;
+
 /// Represents the number of bytes.
 /// The `NumOfBytes` struct encapsulates a 64-bit unsigned integer representing the count of bytes.
 #[derive(PartialEq, Eq, Hash, Clone, Copy, Default)]
@@ -285,7 +335,7 @@ impl<'de> Deserialize<'de> for NumOfBytes {
     where
         D: serde::Deserializer<'de>,
     {
-        let value = u64::deserialize(deserializer)?;
+        let value = deserialize_bounded_u64(deserializer, MAX_NUM_OF_BYTES)?;
         Ok(NumOfBytes(value))
     }
 }
@@ -293,6 +343,8 @@ impl<'de> Deserialize<'de> for NumOfBytes {
 /// The `UnixEpochSecs` type represents a point in time measured as the number of seconds elapsed
 /// since the Unix epoch (1970-01-01 00:00:00 UTC).
 pub type UnixEpochSecs = u64;
+
+/// An announce or scrape interval in seconds, as configured and as sent to clients.
 pub type IntervalDuration = u32;
 
 /// Represents an IP address type, which can be either V4 (IPv4) or V6 (IPv6).
@@ -302,3 +354,121 @@ pub enum IpType {
     V4, // IPv4 address type
     V6, // IPv6 address type
 }
+
+impl From<IpAddr> for IpType {
+    /// IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) are treated as IPv4.
+    fn from(ip: IpAddr) -> Self {
+        match ip.to_canonical() {
+            IpAddr::V4(_) => IpType::V4,
+            IpAddr::V6(_) => IpType::V6,
+        }
+    }
+}
+
+/// The transport a request was received over.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Protocol {
+    Http,
+    Udp,
+}
+
+impl Protocol {
+    pub const ALL: [Protocol; 2] = [Protocol::Http, Protocol::Udp];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::Http => "http",
+            Protocol::Udp => "udp",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{models::tracker::AnnounceRequest, utils::Loggable};
+    use std::net::Ipv6Addr;
+
+    #[test]
+    fn test_ip_type_from_mapped_ipv4() {
+        let ip: IpAddr = "::ffff:192.0.2.1".parse().unwrap();
+        assert_eq!(IpType::from(ip), IpType::V4);
+        assert_eq!(ip.to_canonical(), IpAddr::from([192, 0, 2, 1]));
+
+        assert_eq!(IpType::from(IpAddr::V6(Ipv6Addr::LOCALHOST)), IpType::V6);
+    }
+
+    #[test]
+    fn test_info_hash_ct_eq() {
+        let info_hash = InfoHash(*b"aaaaaaaaaaaaaaaaaaaa");
+        assert!(info_hash.ct_eq(&info_hash.clone()));
+
+        // Differing at any position, or in every bit of a byte, agrees with the derived eq.
+        for i in 0..INFOHASH_LENGTH {
+            for flip in [0x01, 0x80, 0xff] {
+                let mut other = info_hash.clone();
+                other.0[i] ^= flip;
+                assert_eq!(info_hash.ct_eq(&other), info_hash == other);
+                assert!(!info_hash.ct_eq(&other));
+            }
+        }
+
+        assert!(InfoHash::default().ct_eq(&InfoHash([0; INFOHASH_LENGTH])));
+    }
+
+    #[test]
+    fn test_binary_peer_id_logged_as_hex() {
+        let mut bytes = *b"-TS0001-000000000000";
+        bytes[8..12].copy_from_slice(&[0xff, 0xfe, 0x00, 0x80]);
+        let peer_id = PeerId::from(bytes);
+        assert!(std::str::from_utf8(&peer_id.0).is_err());
+
+        let hex = peer_id.to_hex();
+        assert_eq!(hex.len(), PEER_ID_LENGTH * 2);
+        assert!(hex.starts_with("2d5453303030312dfffe0080"));
+        assert_eq!(hex::decode(&hex).unwrap(), bytes);
+
+        let query = b"info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=-TS0001-%ff%fe%00%80\
+            00000000&port=6881";
+        let req: AnnounceRequest = query::from_bytes(query).unwrap();
+        let log = req.log();
+        assert!(log.contains(&hex));
+        assert!(!log.contains(char::REPLACEMENT_CHARACTER));
+    }
+
+    #[test]
+    fn test_peer_key_parse() {
+        let key: PeerKey = "0a1b2c3d".parse().unwrap();
+        assert_eq!(key.0, Some([0x0a, 0x1b, 0x2c, 0x3d]));
+
+        // Not hex, so the first 4 bytes are taken as they are.
+        let key: PeerKey = "zz1b2c3d".parse().unwrap();
+        assert_eq!(key.0, Some(*b"zz1b"));
+
+        let key: PeerKey = "abcdefghij".parse().unwrap();
+        assert_eq!(key.0, Some(*b"abcd"));
+
+        let key: PeerKey = "abc".parse().unwrap();
+        assert_eq!(key.0, None);
+
+        let bytes = b"info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=01234567890123456789&port=6881";
+        let req: AnnounceRequest = query::from_bytes(bytes).unwrap();
+        assert_eq!(req.key.0, None);
+    }
+
+    #[test]
+    fn test_peer_key_serde_round_trip() {
+        let key = PeerKey(Some([0x0a, 0x1b, 0x2c, 0x3d]));
+        let encoded = ts_utils::bencode::encode(&key).unwrap();
+        assert_eq!(encoded.as_ref(), b"8:0a1b2c3d");
+
+        // The serialized hex form is read back as the same key.
+        let hex = std::str::from_utf8(&encoded[2..]).unwrap();
+        let query = format!(
+            "info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=01234567890123456789&port=6881&key={}",
+            hex
+        );
+        let req: AnnounceRequest = query::from_bytes(query.as_bytes()).unwrap();
+        assert_eq!(req.key, key);
+    }
+}
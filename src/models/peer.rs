@@ -1,5 +1,5 @@
 use super::{
-    common::{IpType, Port},
+    common::{IpType, Port, PEERKEY_LENGTH},
     tracker::AnnounceRequest,
 };
 use crate::config::TrackerConfig;
@@ -12,7 +12,8 @@ use std::{
 use ts_utils::time::{Clock, Duration};
 
 /// An enumeration representing the type of a peer in a BitTorrent swarm.
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
 pub enum PeerType {
     /// A leecher is a peer that is currently downloading the torrent and does not have the complete file.
     Leecher,
@@ -25,6 +26,22 @@ pub enum PeerType {
     Partial,
 }
 
+impl PeerType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PeerType::Leecher => "leecher",
+            PeerType::Seeder => "seeder",
+            PeerType::Partial => "partial",
+        }
+    }
+}
+
+impl fmt::Display for PeerType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 /// Represents a peer in a BitTorrent swarm.
 #[derive(Debug, Clone)]
 pub struct Peer {
@@ -33,6 +50,16 @@ pub struct Peer {
 
     /// The duration since unix epoch at which the peer will expire.
     pub expire_at: Duration,
+
+    /// The duration since unix epoch of the last announce of the peer.
+    pub last_seen: Duration,
+
+    /// Set to `1` if the peer requires encrypted connections, `0` otherwise.
+    pub crypto_flag: u8,
+
+    /// The key the peer announced with, kept if peers are identified by their peer id alone,
+    /// so that the key still proves the peer's identity.
+    pub key: Option<[u8; PEERKEY_LENGTH]>,
 }
 
 impl Peer {
@@ -41,17 +68,54 @@ impl Peer {
     }
 }
 
+#[cfg(test)]
+impl Peer {
+    /// Returns a peer at `addr` that announced just now, without a crypto flag or key.
+    pub fn new_test(addr: impl Into<PeerAddr>) -> Self {
+        let now = Clock::now_since_epoch();
+
+        Peer {
+            addr: addr.into(),
+            expire_at: now,
+            last_seen: now,
+            crypto_flag: 0,
+            key: None,
+        }
+    }
+}
+
+/// The latest `expire_at` of a peer, in secs. A coarse `Duration` keeps its secs in 32 bits.
+const MAX_EXPIRE_AT_SECS: u64 = u32::MAX as u64;
+
 impl From<(&AnnounceRequest, IpAddr, &TrackerConfig)> for Peer {
     fn from(value: (&AnnounceRequest, IpAddr, &TrackerConfig)) -> Self {
         let (req, ip, config) = value;
-        let expire_at = Clock::now_since_epoch() + Duration::from(config.peer_idle_time);
+        let now = Clock::now_since_epoch();
+
+        // An idle time reaching past the latest representable expiry is clamped to it.
+        let max_idle_time = MAX_EXPIRE_AT_SECS.saturating_sub(now.as_secs());
+        let idle_time = config
+            .peer_idle_time
+            .min(std::time::Duration::from_secs(max_idle_time));
+        let expire_at = now + Duration::from(idle_time);
 
-        let addr = match ip {
-            IpAddr::V4(ip) => (ip, req.port).into(),
-            IpAddr::V6(ip) => (ip, req.port).into(),
+        let addr = SocketAddr::new(ip.to_canonical(), req.port.0).into();
+
+        let crypto_flag = req.requirecrypto as u8;
+
+        // Otherwise the key is part of the peer's identity already.
+        let key = match config.peer_id_only {
+            true => *req.key,
+            false => None,
         };
 
-        Self { addr, expire_at }
+        Self {
+            addr,
+            expire_at,
+            last_seen: now,
+            crypto_flag,
+            key,
+        }
     }
 }
 
@@ -61,8 +125,28 @@ pub const IP_V4_LENGTH: usize = 4;
 pub const IP_V6_LENGTH: usize = 16;
 pub const PORT_LENGTH: usize = 2;
 
+/// Returns the length of a compact peer address for the given IP type.
+pub fn peer_addr_length(ip_type: IpType) -> usize {
+    match ip_type {
+        IpType::V4 => PEER_ADDR_V4_LENGTH,
+        IpType::V6 => PEER_ADDR_V6_LENGTH,
+    }
+}
+
+/// The error returned when a byte slice is neither an IPv4 nor an IPv6 compact peer address.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct InvalidPeerAddrLength(pub usize);
+
+impl fmt::Display for InvalidPeerAddrLength {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid peer address length: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidPeerAddrLength {}
+
 /// The IP address and port number of a peer in a BitTorrent swarm.
-#[derive(PartialEq, Eq, Clone)]
+#[derive(PartialEq, Eq, Hash, Clone)]
 pub enum PeerAddr {
     /// Represents an IPv4 address of a peer. It contains an array of `u8` with a length of `PEER_ADDR_V4_LENGTH`,
     /// which typically represents the IPv4 address in octets (4 bytes) and an additional 2 bytes for the port number.
@@ -107,6 +191,22 @@ macro_rules! impl_ip_port_into_peer_addr {
 impl_ip_port_into_peer_addr!(Ipv4Addr, V4, PeerAddrV4, PEER_ADDR_V4_LENGTH, IP_V4_LENGTH);
 impl_ip_port_into_peer_addr!(Ipv6Addr, V6, PeerAddrV6, PEER_ADDR_V6_LENGTH, IP_V6_LENGTH);
 
+impl From<&SocketAddr> for PeerAddr {
+    fn from(value: &SocketAddr) -> Self {
+        let port = Port(value.port());
+        match value.ip() {
+            IpAddr::V4(ip) => (ip, port).into(),
+            IpAddr::V6(ip) => (ip, port).into(),
+        }
+    }
+}
+
+impl From<SocketAddr> for PeerAddr {
+    fn from(value: SocketAddr) -> Self {
+        (&value).into()
+    }
+}
+
 macro_rules! extract_ip_port {
     ($ip_type:expr, $ip_conv:ty, $ip_length:expr, $bytes:expr) => {
         let mut ip_bytes = [0; $ip_length];
@@ -159,14 +259,7 @@ impl<'de> Deserialize<'de> for PeerAddr {
         D: serde::Deserializer<'de>,
     {
         let bytes: Vec<u8> = Deserialize::deserialize(deserializer)?;
-        match bytes.len() {
-            PEER_ADDR_V4_LENGTH => Ok(PeerAddr::V4(PeerAddrV4(bytes.try_into().unwrap()))),
-            PEER_ADDR_V6_LENGTH => Ok(PeerAddr::V6(PeerAddrV6(bytes.try_into().unwrap()))),
-            _ => Err(serde::de::Error::custom(format!(
-                "invalid peer address length: {}",
-                bytes.len()
-            ))),
-        }
+        PeerAddr::try_from(bytes.as_slice()).map_err(serde::de::Error::custom)
     }
 }
 
@@ -179,7 +272,7 @@ impl Serialize for PeerAddr {
     }
 }
 
-#[derive(PartialEq, Eq, Clone)]
+#[derive(PartialEq, Eq, Hash, Clone)]
 pub struct PeerAddrV4([u8; PEER_ADDR_V4_LENGTH]);
 
 impl ops::Deref for PeerAddrV4 {
@@ -189,7 +282,7 @@ impl ops::Deref for PeerAddrV4 {
     }
 }
 
-#[derive(PartialEq, Eq, Clone)]
+#[derive(PartialEq, Eq, Hash, Clone)]
 pub struct PeerAddrV6([u8; PEER_ADDR_V6_LENGTH]);
 
 impl ops::Deref for PeerAddrV6 {
@@ -200,13 +293,89 @@ impl ops::Deref for PeerAddrV6 {
 }
 
 impl TryFrom<&[u8]> for PeerAddr {
-    type Error = &'static str;
+    type Error = InvalidPeerAddrLength;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
         match value.len() {
             PEER_ADDR_V4_LENGTH => Ok(PeerAddr::V4(PeerAddrV4(value.try_into().unwrap()))),
             PEER_ADDR_V6_LENGTH => Ok(PeerAddr::V6(PeerAddrV6(value.try_into().unwrap()))),
-            _ => Err("invalid peer address length"),
+            len => Err(InvalidPeerAddrLength(len)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TSConfig;
+
+    #[test]
+    fn test_peer_addr_try_from() {
+        let addr = PeerAddr::try_from([127, 0, 0, 1, 0x1a, 0xe1].as_slice()).unwrap();
+        assert_eq!(addr.ip_type(), IpType::V4);
+        assert_eq!(addr.to_string(), "127.0.0.1:6881");
+
+        let addr = PeerAddr::try_from([0; PEER_ADDR_V6_LENGTH].as_slice()).unwrap();
+        assert_eq!(addr.ip_type(), IpType::V6);
+    }
+
+    #[test]
+    fn test_peer_addr_try_from_invalid_length() {
+        assert_eq!(
+            PeerAddr::try_from([0; 7].as_slice()),
+            Err(InvalidPeerAddrLength(7))
+        );
+
+        assert_eq!(
+            PeerAddr::try_from([0; 17].as_slice()),
+            Err(InvalidPeerAddrLength(17))
+        );
+    }
+
+    #[test]
+    fn test_peer_addr_from_socket_addr() {
+        let socket_addrs: [SocketAddr; 2] = [
+            "127.0.0.1:6881".parse().unwrap(),
+            "[2001:db8::1]:6881".parse().unwrap(),
+        ];
+
+        for socket_addr in socket_addrs {
+            let addr = PeerAddr::from(&socket_addr);
+            assert_eq!(addr.ip_type(), IpType::from(socket_addr.ip()));
+
+            let (ip, port): (IpAddr, Port) = (&addr).into();
+            assert_eq!(SocketAddr::new(ip, port.0), socket_addr);
+            assert!(PeerAddr::from(socket_addr) == addr);
+        }
+    }
+
+    #[test]
+    fn test_extreme_peer_idle_time_clamped() {
+        let mut config = TSConfig::new().unwrap();
+        config.tracker.peer_idle_time = std::time::Duration::MAX;
+
+        let query = b"info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=01234567890123456789&port=6881";
+        let req: AnnounceRequest = ts_utils::query::from_bytes(query).unwrap();
+
+        let peer: Peer = (&req, Ipv4Addr::LOCALHOST.into(), &config.tracker).into();
+        assert_eq!(peer.expire_at.as_secs(), MAX_EXPIRE_AT_SECS);
+
+        config.tracker.peer_idle_time = std::time::Duration::from_secs(60);
+        let peer: Peer = (&req, Ipv4Addr::LOCALHOST.into(), &config.tracker).into();
+        assert!(peer.expire_at > Clock::now_since_epoch());
+    }
+
+    #[test]
+    fn test_peer_type_string_forms() {
+        for (peer_type, name) in [
+            (PeerType::Leecher, "leecher"),
+            (PeerType::Seeder, "seeder"),
+            (PeerType::Partial, "partial"),
+        ] {
+            assert_eq!(peer_type.to_string(), name);
+
+            let encoded = ts_utils::bencode::encode(&peer_type).unwrap();
+            assert_eq!(encoded, format!("{}:{}", name.len(), name).as_bytes());
         }
     }
 }
@@ -2,9 +2,10 @@ use ahash::RandomState;
 use bytes::BytesMut;
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
+use ts_utils::time::Clock;
 
 use super::{
-    common::{InfoHash, PeerId, PEER_ID_LENGTH},
+    common::{InfoHash, PeerId, UnixEpochSecs, PEER_ID_LENGTH},
     peer::{Peer, PeerType},
 };
 
@@ -14,6 +15,9 @@ pub type PeerDict = IndexMap<PeerIdKey, Peer, RandomState>;
 /// A type alias for a list of peers represented as a `Vec`.
 pub type PeerList = Vec<(PeerIdKey, Peer)>;
 
+/// A list of the peers of a swarm along with the type of each.
+pub type SwarmPeers = Vec<(PeerIdKey, PeerType, Peer)>;
+
 /// Represents the swarm associated with a specific torrent in a BitTorrent tracker.
 /// The swarm contains lists of different types of peers, as well as information about the torrent's completion status.
 #[derive(Debug, Default)]
@@ -50,6 +54,8 @@ macro_rules! update_peer_fields {
 
         peer.addr = new_peer.addr;
         peer.expire_at = new_peer.expire_at;
+        peer.last_seen = new_peer.last_seen;
+        peer.crypto_flag = new_peer.crypto_flag;
     }};
 }
 
@@ -64,6 +70,11 @@ impl TorrentSwarm {
         (self.leechers.len() + self.partial_seeds.len()) as u32
     }
 
+    /// Returns the number of leechers in the swarm, excluding the partial seeds.
+    pub fn downloaders_count(&self) -> u32 {
+        self.leechers.len() as u32
+    }
+
     pub fn insert_peer(
         &mut self,
         key: PeerIdKey,
@@ -103,6 +114,8 @@ impl TorrentSwarm {
         }
     }
 
+    /// Moves a leecher to the seeders, inserting it as a seeder if it was not in the swarm.
+    /// Returns `true` if the peer completed the download, i.e. it was not already a seeder.
     pub fn promote_peer(&mut self, key: &PeerIdKey, peer: Peer) -> bool {
         if let Some(mut epeer) = self.remove_peer(key, PeerType::Leecher) {
             update_peer_fields!(&mut epeer, peer);
@@ -110,16 +123,57 @@ impl TorrentSwarm {
             return true;
         }
 
-        false
+        if let Some(epeer) = self.seeders.get_mut(key) {
+            update_peer_fields!(epeer, peer);
+            return false;
+        }
+
+        self.insert_peer(key.clone(), peer, PeerType::Seeder);
+        true
     }
 
+    /// Updates a peer, or inserts it if it was not in the swarm. A peer whose type changed
+    /// since its last announce, e.g. a leecher now announcing `left=0`, is moved over.
     pub fn update_or_insert_peer(&mut self, key: &PeerIdKey, peer: Peer, peer_type: PeerType) {
-        match self.get_mut_peer(&key, peer_type) {
-            Some(epeer) => update_peer_fields!(epeer, peer),
-            None => {
-                let _ = self.insert_peer(key.clone(), peer, peer_type);
+        if let Some(epeer) = self.peers_mut(peer_type).get_mut(key) {
+            update_peer_fields!(epeer, peer);
+            return;
+        }
+
+        let moved = [PeerType::Leecher, PeerType::Seeder, PeerType::Partial]
+            .into_iter()
+            .filter(|other| *other != peer_type)
+            .find_map(|other| self.peers_mut(other).remove(key));
+
+        let peer = match moved {
+            Some(mut epeer) => {
+                update_peer_fields!(&mut epeer, peer);
+                epeer
             }
+            None => peer,
         };
+
+        let _ = self.insert_peer(key.clone(), peer, peer_type);
+    }
+
+    fn peers_mut(&mut self, peer_type: PeerType) -> &mut PeerDict {
+        match peer_type {
+            PeerType::Leecher => &mut self.leechers,
+            PeerType::Seeder => &mut self.seeders,
+            PeerType::Partial => &mut self.partial_seeds,
+        }
+    }
+
+    /// Returns `true` if the swarm has no peers of any type.
+    pub fn is_empty(&self) -> bool {
+        self.seeders.is_empty() && self.leechers.is_empty() && self.partial_seeds.is_empty()
+    }
+
+    /// Releases the capacity of the peer maps left over by removed peers.
+    pub fn shrink_to_fit(&mut self) {
+        self.seeders.shrink_to_fit();
+        self.leechers.shrink_to_fit();
+        self.partial_seeds.shrink_to_fit();
     }
 
     pub fn remove_peer(&mut self, key: &PeerIdKey, peer_type: PeerType) -> Option<Peer> {
@@ -136,15 +190,30 @@ impl TorrentSwarm {
     }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Torrent {
     pub completed: u32,
+
+    /// When the torrent was registered, or `0` if unknown.
+    pub added_at: UnixEpochSecs,
+
+    /// The name of the torrent, if given on registration.
+    pub name: Option<String>,
 }
 
 impl Torrent {
     pub fn incr_completed(&mut self) {
         self.completed += 1;
     }
+
+    /// Sets `added_at` to the current time, unless already set.
+    pub fn registered(mut self) -> Self {
+        if self.added_at == 0 {
+            self.added_at = Clock::now_since_epoch().as_secs();
+        }
+
+        self
+    }
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -159,6 +228,14 @@ pub struct TorrentStats {
 
     /// The number of non-seeder peers.
     pub incomplete: u32,
+
+    /// The number of non-seeder peers that are not partial seeds, if known (BEP 21).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub downloaders: Option<u32>,
+
+    /// The counts of the IPv4 and IPv6 swarms, in that order, if known.
+    #[serde(skip)]
+    pub by_ip_type: Option<[SwarmStats; 2]>,
 }
 
 impl TorrentStats {
@@ -167,6 +244,8 @@ impl TorrentStats {
             completed,
             seeders: 0,
             incomplete: 0,
+            downloaders: None,
+            by_ip_type: None,
         }
     }
 }
@@ -203,6 +282,12 @@ impl std::cmp::PartialOrd<PeerIdKey> for PeerIdKey {
     }
 }
 
+impl From<Vec<u8>> for PeerIdKey {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
+
 impl AsRef<[u8]> for PeerIdKey {
     fn as_ref(&self) -> &[u8] {
         &self.0
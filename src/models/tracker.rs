@@ -1,20 +1,28 @@
 use super::{
-    common::{InfoHash, IntervalDuration, NumOfBytes, PeerId, PeerKey, Port},
+    common::{InfoHash, IntervalDuration, NumOfBytes, PeerId, PeerKey, Port, INFOHASH_LENGTH},
+    peer::{PEER_ADDR_V4_LENGTH, PEER_ADDR_V6_LENGTH},
     torrent::TorrentStats,
 };
 use crate::{constants, utils::Loggable, worker::TaskOutput};
-use serde::{ser::SerializeSeq, Deserialize, Serialize};
-use std::{fmt, net::IpAddr};
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::{
+    de::{self, MapAccess, Visitor},
+    ser::SerializeSeq,
+    Deserialize, Serialize,
+};
+use std::{collections::HashMap, fmt, net::IpAddr};
 use ts_utils::{
     bencode::{self, Bencode},
-    bencode_dict, bencode_int, bencode_str,
-    serde::deserialize_u8_to_bool,
-    MultiMap,
+    bencode_int, bencode_str, query,
+    serde::{deserialize_bounded_u32, deserialize_u8_to_bool},
 };
 
+/// The largest `numwant` accepted in an announce, the widest the UDP protocol can carry.
+pub const MAX_NUMWANT: u32 = i32::MAX as u32;
+
 /// Represents the event type for announcing a download status to a BitTorrent tracker.
 /// The `AnnounceEvent` enum is used to indicate the different states of the announcement.
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
 pub enum AnnounceEvent {
     /// An announcement using `started` is sent when a download first begins.
@@ -71,9 +79,9 @@ pub struct AnnounceRequest {
     /// The total amount downloaded (since the client sent the `started` event to the tracker).
     pub downloaded: NumOfBytes,
 
-    #[serde(default)]
     /// The number of bytes this peer still has to download.
-    pub left: NumOfBytes,
+    /// Only an explicit `0` marks the peer as a seeder, a client omitting it is a leecher.
+    pub left: Option<NumOfBytes>,
 
     #[serde(default = "default_compact")]
     #[serde(deserialize_with = "deserialize_u8_to_bool")]
@@ -90,14 +98,41 @@ pub struct AnnounceRequest {
     /// If not specified, then this request is one performed at regular intervals.
     pub event: Option<AnnounceEvent>,
 
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_numwant")]
     /// Number of peers that the client would like to receive from the tracker.
     pub numwant: Option<u32>,
 
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_u8_to_bool")]
+    /// Indicates that the client supports encrypted connections and accepts `crypto_flags`.
+    pub supportcrypto: bool,
+
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_u8_to_bool")]
+    /// Indicates that the client only accepts encrypted connections.
+    pub requirecrypto: bool,
+
     /// An additional identification that is not shared with any other peers.
     /// It is intended to allow a client to prove their identity should their IP address change.
     /// The key should remain the same for a particular infohash during a torrent session.
     /// https://www.bittorrent.org/beps/bep_0007.html
     pub key: PeerKey,
+
+    /// The `tracker id` received in a previous announce response, if any.
+    pub trackerid: Option<String>,
+
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_ip")]
+    /// The IP address the client declared, if it is one. Only used if `ip_param_mode` allows.
+    pub ip: Option<IpAddr>,
+}
+
+impl AnnounceRequest {
+    /// Returns `true` if the client asked for `crypto_flags` in a compact response.
+    pub fn wants_crypto_flags(&self) -> bool {
+        self.compact && (self.supportcrypto || self.requirecrypto)
+    }
 }
 
 impl Loggable for AnnounceRequest {
@@ -114,13 +149,33 @@ fn default_no_peer_id() -> bool {
     true
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+fn deserialize_numwant<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    deserialize_bounded_u32(deserializer, MAX_NUMWANT).map(Some)
+}
+
+/// Ignores an `ip` that is not an IP address, such as a DNS name.
+fn deserialize_ip<'de, D>(deserializer: D) -> Result<Option<IpAddr>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    let ip: Option<String> = Option::deserialize(deserializer)?;
+    Ok(ip.and_then(|ip| ip.parse().ok()))
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
 /// Represents the response sent by tracker for `AnnounceRequest`.
 pub struct AnnounceResponse {
     /// The number of peers with the entire file, aka "seeders".
     #[serde(rename = "complete")]
     pub complete: u32,
 
+    /// One byte per peer in the compact `peers` list, then in `peers6`, set to `1` if that peer
+    /// requires encrypted connections. Only present if the client sent `supportcrypto`.
+    pub crypto_flags: Option<Bytes>,
+
     /// The number of non-seeder peers, aka "leechers".
     #[serde(rename = "incomplete")]
     pub incomplete: u32,
@@ -140,12 +195,29 @@ pub struct AnnounceResponse {
     /// It is optional and may be absent if there are no IPv6 peers in the response.
     pub peers6: Option<ResponsePeerList>,
 
+    #[serde(rename = "tracker id")]
+    /// A string that the client should send back on its next announcements.
+    pub tracker_id: Option<String>,
+
     #[serde(rename = "warning message")]
     /// Similar to failure reason, but the response still gets processed normally.
     /// The warning message is shown just like an error.
     pub warning_message: Option<String>,
 }
 
+impl AnnounceResponse {
+    /// Returns the number of peers in the `peers` and `peers6` lists.
+    pub fn peer_count(&self) -> usize {
+        let count = |peers: &Option<ResponsePeerList>, addr_length: usize| match peers {
+            Some(ResponsePeerList::Compact(peers)) => peers.len() / addr_length,
+            Some(ResponsePeerList::NonCompact(peers)) => peers.len(),
+            None => 0,
+        };
+
+        count(&self.peers, PEER_ADDR_V4_LENGTH) + count(&self.peers6, PEER_ADDR_V6_LENGTH)
+    }
+}
+
 impl Loggable for AnnounceResponse {
     fn log(&self) -> String {
         format!("announce response: {:?}", self)
@@ -163,70 +235,181 @@ impl Bencode for AnnounceResponse {
 }
 
 /// Represents a scrape request sent to a BitTorrent tracker.
-#[derive(Debug, Deserialize, Default)]
-#[serde(from = "ScrapeRequestMap")]
+#[derive(Debug, Default)]
 pub struct ScrapeRequest {
+    /// The infohashes to scrape. An empty list requests a full scrape.
     pub info_hashes: Vec<InfoHash>,
+
+    /// The output format of a full scrape. Falls back to the configured default if absent.
+    pub format: Option<FullScrapeFormat>,
 }
 
-type ScrapeRequestMap<'a> = MultiMap<&'a str, InfoHash, ahash::RandomState>;
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "snake_case")]
+enum ScrapeRequestField {
+    InfoHash,
+    Format,
+    #[serde(other)]
+    Unknown,
+}
+
+struct ScrapeRequestVisitor;
 
-impl<'a> From<ScrapeRequestMap<'a>> for ScrapeRequest {
-    fn from(mut map: ScrapeRequestMap<'a>) -> Self {
-        match map.remove("info_hash") {
-            Some(vec) => Self { info_hashes: vec },
-            None => Self::default(),
+impl<'de> Visitor<'de> for ScrapeRequestVisitor {
+    type Value = ScrapeRequest;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a scrape request")
+    }
+
+    fn visit_map<M>(self, mut map: M) -> Result<Self::Value, M::Error>
+    where
+        M: MapAccess<'de>,
+    {
+        let mut req = ScrapeRequest::default();
+        while let Some(field) = map.next_key()? {
+            match field {
+                ScrapeRequestField::InfoHash => req.info_hashes.push(map.next_value()?),
+                ScrapeRequestField::Format => req.format = Some(map.next_value()?),
+                ScrapeRequestField::Unknown => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
         }
+
+        Ok(req)
+    }
+}
+
+impl<'de> Deserialize<'de> for ScrapeRequest {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_map(ScrapeRequestVisitor)
     }
 }
 
+/// Parses the query string of an announce. Queries come straight from clients, so this has to
+/// fail rather than panic on any input; it is the entry point of the `query` fuzz target.
+pub fn parse_announce(query: &[u8]) -> Result<AnnounceRequest, query::Error> {
+    query::from_bytes(query)
+}
+
+/// Parses the query string of a scrape, like `parse_announce`.
+pub fn parse_scrape(query: &[u8]) -> Result<ScrapeRequest, query::Error> {
+    query::from_bytes(query)
+}
+
 /// Represents the response sent by tracker for `ScrapeRequest`.
+///
+/// The files are bencoded sorted by infohash bytes, unless kept in request order.
 #[derive(Debug, Default, Serialize)]
 pub struct ScrapeResponse {
     pub files: Vec<(InfoHash, TorrentStats)>,
+
+    /// Whether the files are bencoded in their order in `files`, i.e. request order.
+    #[serde(skip)]
+    pub request_order: bool,
 }
 
 impl ScrapeResponse {
     pub fn new(files: Vec<(InfoHash, TorrentStats)>) -> Self {
-        Self { files }
+        Self {
+            files,
+            request_order: false,
+        }
     }
-}
 
-impl Bencode for ScrapeResponse {
-    fn bencode(&self) -> bencode::Result<bytes::Bytes> {
-        let files = &self.files;
-        let capacity = 100 * files.len();
-        let mut serializer = bencode::Serializer::with_capacity(capacity);
+    pub fn with_request_order(mut self, request_order: bool) -> Self {
+        self.request_order = request_order;
+        self
+    }
+
+    fn bencode_into(&self, serializer: &mut bencode::Serializer) {
+        // The dictionary of files is written directly, so its keys must be sorted here.
+        let mut files: Vec<_> = self.files.iter().collect();
+        if !self.request_order {
+            files.sort_unstable_by(|(a, _), (b, _)| a.as_ref().cmp(b.as_ref()));
+        }
 
         serializer.start_dict();
         bencode_str!(serializer, constants::TRACKER_RESPONSE_FILES);
         serializer.start_dict();
 
         for (info_hash, stats) in files {
-            bencode_file(&mut serializer, info_hash, stats);
+            bencode_file(serializer, info_hash, stats, false);
         }
 
         serializer.end_dict();
         serializer.end_dict();
+    }
+}
 
+impl Bencode for ScrapeResponse {
+    fn capacity(&self) -> usize {
+        50 * self.files.len()
+    }
+
+    fn bencode(&self) -> bencode::Result<bytes::Bytes> {
+        let mut serializer = bencode::Serializer::with_capacity(self.capacity() * 2);
+        self.bencode_into(&mut serializer);
         Ok(serializer.finalize())
     }
+
+    fn bencode_pooled(&self) -> bencode::Result<bytes::Bytes> {
+        let mut serializer = bencode::Serializer::pooled(self.capacity() * 2);
+        self.bencode_into(&mut serializer);
+        Ok(serializer.finalize_pooled())
+    }
 }
 
-fn bencode_file(serializer: &mut bencode::Serializer, info_hash: &InfoHash, stats: &TorrentStats) {
+fn bencode_file(
+    serializer: &mut bencode::Serializer,
+    info_hash: &InfoHash,
+    stats: &TorrentStats,
+    split_ip_types: bool,
+) {
     serializer.encode_bytes(info_hash.as_ref());
-    bencode_dict!(
-        serializer,
-        constants::TRACKER_RESPONSE_COMPLETE => bencode_int!(serializer, stats.seeders),
-        constants::TRACKER_RESPONSE_DOWNLOADED => bencode_int!(serializer, stats.completed),
-        constants::TRACKER_RESPONSE_INCOMPLETE => bencode_int!(serializer, stats.incomplete)
-    );
+    serializer.start_dict();
+    bencode_str!(serializer, constants::TRACKER_RESPONSE_COMPLETE);
+    bencode_int!(serializer, stats.seeders);
+    bencode_str!(serializer, constants::TRACKER_RESPONSE_DOWNLOADED);
+    bencode_int!(serializer, stats.completed);
+
+    if let Some(downloaders) = stats.downloaders {
+        bencode_str!(serializer, constants::TRACKER_RESPONSE_DOWNLOADERS);
+        bencode_int!(serializer, downloaders);
+    }
+
+    bencode_str!(serializer, constants::TRACKER_RESPONSE_INCOMPLETE);
+    bencode_int!(serializer, stats.incomplete);
+
+    if let (true, Some([v4, v6])) = (split_ip_types, &stats.by_ip_type) {
+        for (key, stats) in [
+            (constants::TRACKER_RESPONSE_IPV4, v4),
+            (constants::TRACKER_RESPONSE_IPV6, v6),
+        ] {
+            bencode_str!(serializer, key);
+            serializer.start_dict();
+            bencode_str!(serializer, constants::TRACKER_RESPONSE_COMPLETE);
+            bencode_int!(serializer, stats.complete);
+            bencode_str!(serializer, constants::TRACKER_RESPONSE_INCOMPLETE);
+            bencode_int!(serializer, stats.incomplete);
+            serializer.end_dict();
+        }
+    }
+
+    serializer.end_dict();
 }
 
 /// Represents a full scrape response for a BitTorrent tracker.
 #[derive(Debug)]
 pub struct FullScrapeResponse {
     pub ser: Option<bencode::Serializer>,
+
+    /// Whether each file also holds the counts of each IP family, see `FullScrapeFormat::Split`.
+    pub split_ip_types: bool,
 }
 
 impl FullScrapeResponse {
@@ -236,7 +419,17 @@ impl FullScrapeResponse {
         bencode_str!(ser, constants::TRACKER_RESPONSE_FILES);
         ser.start_dict();
 
-        Self { ser: Some(ser) }
+        Self {
+            ser: Some(ser),
+            split_ip_types: false,
+        }
+    }
+
+    pub fn with_split_ip_types() -> Self {
+        Self {
+            split_ip_types: true,
+            ..Self::new()
+        }
     }
 
     pub fn output(&mut self) -> Option<bytes::Bytes> {
@@ -256,7 +449,60 @@ impl FullScrapeResponse {
     {
         if let Some(ref mut serializer) = self.ser {
             for (info_hash, stats) in files {
-                bencode_file(serializer, info_hash, stats);
+                bencode_file(serializer, info_hash, stats, self.split_ip_types);
+            }
+        }
+    }
+}
+
+/// The output format of a full scrape.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FullScrapeFormat {
+    /// A bencoded `files` dictionary, as for a regular scrape.
+    #[default]
+    Bencode,
+
+    /// A bencoded `files` dictionary whose files also hold the `complete` and `incomplete`
+    /// counts of each IP family, under `ipv4` and `ipv6`. Meant for diagnostics.
+    Split,
+
+    /// A stream of fixed size entries, see `BINARY_FULL_SCRAPE_ENTRY_LENGTH`.
+    Binary,
+}
+
+/// The length of a single torrent entry in a binary full scrape: the infohash followed by
+/// the `complete`, `downloaded` and `incomplete` counts as big-endian `u32` values.
+pub const BINARY_FULL_SCRAPE_ENTRY_LENGTH: usize = INFOHASH_LENGTH + 3 * 4;
+
+/// Represents a full scrape response encoded in the compact binary format.
+#[derive(Debug)]
+pub struct BinaryFullScrapeResponse {
+    pub buf: Option<BytesMut>,
+}
+
+impl BinaryFullScrapeResponse {
+    pub fn new() -> Self {
+        Self {
+            buf: Some(BytesMut::new()),
+        }
+    }
+
+    pub fn output(&mut self) -> Option<bytes::Bytes> {
+        self.buf.take().map(BytesMut::freeze)
+    }
+
+    pub fn encode<'a, T>(&mut self, files: T)
+    where
+        T: Iterator<Item = &'a (InfoHash, TorrentStats)>,
+    {
+        if let Some(ref mut buf) = self.buf {
+            for (info_hash, stats) in files {
+                buf.reserve(BINARY_FULL_SCRAPE_ENTRY_LENGTH);
+                buf.extend_from_slice(info_hash.as_ref());
+                buf.put_u32(stats.seeders);
+                buf.put_u32(stats.completed);
+                buf.put_u32(stats.incomplete);
             }
         }
     }
@@ -280,6 +526,40 @@ impl Bencode for TrackerError {
     }
 }
 
+/// The tracker errors answered on hot rejection paths, serialized once rather than per request.
+const STATIC_TRACKER_ERRORS: [&str; 14] = [
+    constants::TRACKER_ERROR_NOT_FOUND_TORRENT,
+    constants::TRACKER_ERROR_UNREGISTERED_TORRENT_PASS,
+    constants::TRACKER_ERROR_UNREGISTERED_TORRENT,
+    constants::TRACKER_ERROR_BLOCKED_INFOHASH,
+    constants::TRACKER_ERROR_BLOCKED_CLIENT,
+    constants::TRACKER_ERROR_BLOCKED_IP,
+    constants::TRACKER_ERROR_HTTP_SCRAPE_NOT_ALLOWED,
+    constants::TRACKER_ERROR_HTTP_ANNOUNCE_NOT_ALLOWED,
+    constants::TRACKER_ERROR_FULL_SCRAPE_NOT_ALLOWED,
+    constants::TRACKER_ERROR_TOO_MANY_INFOHASHES,
+    constants::TRACKER_ERROR_MISSING_FORWARDED_IP,
+    constants::TRACKER_ERROR_PEER_LIST_NOT_SUPPORTED,
+    constants::TRACKER_ERROR_MISSING_KEY,
+    constants::TRACKER_ERROR_IP_FAMILY_MISMATCH,
+];
+
+lazy_static! {
+    static ref STATIC_TRACKER_ERROR_BYTES: HashMap<&'static str, Bytes> = STATIC_TRACKER_ERRORS
+        .into_iter()
+        .filter_map(|reason| Some((reason, TrackerError::from(reason).bencode().ok()?)))
+        .collect();
+}
+
+impl TrackerError {
+    /// Returns the serialized error if it is one of the static tracker errors.
+    pub fn static_bytes(&self) -> Option<Bytes> {
+        STATIC_TRACKER_ERROR_BYTES
+            .get(self.failure_reason.as_str())
+            .cloned()
+    }
+}
+
 impl From<&str> for TrackerError {
     fn from(err: &str) -> Self {
         err.to_string().into()
@@ -330,7 +610,7 @@ impl From<TaskOutput> for ScrapeResponse {
 ///     port: Port(6881),
 /// };
 /// ```
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct NonCompactPeer {
     /// The peer's IP address.
     pub ip: IpAddr,
@@ -364,7 +644,7 @@ pub struct NonCompactPeer {
 ///     },
 /// ]);
 /// ```
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub enum ResponsePeerList {
     /// A compact list of peer information represented as byte vectors.
     Compact(Vec<u8>),
@@ -436,3 +716,323 @@ macro_rules! try_into_bytes {
 try_into_bytes!(AnnounceResponse);
 try_into_bytes!(ScrapeResponse);
 try_into_bytes!(TrackerError);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{common::MAX_NUM_OF_BYTES, torrent::TorrentStatsList};
+
+    const INFOHASH_A: &str = "2a7b9e1f5c8d3a6b0f2e4c5a9b7d1e3a6c8b5d99";
+    const INFOHASH_B: &str = "3b8c2d0e6f9a4b7c1d4e5f6a2b8c3d9e4f5a6b7c";
+
+    fn create_files() -> TorrentStatsList {
+        vec![
+            (
+                INFOHASH_A.parse().unwrap(),
+                TorrentStats {
+                    seeders: 1,
+                    completed: 2,
+                    incomplete: 3,
+                    downloaders: None,
+                    by_ip_type: None,
+                },
+            ),
+            (
+                INFOHASH_B.parse().unwrap(),
+                TorrentStats {
+                    seeders: 4,
+                    completed: 5,
+                    incomplete: 6,
+                    downloaders: None,
+                    by_ip_type: None,
+                },
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_full_scrape_bencode_format() {
+        let files = create_files();
+        let mut response = FullScrapeResponse::new();
+        response.bencode(files.iter());
+
+        let mut expected = b"d5:filesd20:".to_vec();
+        expected.extend_from_slice(files[0].0.as_ref());
+        expected.extend_from_slice(b"d8:completei1e10:downloadedi2e10:incompletei3ee20:");
+        expected.extend_from_slice(files[1].0.as_ref());
+        expected.extend_from_slice(b"d8:completei4e10:downloadedi5e10:incompletei6eeee");
+
+        assert_eq!(response.output().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_scrape_response_sorted() {
+        let mut files = create_files();
+        files.reverse();
+
+        let bytes = ScrapeResponse::new(files.clone()).bencode().unwrap();
+
+        let mut expected = b"d5:filesd20:".to_vec();
+        expected.extend_from_slice(files[1].0.as_ref());
+        expected.extend_from_slice(b"d8:completei1e10:downloadedi2e10:incompletei3ee20:");
+        expected.extend_from_slice(files[0].0.as_ref());
+        expected.extend_from_slice(b"d8:completei4e10:downloadedi5e10:incompletei6eeee");
+
+        assert_eq!(bytes, expected);
+        assert_eq!(
+            ScrapeResponse::new(files).bencode_pooled().unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_scrape_file_keys_bep_48() {
+        // Decodes the `key => int` pairs of the dictionary at the start of `bytes`.
+        fn decode_int_dict(mut bytes: &[u8]) -> Vec<(String, u32)> {
+            let mut pairs = Vec::new();
+            bytes = bytes.strip_prefix(b"d").unwrap();
+
+            while bytes[0] != b'e' {
+                let colon = bytes.iter().position(|b| *b == b':').unwrap();
+                let len: usize = std::str::from_utf8(&bytes[..colon])
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                let key = String::from_utf8(bytes[colon + 1..colon + 1 + len].to_vec()).unwrap();
+                bytes = bytes[colon + 1 + len..].strip_prefix(b"i").unwrap();
+
+                let end = bytes.iter().position(|b| *b == b'e').unwrap();
+                let value = std::str::from_utf8(&bytes[..end]).unwrap().parse().unwrap();
+                bytes = &bytes[end + 1..];
+
+                pairs.push((key, value));
+            }
+
+            pairs
+        }
+
+        let mut files = create_files();
+        files.truncate(1);
+
+        for downloaders in [None, Some(7)] {
+            files[0].1.downloaders = downloaders;
+            let bytes = ScrapeResponse::new(files.clone()).bencode().unwrap();
+
+            let prefix = [b"d5:filesd20:".as_slice(), files[0].0.as_ref()].concat();
+            let pairs = decode_int_dict(bytes.strip_prefix(prefix.as_slice()).unwrap());
+            let keys: Vec<_> = pairs.iter().map(|(key, _)| key.as_str()).collect();
+
+            let mut sorted = keys.clone();
+            sorted.sort_unstable();
+            assert_eq!(keys, sorted);
+
+            let mut expected = vec![("complete", 1), ("downloaded", 2), ("incomplete", 3)];
+            if let Some(downloaders) = downloaders {
+                expected.insert(2, ("downloaders", downloaders));
+            }
+
+            let expected: Vec<_> = expected
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect();
+            assert_eq!(pairs, expected);
+        }
+    }
+
+    #[test]
+    fn test_scrape_response_request_order() {
+        let mut files = create_files();
+        files.reverse();
+
+        let response = ScrapeResponse::new(files.clone()).with_request_order(true);
+
+        let mut expected = b"d5:filesd20:".to_vec();
+        expected.extend_from_slice(files[0].0.as_ref());
+        expected.extend_from_slice(b"d8:completei4e10:downloadedi5e10:incompletei6ee20:");
+        expected.extend_from_slice(files[1].0.as_ref());
+        expected.extend_from_slice(b"d8:completei1e10:downloadedi2e10:incompletei3eeee");
+
+        assert_eq!(response.bencode().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_announce_response_sorted() {
+        let response = AnnounceResponse {
+            complete: 1,
+            crypto_flags: Some(Bytes::from_static(&[0, 1])),
+            incomplete: 2,
+            interval: 1800,
+            min_interval: 900,
+            peers: Some(ResponsePeerList::Compact(vec![0; 12])),
+            peers6: Some(ResponsePeerList::Compact(vec![0; 36])),
+            tracker_id: Some("ts".to_string()),
+            warning_message: Some("warning".to_string()),
+        };
+
+        // The unsorted path must match the output of the sorting serializer.
+        let sorted = bencode::encode(&response).unwrap();
+        assert_eq!(response.bencode().unwrap(), sorted);
+    }
+
+    #[test]
+    fn test_full_scrape_binary_format() {
+        let files = create_files();
+        let mut response = BinaryFullScrapeResponse::new();
+        response.encode(files.iter());
+
+        let output = response.output().unwrap();
+        assert_eq!(output.len(), files.len() * BINARY_FULL_SCRAPE_ENTRY_LENGTH);
+
+        for (entry, (info_hash, stats)) in output
+            .chunks(BINARY_FULL_SCRAPE_ENTRY_LENGTH)
+            .zip(files.iter())
+        {
+            let (hash, counts) = entry.split_at(INFOHASH_LENGTH);
+            let counts: Vec<u32> = counts
+                .chunks(4)
+                .map(|c| u32::from_be_bytes(c.try_into().unwrap()))
+                .collect();
+
+            assert_eq!(hash, info_hash.as_ref());
+            assert_eq!(
+                counts,
+                vec![stats.seeders, stats.completed, stats.incomplete]
+            );
+        }
+    }
+
+    #[test]
+    fn test_announce_request_crypto() {
+        let query = "info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=01234567890123456789&port=6881";
+
+        let req: AnnounceRequest = query::from_bytes(query.as_bytes()).unwrap();
+        assert!(!req.supportcrypto);
+        assert!(!req.requirecrypto);
+        assert!(!req.wants_crypto_flags());
+
+        let query = format!("{}&supportcrypto=1", query);
+        let req: AnnounceRequest = query::from_bytes(query.as_bytes()).unwrap();
+        assert!(req.supportcrypto);
+        assert!(!req.requirecrypto);
+        assert!(req.wants_crypto_flags());
+
+        let query = format!("{}&compact=0", query);
+        let req: AnnounceRequest = query::from_bytes(query.as_bytes()).unwrap();
+        assert!(!req.wants_crypto_flags());
+    }
+
+    #[test]
+    fn test_announce_request_bounded_numbers() {
+        let query = "info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=01234567890123456789&port=6881";
+        let parse = |params: String| {
+            let query = format!("{}&{}", query, params);
+            query::from_bytes::<AnnounceRequest>(query.as_bytes())
+        };
+
+        let req = parse("uploaded=10&downloaded=20&left=30&numwant=40".to_string()).unwrap();
+        assert_eq!(req.uploaded, NumOfBytes(10));
+        assert_eq!(req.downloaded, NumOfBytes(20));
+        assert_eq!(req.left, Some(NumOfBytes(30)));
+        assert_eq!(req.numwant, Some(40));
+
+        for field in ["uploaded", "downloaded", "left"] {
+            let req = parse(format!("{}={}", field, MAX_NUM_OF_BYTES)).unwrap();
+            let value = [req.uploaded, req.downloaded, req.left.unwrap_or_default()];
+            assert!(value.contains(&NumOfBytes(MAX_NUM_OF_BYTES)));
+
+            assert!(parse(format!("{}={}", field, MAX_NUM_OF_BYTES + 1)).is_err());
+            assert!(parse(format!("{}=18446744073709551616", field)).is_err());
+        }
+
+        let req = parse(format!("numwant={}", MAX_NUMWANT)).unwrap();
+        assert_eq!(req.numwant, Some(MAX_NUMWANT));
+        assert!(parse(format!("numwant={}", MAX_NUMWANT as u64 + 1)).is_err());
+    }
+
+    #[test]
+    fn test_parse_malformed_queries() {
+        let queries: [&[u8]; 12] = [
+            b"",
+            b"&&&===&=",
+            b"%",
+            b"%zz%f",
+            b"info_hash=%ff%fe%fd&peer_id=%",
+            b"\xff\xfe=\x80&\xc3=%c3",
+            b"info_hash=aaaaaaaaaaaaaaaaaaaa&info_hash=aaaaaaaaaaaaaaaaaaaa",
+            b"port=99999999999999999999999&numwant=-1&left=+1",
+            b"event=%00&compact=256&key=%ff%ff%ff%ff",
+            b"info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=01234567890123456789&port=6881&key=",
+            b"format=&info_hash=&format=binary",
+            b"++=++&%2B=%2b",
+        ];
+
+        for query in queries {
+            let _ = parse_announce(query);
+            let _ = parse_scrape(query);
+        }
+
+        assert!(parse_announce(b"port=6881").is_err());
+        assert_eq!(parse_scrape(b"").unwrap().info_hashes, vec![]);
+    }
+
+    #[test]
+    fn test_announce_response_tracker_id() {
+        let response = AnnounceResponse {
+            complete: 1,
+            incomplete: 2,
+            interval: 1800,
+            min_interval: 900,
+            tracker_id: Some("ts".to_string()),
+            ..Default::default()
+        };
+
+        let bytes: Bytes = response.try_into().unwrap();
+        assert_eq!(
+            bytes,
+            b"d8:completei1e10:incompletei2e8:intervali1800e12:min intervali900e10:tracker id2:tse"
+                .as_slice()
+        );
+    }
+
+    #[test]
+    fn test_scrape_request_format() {
+        let req: ScrapeRequest = query::from_bytes(b"format=binary").unwrap();
+        assert!(req.info_hashes.is_empty());
+        assert_eq!(req.format, Some(FullScrapeFormat::Binary));
+
+        let req: ScrapeRequest = query::from_bytes(b"").unwrap();
+        assert_eq!(req.format, None);
+
+        assert!(query::from_bytes::<ScrapeRequest>(b"format=xml").is_err());
+    }
+
+    #[test]
+    fn test_scrape_request_info_hashes() {
+        let req: ScrapeRequest = query::from_bytes(b"").unwrap();
+        assert!(req.info_hashes.is_empty());
+
+        let req: ScrapeRequest = query::from_bytes(b"info_hash=aaaaaaaaaaaaaaaaaaaa").unwrap();
+        assert_eq!(req.info_hashes, vec![InfoHash([b'a'; INFOHASH_LENGTH])]);
+
+        let req: ScrapeRequest =
+            query::from_bytes(b"info_hash=aaaaaaaaaaaaaaaaaaaa&info_hash=bbbbbbbbbbbbbbbbbbbb")
+                .unwrap();
+        assert_eq!(
+            req.info_hashes,
+            vec![
+                InfoHash([b'a'; INFOHASH_LENGTH]),
+                InfoHash([b'b'; INFOHASH_LENGTH])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tracker_error_static_bytes() {
+        let err = TrackerError::from(constants::TRACKER_ERROR_BLOCKED_INFOHASH);
+        assert_eq!(err.static_bytes(), Some(err.bencode().unwrap()));
+        let expected = b"d14:failure reason16:blocked infohashe";
+        assert_eq!(err.static_bytes().unwrap(), expected.as_slice());
+
+        assert_eq!(TrackerError::from("a dynamic error").static_bytes(), None);
+    }
+}
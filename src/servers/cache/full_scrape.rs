@@ -7,8 +7,12 @@ use ts_utils::{
 
 use super::Cache;
 use crate::{
-    models::{torrent::TorrentStatsList, tracker::FullScrapeResponse},
+    models::{
+        torrent::TorrentStatsList,
+        tracker::{BinaryFullScrapeResponse, FullScrapeFormat, FullScrapeResponse},
+    },
     storage::Processor,
+    utils::metrics::METRICS,
     worker::{FullScrapeProcessor, Task, TaskOutput, Worker},
 };
 
@@ -33,9 +37,15 @@ impl FullScrapeCache {
 /// * `cache` - An `Arc` reference to the cache to be refreshed.
 /// * `worker` - An `Arc` reference to the worker responsible for refreshing the cache.
 /// * `expires_in` - The new validity duration to apply after the refresh operation.
-pub async fn refresh(cache: Arc<Cache>, worker: Arc<Worker>, expires_in: Duration) {
+/// * `format` - The output format of the full scrape to refresh.
+pub async fn refresh(
+    cache: Arc<Cache>,
+    worker: Arc<Worker>,
+    expires_in: Duration,
+    format: FullScrapeFormat,
+) {
     let should_refresh = {
-        let mut cache = cache.full_scrape.write().await;
+        let mut cache = cache.full_scrape(format).write().await;
         match cache.as_ref() {
             Some(_) if cache.is_expired() && !cache.is_refreshing() => {
                 cache.set_refreshing();
@@ -53,13 +63,25 @@ pub async fn refresh(cache: Arc<Cache>, worker: Arc<Worker>, expires_in: Duratio
         return;
     }
 
-    let task = Task::FullScrape(Box::new(FullScrapeResponse::new()));
+    let task = match format {
+        FullScrapeFormat::Bencode => Task::FullScrape(Box::new(FullScrapeResponse::new())),
+        FullScrapeFormat::Binary => Task::FullScrape(Box::new(BinaryFullScrapeResponse::new())),
+        FullScrapeFormat::Split => {
+            Task::FullScrape(Box::new(FullScrapeResponse::with_split_ip_types()))
+        }
+    };
+
+    // Other formats may be refreshing, each holding a permit until its entry is set.
+    let permit = cache.full_scrape_refreshes.acquire().await;
     let data = match worker.work(task).await {
         Ok(TaskOutput::FullScrape(mut handler)) => handler.output().unwrap_or_default(),
         _ => Bytes::new(),
     };
+    drop(permit);
+
+    METRICS.full_scrape_response_size.observe(data.len());
 
-    let mut cache = cache.full_scrape.write().await;
+    let mut cache = cache.full_scrape(format).write().await;
 
     cache.set(
         FullScrapeCache::new(data),
@@ -96,3 +118,66 @@ impl Processor<TorrentStatsList> for FullScrapeResponse {
         return true;
     }
 }
+
+impl FullScrapeProcessor for BinaryFullScrapeResponse {
+    fn as_processor(&mut self) -> &mut dyn Processor<TorrentStatsList> {
+        self
+    }
+
+    fn output(&mut self) -> Option<Bytes> {
+        self.output()
+    }
+}
+
+impl Processor<TorrentStatsList> for BinaryFullScrapeResponse {
+    fn process(&mut self, input: &TorrentStatsList) -> bool {
+        self.encode(input.iter());
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TSConfig;
+    use tokio::time::sleep;
+
+    #[tokio::test]
+    async fn test_concurrent_refreshes() {
+        let config = Arc::new(TSConfig::new().unwrap());
+        let mut worker = Worker::new(config);
+        let _worker_job = worker.start();
+        let worker = Arc::new(worker);
+
+        let cache = Arc::new(Cache::new(1));
+        let permit = cache.full_scrape_refreshes.acquire().await.unwrap();
+
+        let formats = [FullScrapeFormat::Bencode, FullScrapeFormat::Binary];
+        let refreshes = formats
+            .iter()
+            .cycle()
+            .take(10)
+            .map(|format| {
+                let expires_in = Duration::from_secs(60);
+                tokio::spawn(refresh(cache.clone(), worker.clone(), expires_in, *format))
+            })
+            .collect::<Vec<_>>();
+
+        // No refresh runs while another one holds the only permit.
+        sleep(std::time::Duration::from_millis(50)).await;
+        for format in formats {
+            let entry = cache.full_scrape(format).read().await;
+            assert!(entry.is_none() && entry.is_refreshing());
+        }
+
+        drop(permit);
+        for refresh in refreshes {
+            refresh.await.unwrap();
+        }
+
+        for format in formats {
+            let entry = cache.full_scrape(format).read().await;
+            assert!(entry.is_some() && !entry.is_refreshing());
+        }
+    }
+}
@@ -1,20 +1,42 @@
 pub mod full_scrape;
 
 use self::full_scrape::FullScrapeCache;
+use crate::models::tracker::FullScrapeFormat;
 use std::sync::atomic::{AtomicBool, Ordering};
-use tokio::sync::RwLock;
+use tokio::sync::{RwLock, Semaphore};
 use ts_utils::time::Instant;
 
 /// A cache for storing various types of data, such as full scrape responses.
 pub struct Cache {
-    /// Cached data for full scrape responses, protected by a read-write lock.
-    pub full_scrape: RwLock<CacheEntry<FullScrapeCache>>,
+    /// Cached data for bencoded full scrape responses, protected by a read-write lock.
+    full_scrape_bencode: RwLock<CacheEntry<FullScrapeCache>>,
+
+    /// Cached data for binary full scrape responses, protected by a read-write lock.
+    full_scrape_binary: RwLock<CacheEntry<FullScrapeCache>>,
+
+    /// Cached data for split full scrape responses, protected by a read-write lock.
+    full_scrape_split: RwLock<CacheEntry<FullScrapeCache>>,
+
+    /// Limits the full scrapes refreshed at a time, whatever their format.
+    full_scrape_refreshes: Semaphore,
 }
 
 impl Cache {
-    pub fn new() -> Cache {
+    pub fn new(max_full_scrape_refreshes: usize) -> Cache {
         Cache {
-            full_scrape: RwLock::new(CacheEntry::default()),
+            full_scrape_bencode: RwLock::new(CacheEntry::default()),
+            full_scrape_binary: RwLock::new(CacheEntry::default()),
+            full_scrape_split: RwLock::new(CacheEntry::default()),
+            full_scrape_refreshes: Semaphore::new(max_full_scrape_refreshes),
+        }
+    }
+
+    /// Returns the cached full scrape response for the given output format.
+    pub fn full_scrape(&self, format: FullScrapeFormat) -> &RwLock<CacheEntry<FullScrapeCache>> {
+        match format {
+            FullScrapeFormat::Bencode => &self.full_scrape_bencode,
+            FullScrapeFormat::Binary => &self.full_scrape_binary,
+            FullScrapeFormat::Split => &self.full_scrape_split,
         }
     }
 }
@@ -0,0 +1,165 @@
+//! Handlers for the tracker API, authenticated with the configured `api_key`.
+
+use super::error::{HttpError, Result};
+use super::request::HttpRequest;
+use super::response::HttpResponse;
+use crate::constants;
+use crate::models::{common::InfoHash, torrent::SwarmPeers};
+use crate::servers::State;
+use crate::utils::metrics::METRICS;
+use crate::worker::Task;
+
+use http_body_util::{BodyExt, LengthLimitError, Limited};
+use hyper::body::Incoming as IncomingBody;
+use std::fmt::Write;
+use ts_utils::{cmp, hex};
+
+/// The most peers listed for a torrent, so that a huge swarm can't stall the tracker.
+const MAX_LISTED_PEERS: usize = 1000;
+
+/// The largest bulk import body, about 100k infohashes, so that a request can't exhaust memory.
+const MAX_BULK_BODY_SIZE: usize = 4 * 1024 * 1024;
+
+/// Registers the torrents listed in the request body, one hex encoded infohash per line.
+/// Responds with the number of torrents imported.
+pub(super) async fn insert_torrents(
+    req: HttpRequest<IncomingBody>,
+    state: State,
+) -> Result<HttpResponse> {
+    authorize(&req, &state)?;
+
+    let body = Limited::new(req.0.into_body(), MAX_BULK_BODY_SIZE)
+        .collect()
+        .await
+        .map_err(|err| match err.is::<LengthLimitError>() {
+            true => HttpError::PayloadTooLarge,
+            false => HttpError::Other(err),
+        })?
+        .to_bytes();
+    let body = std::str::from_utf8(&body).map_err(|err| HttpError::BadRequest(err.into()))?;
+
+    let items = body
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| line.parse::<InfoHash>().map(|info_hash| (info_hash, None)))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|err| HttpError::BadRequest(err.into()))?;
+
+    let count = items.len();
+    state.worker.work(Task::InsertTorrents(items)).await?;
+
+    Ok(HttpResponse::from(count.to_string()))
+}
+
+/// Lists the peers of the torrent at `/api/torrents/{infohash}/peers`, one per line as the hex
+/// encoded peer id key, the peer type, the address and the secs since epoch of its last announce.
+pub(super) async fn list_swarm(
+    req: HttpRequest<IncomingBody>,
+    state: State,
+    info_hash: InfoHash,
+) -> Result<HttpResponse> {
+    authorize(&req, &state)?;
+
+    let peers: SwarmPeers = state
+        .worker
+        .work(Task::ListSwarm((info_hash, MAX_LISTED_PEERS)))
+        .await?
+        .into();
+
+    let mut body = String::new();
+    for (peer_id_key, peer_type, peer) in peers {
+        let peer_id_key = hex::encode(peer_id_key.as_ref());
+        let last_seen = peer.last_seen.as_secs();
+        let _ = writeln!(
+            body,
+            "{} {} {} {}",
+            peer_id_key, peer_type, peer.addr, last_seen
+        );
+    }
+
+    Ok(HttpResponse::from(body))
+}
+
+/// Returns the infohash of a `/api/torrents/{infohash}/peers` path.
+pub(super) fn parse_swarm_path(path: &str) -> Option<InfoHash> {
+    path.strip_prefix("/api/torrents/")?
+        .strip_suffix("/peers")?
+        .parse()
+        .ok()
+}
+
+/// Responds with the tracker metrics in the Prometheus text format.
+pub(super) fn metrics(req: HttpRequest<IncomingBody>, state: State) -> Result<HttpResponse> {
+    authorize(&req, &state)?;
+    Ok(HttpResponse::from(METRICS.render()))
+}
+
+/// Checks the API key header against the configured `api_key`.
+/// The API is disabled if no key is configured.
+fn authorize(req: &HttpRequest<IncomingBody>, state: &State) -> Result<()> {
+    let api_key = state.config.api_key().ok_or(HttpError::Unauthorized)?;
+
+    match req.headers().get(constants::API_KEY_HEADER) {
+        Some(value) if cmp::ct_eq(value.as_bytes(), api_key.as_bytes()) => Ok(()),
+        _ => Err(HttpError::Unauthorized),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::TSConfig, servers::http::start_test_server};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    #[test]
+    fn test_parse_swarm_path() {
+        let info_hash = "2a7b9e1f5c8d3a6b0f2e4c5a9b7d1e3a6c8b5d99";
+        let path = format!("/api/torrents/{}/peers", info_hash);
+        assert_eq!(parse_swarm_path(&path), info_hash.parse::<InfoHash>().ok());
+
+        assert!(parse_swarm_path("/api/torrents/bulk").is_none());
+        assert!(parse_swarm_path("/api/torrents/xyz/peers").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_torrents_path_not_found() {
+        let (addr, _, _stop_tx) = start_test_server(TSConfig::new().unwrap()).await;
+
+        // Not a swarm path, so even without an API key the path is unknown.
+        let request = "GET /api/torrents/unknown HTTP/1.1\r\nHost: localhost\r\n\
+            Connection: close\r\n\r\n";
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 404"));
+    }
+
+    #[tokio::test]
+    async fn test_insert_torrents_body_limit() {
+        let mut config = TSConfig::new().unwrap();
+        config.server.http.api_key = Some("secret".to_string());
+        let (addr, _, _stop_tx) = start_test_server(config).await;
+
+        let request = format!(
+            "POST /api/torrents/bulk HTTP/1.1\r\nHost: localhost\r\nx-api-key: secret\r\n\
+            Content-Length: {}\r\nConnection: close\r\n\r\n",
+            MAX_BULK_BODY_SIZE + 1
+        );
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        stream
+            .write_all(&vec![b'\n'; MAX_BULK_BODY_SIZE + 1])
+            .await
+            .unwrap();
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 413"));
+    }
+}
@@ -20,8 +20,16 @@ pub(super) enum HttpError {
     RequestTimeout,
     /// The request lacks valid authentication credentials (401 Unauthorized).
     Unauthorized,
+    /// The request is refused by the tracker config, along with the reason (403 Forbidden).
+    Forbidden(Cause),
     /// The request contains invalid data or parameters, along with an additional error message  (400 BadRequest).
     BadRequest(Cause),
+    /// The server is too busy to handle the request (503 Service Unavailable).
+    ServiceUnavailable,
+    /// The request has too many or too large headers (431 Request Header Fields Too Large).
+    RequestHeaderFieldsTooLarge,
+    /// The request body is larger than the endpoint accepts (413 Payload Too Large).
+    PayloadTooLarge,
     /// An HTTP error occurred, along with cause.
     Other(Cause),
 }
@@ -41,7 +49,13 @@ impl fmt::Display for HttpError {
             HttpError::NotFound => write!(f, "{}", constants::NOT_FOUND),
             HttpError::RequestTimeout => write!(f, "{}", constants::REQUEST_TIMEOUT),
             HttpError::Unauthorized => write!(f, "{}", constants::UNAUTHORIZED),
+            HttpError::Forbidden(reason) => write!(f, "{}", reason),
             HttpError::BadRequest(reason) => write!(f, "{}", reason),
+            HttpError::ServiceUnavailable => write!(f, "{}", constants::SERVICE_UNAVAILABLE),
+            HttpError::RequestHeaderFieldsTooLarge => {
+                write!(f, "{}", constants::REQUEST_HEADER_FIELDS_TOO_LARGE)
+            }
+            HttpError::PayloadTooLarge => write!(f, "{}", constants::PAYLOAD_TOO_LARGE),
             HttpError::Other(_) => write!(f, "{}", constants::INTERNAL_SERVER_ERROR),
         }
     }
@@ -51,6 +65,7 @@ impl StdError for HttpError {
     fn source(&self) -> Option<&(dyn StdError + 'static)> {
         match self {
             HttpError::BadRequest(cause) => Some(cause.as_ref()),
+            HttpError::Forbidden(cause) => Some(cause.as_ref()),
             HttpError::Other(cause) => Some(cause.as_ref()),
             _ => None,
         }
@@ -64,6 +79,10 @@ impl Into<Response<Body>> for HttpError {
             HttpError::BadRequest(_) => StatusCode::BAD_REQUEST,
             HttpError::RequestTimeout => StatusCode::REQUEST_TIMEOUT,
             HttpError::Unauthorized => StatusCode::UNAUTHORIZED,
+            HttpError::Forbidden(_) => StatusCode::FORBIDDEN,
+            HttpError::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            HttpError::RequestHeaderFieldsTooLarge => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            HttpError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
@@ -78,6 +97,14 @@ impl Into<Response<Body>> for HttpError {
 
 impl From<worker::Error> for HttpError {
     fn from(err: worker::Error) -> Self {
+        if err.is_saturated() {
+            return HttpError::ServiceUnavailable;
+        }
+
+        if let Some(reason) = err.tracker_error() {
+            return HttpError::BadRequest(reason.into());
+        }
+
         HttpError::Other(err.into())
     }
 }
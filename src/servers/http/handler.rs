@@ -1,28 +1,38 @@
+use super::api;
 use super::error::HttpError;
 use super::response::{Body, BodyStream, HttpResponse};
+use crate::config::{DisabledProtocolStatus, TSConfig};
 use crate::constants;
-use crate::models::common::IpType;
+use crate::models::common::Protocol;
 use crate::models::tracker::{
-    AnnounceRequest, AnnounceResponse, ScrapeRequest, ScrapeResponse, TrackerError,
+    AnnounceRequest, AnnounceResponse, FullScrapeFormat, ScrapeRequest, ScrapeResponse,
+    TrackerError,
 };
 use crate::servers::cache::full_scrape;
-use crate::servers::http::request::HttpRequest;
+use crate::servers::http::request::{HttpRequest, TrustedProxy};
 use crate::servers::State;
-use crate::utils::Loggable;
+use crate::utils::{metrics::METRICS, Loggable, RequestId};
 use crate::worker::Task;
 
 use bytes::Bytes;
-use hyper::Method;
+use hyper::header::{HeaderMap, HeaderValue, ALLOW, CONNECTION};
 use hyper::{body::Incoming as IncomingBody, service::Service, Request, Response};
-use log::{debug, info, log_enabled, Level};
+use hyper::{Method, StatusCode};
+use log::{debug, error, info, log_enabled, Level};
+use std::fmt;
 use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::{net::SocketAddr, pin::Pin};
 use tokio::sync::mpsc;
+use ts_utils::bencode::{self, Bencode};
 
 pub(super) struct Handler {
     addr: SocketAddr,
     state: State,
     on_response_finish: Option<mpsc::Sender<()>>,
+
+    /// The number of requests received on the connection.
+    request_count: AtomicUsize,
 }
 
 impl Service<Request<IncomingBody>> for Handler {
@@ -30,23 +40,42 @@ impl Service<Request<IncomingBody>> for Handler {
     type Error = std::convert::Infallible;
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
 
-    fn call(&self, req: Request<IncomingBody>) -> Self::Future {
+    fn call(&self, mut req: Request<IncomingBody>) -> Self::Future {
         let state = self.state.clone();
         let addr = self.addr;
+        let request_id = RequestId::next();
+        req.extensions_mut().insert(request_id);
+
+        if state
+            .config
+            .trusted_proxies()
+            .contains(&addr.ip().to_canonical())
+        {
+            req.extensions_mut().insert(TrustedProxy);
+        }
+
         let req = HttpRequest(req);
+        let is_last_request = self.is_last_request();
+        let is_connection_close = req.is_connection_close() || is_last_request;
         let tx = self.on_response_finish.clone();
 
         Box::pin(async move {
-            let res = Handler::handle_request(req, state, addr)
+            let mut res = Handler::handle_request(req, state, addr)
                 .await
                 .map(Into::into)
-                .or_else(request_error_handler);
+                .or_else(request_error_handler)?;
 
-            if let Some(tx) = tx {
+            set_request_id_header(&mut res, request_id);
+
+            // The connection is not kept alive, so there is no idle window to restart.
+            if is_connection_close {
+                res.headers_mut()
+                    .insert(CONNECTION, HeaderValue::from_static("close"));
+            } else if let Some(tx) = tx {
                 let _ = tx.send(()).await;
             }
 
-            Ok(res?)
+            Ok(res)
         })
     }
 }
@@ -61,7 +90,20 @@ impl Handler {
             addr,
             state,
             on_response_finish,
+            request_count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Counts a new request on a keep-alive connection, and returns whether it is the last one
+    /// allowed on it. Without keep-alive each connection serves a single request anyway.
+    fn is_last_request(&self) -> bool {
+        let max_requests = self.state.config.max_requests_per_connection();
+        if !self.state.config.is_keep_alive_enabled() || max_requests == 0 {
+            return false;
         }
+
+        let count = self.request_count.fetch_add(1, Ordering::Relaxed) + 1;
+        count >= max_requests
     }
 
     async fn handle_request(
@@ -69,21 +111,39 @@ impl Handler {
         state: State,
         addr: SocketAddr,
     ) -> Result<HttpResponse, HttpError> {
+        // The size of the headers is capped by hyper's read buffer, see `accept_loop`.
+        check_headers(req.headers(), state.config.http_max_headers())?;
+
         if log_enabled!(Level::Info) && state.config.http_log_request() {
             info!("{}", req.log());
         }
 
-        match (req.method(), req.uri().path()) {
+        // A HEAD request is served like a GET on the routes without side effects, hyper leaves
+        // out the body. An announce would update the swarm, so it only answers to a GET.
+        let method = match (req.method(), req.uri().path()) {
+            (&Method::HEAD, "/ping" | "/version" | "/api/metrics") => Method::GET,
+            (method, _) => method.clone(),
+        };
+
+        match (&method, req.uri().path()) {
+            (&Method::OPTIONS, path) => options(path),
             (&Method::GET, "/ping") => Ok(HttpResponse::from("pong")),
+            (&Method::GET, "/version") => Ok(version(&state.config)),
             (&Method::GET, "/announce") => announce(req, state, addr).await.or_else(|err| {
                 debug!("announce failed: {:?}", err);
                 convert_to_tracker_response(err)
             }),
-            (&Method::GET, "/scrape") => scrape(req, state, addr).await.or_else(|err| {
+            (&Method::GET, "/scrape") => scrape(req, state).await.or_else(|err| {
                 debug!("scrape failed: {:?}", err);
                 convert_to_tracker_response(err)
             }),
-            _ => Err(HttpError::NotFound),
+            (&Method::POST, "/api/torrents/bulk") => api::insert_torrents(req, state).await,
+            (&Method::GET, "/api/metrics") => api::metrics(req, state),
+            (&Method::GET, path) => match api::parse_swarm_path(path) {
+                Some(info_hash) => api::list_swarm(req, state, info_hash).await,
+                None => not_found(req.method()),
+            },
+            _ => not_found(req.method()),
         }
     }
 }
@@ -94,64 +154,99 @@ async fn announce(
     addr: SocketAddr,
 ) -> Result<HttpResponse, HttpError> {
     if !state.config.allow_http_announce() {
-        let err: TrackerError = constants::TRACKER_ERROR_HTTP_ANNOUNCE_NOT_ALLOWED.into();
-        return HttpResponse::try_from(err);
+        return disabled_protocol(
+            state.config.disabled_protocol_status(),
+            constants::TRACKER_ERROR_HTTP_ANNOUNCE_NOT_ALLOWED,
+        );
     }
 
-    let request: AnnounceRequest = req.query_params()?;
+    METRICS.announces.increment(Protocol::Http);
+
+    let request: AnnounceRequest =
+        req.query_params_with_binary_keys(state.config.binary_query_keys())?;
     if log_enabled!(Level::Debug) {
         debug!("{}", request.log());
     }
 
-    let ip_addr = match state.config.ip_forward_header_name() {
-        Some(header_name) => req.reverse_ip(header_name),
-        _ => None,
-    }
-    .unwrap_or_else(|| addr.ip());
+    let header_name = state.config.ip_forward_header_name().map(String::as_str);
+    let ip_addr = req
+        .client_ip(header_name, state.config.require_forward_header(), addr)?
+        .to_canonical();
+
+    let requested = request
+        .numwant
+        .unwrap_or_else(|| state.config.numwant_for_event(request.event));
 
     let task = Task::Announce((request, ip_addr));
-    let response: AnnounceResponse = state.worker.work(task).await?.into();
+    let response: AnnounceResponse = state
+        .worker
+        .work_with_id(task, req.request_id())
+        .await?
+        .into();
 
     if log_enabled!(Level::Debug) {
         debug!("{}", response.log());
     }
 
-    HttpResponse::try_from(response)
+    let bytes = bencode_response(&response, &state)?;
+    METRICS.announce_response_size.observe(bytes.len());
+
+    // Reports the peers served out of those requested, next to the bencoded body.
+    let numwant = format!("{}/{}", response.peer_count(), requested);
+    let response = HttpResponse::from(bytes);
+    match HeaderValue::from_str(&numwant) {
+        Ok(value) => Ok(response.with_header(constants::NUMWANT_HEADER, value)),
+        Err(_) => Ok(response),
+    }
 }
 
-async fn scrape(
-    req: HttpRequest<IncomingBody>,
-    state: State,
-    addr: SocketAddr,
-) -> Result<HttpResponse, HttpError> {
+async fn scrape(req: HttpRequest<IncomingBody>, state: State) -> Result<HttpResponse, HttpError> {
     if !state.config.allow_http_scrape() {
-        let err: TrackerError = constants::TRACKER_ERROR_HTTP_SCRAPE_NOT_ALLOWED.into();
+        return disabled_protocol(
+            state.config.disabled_protocol_status(),
+            constants::TRACKER_ERROR_HTTP_SCRAPE_NOT_ALLOWED,
+        );
+    }
+
+    METRICS.scrapes.increment(Protocol::Http);
+
+    // Reject an over-long multi scrape before allocating its list of infohashes.
+    if req.query_param_count("info_hash") > state.config.max_multi_scrape_count() as usize {
+        let err: TrackerError = constants::TRACKER_ERROR_TOO_MANY_INFOHASHES.into();
         return HttpResponse::try_from(err);
     }
 
-    let request: ScrapeRequest = req.query_params()?;
-    let ip_type = match addr.ip() {
-        std::net::IpAddr::V4(_) => IpType::V4,
-        std::net::IpAddr::V6(_) => IpType::V6,
-    };
+    let request: ScrapeRequest =
+        req.query_params_with_binary_keys(state.config.binary_query_keys())?;
 
     if request.info_hashes.is_empty() {
-        return full_scrape(state).await;
+        let format = request
+            .format
+            .unwrap_or_else(|| state.config.full_scrape_format());
+
+        return full_scrape(state, format).await;
     }
 
-    let task = Task::Scrape((request, ip_type));
-    let response: ScrapeResponse = state.worker.work(task).await?.into();
+    let task = Task::Scrape(request);
+    let response: ScrapeResponse = state
+        .worker
+        .work_with_id(task, req.request_id())
+        .await?
+        .into();
+
+    let bytes = bencode_response(&response, &state)?;
+    METRICS.scrape_response_size.observe(bytes.len());
 
-    HttpResponse::try_from(response)
+    Ok(HttpResponse::from(bytes))
 }
 
-async fn full_scrape(state: State) -> Result<HttpResponse, HttpError> {
+async fn full_scrape(state: State, format: FullScrapeFormat) -> Result<HttpResponse, HttpError> {
     if !state.config.allow_full_scrape() {
         let err: TrackerError = constants::TRACKER_ERROR_FULL_SCRAPE_NOT_ALLOWED.into();
         return HttpResponse::try_from(err);
     }
 
-    let cache = state.cache.full_scrape.read().await;
+    let cache = state.cache.full_scrape(format).read().await;
     let is_cache_expired = cache.is_expired() || cache.is_none();
 
     if is_cache_expired && !cache.is_refreshing() {
@@ -159,16 +254,19 @@ async fn full_scrape(state: State) -> Result<HttpResponse, HttpError> {
         let expires_in = state.config.full_scrape_cache_ttl().into();
 
         tokio::spawn(async move {
-            full_scrape::refresh(state.cache, state.worker, expires_in).await;
+            full_scrape::refresh(state.cache, state.worker, expires_in, format).await;
         });
     }
 
-    match cache.as_ref() {
-        Some(val) => {
-            let stream = BodyStream::from(val.clone());
+    match (cache.as_ref(), format) {
+        (Some(val), _) => {
+            let stream = BodyStream::from(val.clone()).chunk_size(state.config.stream_chunk_size());
             return Ok(HttpResponse::from(stream));
         }
-        _ => HttpResponse::try_from(ScrapeResponse::default()),
+        (None, FullScrapeFormat::Bencode | FullScrapeFormat::Split) => {
+            HttpResponse::try_from(ScrapeResponse::default())
+        }
+        (None, FullScrapeFormat::Binary) => Ok(HttpResponse::from(Bytes::new())),
     }
 }
 
@@ -176,7 +274,11 @@ impl TryFrom<TrackerError> for HttpResponse {
     type Error = HttpError;
 
     fn try_from(err: TrackerError) -> Result<Self, Self::Error> {
-        let bytes: Bytes = err.try_into()?;
+        let bytes: Bytes = match err.static_bytes() {
+            Some(bytes) => bytes,
+            None => err.try_into()?,
+        };
+
         Ok(HttpResponse::from(bytes))
     }
 }
@@ -199,12 +301,280 @@ impl TryFrom<ScrapeResponse> for HttpResponse {
     }
 }
 
+/// Answers an `OPTIONS` request for a known route with the methods it allows.
+fn options(path: &str) -> Result<HttpResponse, HttpError> {
+    let allow = match path {
+        "/api/torrents/bulk" => "POST",
+        "/ping" | "/version" | "/api/metrics" => "GET, HEAD",
+        "/announce" | "/scrape" => "GET",
+        path if api::parse_swarm_path(path).is_some() => "GET",
+        _ => return not_found(&Method::OPTIONS),
+    };
+
+    Ok(HttpResponse::from(Bytes::new())
+        .with_status(StatusCode::NO_CONTENT)
+        .with_header(ALLOW, HeaderValue::from_static(allow)))
+}
+
+/// Reports the build of the tracker and its storage type, one `key value` pair per line.
+fn version(config: &TSConfig) -> HttpResponse {
+    let features = [
+        ("redis-store", cfg!(feature = "redis-store")),
+        ("memalloc", cfg!(feature = "memalloc")),
+        ("jemalloc", cfg!(feature = "jemalloc")),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(name, _)| name)
+    .collect::<Vec<_>>()
+    .join(",");
+
+    HttpResponse::from(format!(
+        "version {}\ngit_hash {}\nstorage {}\nfeatures {}\nmax_read_buffer_size {}\n",
+        env!("CARGO_PKG_VERSION"),
+        env!("TS_GIT_HASH"),
+        config.storage.name.as_str(),
+        features,
+        config.max_read_buffer_size()
+    ))
+}
+
+/// Answers a request to an unknown route, counting it by method.
+fn not_found(method: &Method) -> Result<HttpResponse, HttpError> {
+    METRICS.not_found.increment(method.as_str());
+    Err(HttpError::NotFound)
+}
+
+/// Rejects requests with more headers than an announce or a scrape needs.
+fn check_headers(headers: &HeaderMap, max_count: usize) -> Result<(), HttpError> {
+    if headers.len() > max_count {
+        return Err(HttpError::RequestHeaderFieldsTooLarge);
+    }
+
+    Ok(())
+}
+
+fn set_request_id_header(res: &mut Response<Body>, request_id: RequestId) {
+    if let Ok(value) = HeaderValue::from_str(&request_id.to_string()) {
+        res.headers_mut()
+            .insert(constants::REQUEST_ID_HEADER, value);
+    }
+}
+
 fn request_error_handler(err: HttpError) -> Result<Response<Body>, std::convert::Infallible> {
     debug!("request failed: {:?}", err);
     Ok(err.into())
 }
 
+/// Serializes an announce or scrape response, into a pooled buffer if enabled.
+/// A failure is answered with a 500 rather than a tracker error, and logged along with the
+/// response.
+fn bencode_response<T>(response: &T, state: &State) -> Result<Bytes, HttpError>
+where
+    T: Bencode + fmt::Debug,
+{
+    let result = match state.config.pool_response_buffers() {
+        true => response.bencode_pooled(),
+        false => response.bencode(),
+    };
+
+    result.map_err(|err| {
+        error!("failed to bencode {:?}: {}", response, err);
+        err.into()
+    })
+}
+
+/// Answers a request to a protocol disabled by the tracker config.
+fn disabled_protocol(
+    status: DisabledProtocolStatus,
+    reason: &'static str,
+) -> Result<HttpResponse, HttpError> {
+    match status {
+        DisabledProtocolStatus::Bencode => HttpResponse::try_from(TrackerError::from(reason)),
+        DisabledProtocolStatus::Forbidden => Err(HttpError::Forbidden(reason.into())),
+    }
+}
+
 fn convert_to_tracker_response(err: HttpError) -> Result<HttpResponse, HttpError> {
+    // Let clients back off with a 503 rather than a tracker error when the worker is saturated.
+    // A 403 for a disabled protocol is the configured answer, so it is kept as well, and a
+    // response that failed to serialize is a server error rather than a failure reason.
+    match &err {
+        HttpError::ServiceUnavailable | HttpError::Forbidden(_) => return Err(err),
+        HttpError::Other(cause) if cause.is::<bencode::Error>() => return Err(err),
+        _ => {}
+    }
+
     let err: TrackerError = err.to_string().into();
     return HttpResponse::try_from(err);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_id_header() {
+        let request_id = RequestId::next();
+        let mut res: Response<Body> = HttpResponse::from("pong").into();
+        set_request_id_header(&mut res, request_id);
+
+        let header = res.headers().get(constants::REQUEST_ID_HEADER).unwrap();
+        assert_eq!(header.to_str().unwrap(), request_id.to_string());
+    }
+
+    #[test]
+    fn test_options() {
+        for (path, allow) in [
+            ("/ping", "GET, HEAD"),
+            ("/announce", "GET"),
+            ("/api/torrents/bulk", "POST"),
+            (
+                "/api/torrents/2a7b9e1f5c8d3a6b0f2e4c5a9b7d1e3a6c8b5d99/peers",
+                "GET",
+            ),
+        ] {
+            let response: Response<Body> = options(path).unwrap().into();
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+            assert_eq!(response.headers().get(ALLOW).unwrap(), allow);
+        }
+
+        assert!(matches!(options("/unknown"), Err(HttpError::NotFound)));
+        assert!(matches!(
+            options("/api/torrents/unknown"),
+            Err(HttpError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_last_request_only_with_keep_alive() {
+        for enable_keep_alive in [false, true] {
+            let mut config = TSConfig::new().unwrap();
+            config.server.http.enable_keep_alive = enable_keep_alive;
+            config.server.http.max_requests_per_connection = 1;
+            let (addr, state, _stop_tx) = super::super::start_test_server(config).await;
+
+            let handler = Handler::new(addr, state, None);
+            assert_eq!(handler.is_last_request(), enable_keep_alive);
+            assert_eq!(
+                handler.request_count.load(Ordering::Relaxed),
+                enable_keep_alive as usize
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_version() {
+        use http_body_util::BodyExt;
+
+        let mut config = TSConfig::new().unwrap();
+        config.server.http.max_read_buffer_size = 16384;
+        let body = version(&config).body.collect().await.unwrap().to_bytes();
+        let body = std::str::from_utf8(&body).unwrap();
+
+        let mut lines = body.lines();
+        assert_eq!(
+            lines.next(),
+            Some(concat!("version ", env!("CARGO_PKG_VERSION")))
+        );
+        assert!(lines.next().unwrap().starts_with("git_hash "));
+        assert_eq!(lines.next(), Some("storage memory"));
+        assert!(lines.next().unwrap().starts_with("features"));
+        assert_eq!(lines.next(), Some("max_read_buffer_size 16384"));
+    }
+
+    #[test]
+    fn test_check_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("host", HeaderValue::from_static("tracker.example"));
+        assert!(check_headers(&headers, 2).is_ok());
+
+        for i in 0..3 {
+            headers.append("x-padding", HeaderValue::from(i));
+        }
+
+        let err = check_headers(&headers, 2).unwrap_err();
+        assert!(matches!(err, HttpError::RequestHeaderFieldsTooLarge));
+
+        let response: Response<Body> = err.into();
+        assert_eq!(
+            response.status(),
+            StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+        );
+    }
+
+    #[test]
+    fn test_disabled_announce_status() {
+        let reason = constants::TRACKER_ERROR_HTTP_ANNOUNCE_NOT_ALLOWED;
+
+        let response: Response<Body> = disabled_protocol(DisabledProtocolStatus::Bencode, reason)
+            .or_else(convert_to_tracker_response)
+            .unwrap()
+            .into();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let err = disabled_protocol(DisabledProtocolStatus::Forbidden, reason)
+            .or_else(convert_to_tracker_response)
+            .unwrap_err();
+        assert_eq!(err.to_string(), reason);
+
+        let response: Response<Body> = err.into();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_not_found_torrent_failure_reason() {
+        use crate::{
+            models::common::{InfoHash, IpType, INFOHASH_LENGTH},
+            storage::{MemoryStorage, Storage},
+            worker,
+        };
+        use http_body_util::BodyExt;
+
+        let info_hash = InfoHash([b'a'; INFOHASH_LENGTH]);
+        let storage_err = MemoryStorage::new()
+            .get_torrent_stats(&info_hash, IpType::V4)
+            .await
+            .unwrap_err();
+
+        let task_errors = [
+            worker::Error::from(storage_err),
+            worker::Error::from(constants::TRACKER_ERROR_NOT_FOUND_TORRENT),
+        ];
+
+        for err in task_errors {
+            let response = convert_to_tracker_response(err.into()).unwrap();
+            let body = response.body.collect().await.unwrap().to_bytes();
+            assert_eq!(&body[..], b"d14:failure reason17:torrent not founde");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unserializable_response_server_error() {
+        use crate::worker::Worker;
+        use http_body_util::BodyExt;
+        use std::sync::Arc;
+
+        #[derive(Debug, serde::Serialize)]
+        struct Unserializable {
+            ratio: f64,
+        }
+
+        impl Bencode for Unserializable {}
+
+        let config = Arc::new(TSConfig::new().unwrap());
+        let state = State::new(Arc::new(Worker::new(config.clone())), config);
+
+        // Follows the path of an announce, from its handler down to the response of `Handler`.
+        let response = bencode_response(&Unserializable { ratio: 0.5 }, &state)
+            .map(HttpResponse::from)
+            .or_else(convert_to_tracker_response)
+            .map(Into::into)
+            .or_else(request_error_handler)
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], constants::INTERNAL_SERVER_ERROR.as_bytes());
+    }
+}
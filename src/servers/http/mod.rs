@@ -1,13 +1,16 @@
+mod api;
 mod error;
 mod handler;
 mod request;
 mod response;
+mod selftest;
 
 use hyper::server::conn::http1;
 use hyper_util::rt::TokioIo;
 use log::{debug, error, info};
 use socket2::{Protocol, Socket};
 use std::{
+    cmp,
     net::{IpAddr, SocketAddr},
     sync::Arc,
     time::Duration,
@@ -19,6 +22,7 @@ use tokio::{
 };
 
 use self::handler::Handler;
+pub use self::selftest::self_test;
 use super::State;
 use crate::signals::StopSignalRx;
 
@@ -39,24 +43,12 @@ impl HttpServer {
         let port = config.http_port();
         let ip: IpAddr = config.http_host().parse()?;
 
-        let domain = if ip.is_ipv6() {
-            socket2::Domain::IPV6
-        } else {
-            socket2::Domain::IPV4
-        };
-
-        let socket = Socket::new(domain, socket2::Type::STREAM, Some(Protocol::TCP))?;
         let addr = SocketAddr::from((ip, port));
-        let sock_addr = addr.into();
-
-        #[cfg(unix)]
-        socket.set_reuse_port(true)?;
-
-        socket.bind(&sock_addr)?;
-        socket.listen(config.connection_backlog_size())?;
-
-        let listener: std::net::TcpListener = socket.into();
-        listener.set_nonblocking(true)?;
+        let listener = bind_listener(
+            addr,
+            config.connection_backlog_size(),
+            config.http_reuse_port(),
+        )?;
         let listener = TcpListener::from_std(listener)?;
 
         info!("Listening on http://{}", addr);
@@ -81,20 +73,54 @@ impl HttpServer {
     }
 }
 
-// hyper/src/server/conn/http1.rs:298:9
-const MINIMUM_MAX_BUFFER_SIZE: usize = 8192;
+/// Creates a non-blocking listening socket bound to `addr`.
+fn bind_listener(
+    addr: SocketAddr,
+    backlog: i32,
+    reuse_port: bool,
+) -> std::io::Result<std::net::TcpListener> {
+    let domain = if addr.is_ipv6() {
+        socket2::Domain::IPV6
+    } else {
+        socket2::Domain::IPV4
+    };
+
+    let socket = Socket::new(domain, socket2::Type::STREAM, Some(Protocol::TCP))?;
+
+    // Allows a restart to bind while connections of the previous process are in TIME_WAIT.
+    socket.set_reuse_address(true)?;
+
+    #[cfg(unix)]
+    socket.set_reuse_port(reuse_port)?;
+
+    #[cfg(not(unix))]
+    let _ = reuse_port;
+
+    socket.bind(&addr.into())?;
+    socket.listen(backlog)?;
+
+    let listener: std::net::TcpListener = socket.into();
+    listener.set_nonblocking(true)?;
+
+    Ok(listener)
+}
 
 async fn accept_loop(
     listener: TcpListener,
     state: State,
     stop_global_signal_rx: StopSignalRx,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let max_buffer_size =
-        std::cmp::max(MINIMUM_MAX_BUFFER_SIZE, state.config.max_read_buffer_size());
+    // Hyper holds the request head in its read buffer and rejects it with a 431 once it
+    // outgrows the buffer, so capping the buffer caps the head.
+    let max_buffer_size = cmp::min(
+        state.config.max_read_buffer_size(),
+        state.config.http_max_header_size(),
+    );
 
     let is_keep_alive_enabled = state.config.is_keep_alive_enabled();
     let max_open_connections = state.config.max_open_connections();
     let request_timeout = state.config.http_request_timeout();
+    let keep_alive_idle_time = state.config.keep_alive_idle_time();
 
     let semaphore = Arc::new(Semaphore::new(max_open_connections));
 
@@ -126,7 +152,7 @@ async fn accept_loop(
                     connection.graceful_shutdown();
                 }
 
-                _ = create_request_timer(request_timeout, reset_timer_rx) => {
+                _ = create_request_timer(request_timeout, keep_alive_idle_time, reset_timer_rx) => {
                     drop(permit);
                     connection.graceful_shutdown();
                 }
@@ -146,20 +172,27 @@ async fn accept_loop(
     }
 }
 
-async fn create_request_timer(timeout_duration: Duration, mut reset_timer_rx: mpsc::Receiver<()>) {
+/// Resolves once the connection should be closed: either the first request did not complete
+/// within `timeout_duration`, or no new request completed within `idle_duration` of the last one.
+async fn create_request_timer(
+    timeout_duration: Duration,
+    idle_duration: Duration,
+    mut reset_timer_rx: mpsc::Receiver<()>,
+) {
     let deadline = Instant::now() + timeout_duration;
     let timeout_fut = sleep_until(deadline);
+    let mut is_reset_open = true;
 
     tokio::pin!(timeout_fut);
 
     loop {
         tokio::select! {
-            res = reset_timer_rx.recv() => match res {
+            res = reset_timer_rx.recv(), if is_reset_open => match res {
                 Some(_) => {
-                    let deadline = Instant::now() + timeout_duration;
+                    let deadline = Instant::now() + idle_duration;
                     timeout_fut.as_mut().reset(deadline);
                 },
-                None => {},
+                None => is_reset_open = false,
             },
 
             _ = &mut timeout_fut => {
@@ -168,3 +201,226 @@ async fn create_request_timer(timeout_duration: Duration, mut reset_timer_rx: mp
         }
     }
 }
+
+/// Serves `config` on an ephemeral localhost port, until the returned sender is dropped.
+#[cfg(test)]
+async fn start_test_server(
+    config: crate::config::TSConfig,
+) -> (SocketAddr, State, tokio::sync::watch::Sender<bool>) {
+    let config = Arc::new(config);
+
+    let mut worker = crate::worker::Worker::new(config.clone());
+    let _worker_job = worker.start();
+    let state = State::new(Arc::new(worker), config);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (stop_tx, stop_rx) = tokio::sync::watch::channel(false);
+    tokio::spawn(accept_loop(listener, state.clone(), stop_rx));
+
+    (addr, state, stop_tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::TSConfig, models::common::Protocol, utils::metrics::METRICS};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        time::timeout,
+    };
+
+    #[tokio::test]
+    async fn test_idle_keep_alive_connection_closed() {
+        let idle_duration = Duration::from_millis(50);
+        let (reset_timer_tx, reset_timer_rx) = mpsc::channel(1);
+
+        let start = Instant::now();
+        let timer = tokio::spawn(create_request_timer(
+            Duration::from_secs(10),
+            idle_duration,
+            reset_timer_rx,
+        ));
+
+        // A completed request starts the idle window.
+        reset_timer_tx.send(()).await.unwrap();
+        timer.await.unwrap();
+
+        let elapsed = start.elapsed();
+        assert!(elapsed >= idle_duration);
+        assert!(elapsed < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn test_max_requests_per_connection() {
+        let mut config = TSConfig::new().unwrap();
+        config.server.http.enable_keep_alive = true;
+        config.server.http.max_requests_per_connection = 2;
+        let (addr, _, _stop_tx) = start_test_server(config).await;
+
+        // Three pipelined requests, of which only two are served before the connection closes.
+        let request = "GET /ping HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(request.repeat(3).as_bytes())
+            .await
+            .unwrap();
+
+        let mut response = Vec::new();
+        timeout(Duration::from_secs(5), stream.read_to_end(&mut response))
+            .await
+            .expect("the connection was not closed")
+            .unwrap();
+
+        let response = String::from_utf8(response).unwrap().to_ascii_lowercase();
+        let responses: Vec<_> = response.split("http/1.1 200").skip(1).collect();
+        assert_eq!(responses.len(), 2);
+        assert!(!responses[0].contains("connection: close"));
+        assert!(responses[1].contains("connection: close"));
+    }
+
+    #[tokio::test]
+    async fn test_announce_counted_by_protocol() {
+        let (addr, _, _stop_tx) = start_test_server(TSConfig::new().unwrap()).await;
+
+        let http_announces = METRICS.announces.get(Protocol::Http);
+        let udp_announces = METRICS.announces.get(Protocol::Udp);
+
+        let request = "GET /announce?info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=bbbbbbbbbbbbbbbbbbbb\
+            &port=6881&uploaded=0&downloaded=0&left=0&compact=1 HTTP/1.1\r\n\
+            Host: localhost\r\nConnection: close\r\n\r\n";
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 200"));
+
+        // Other tests may announce over HTTP concurrently, but none over UDP.
+        assert!(METRICS.announces.get(Protocol::Http) > http_announces);
+        assert_eq!(METRICS.announces.get(Protocol::Udp), udp_announces);
+    }
+
+    #[tokio::test]
+    async fn test_head_only_without_side_effects() {
+        let (addr, _, _stop_tx) = start_test_server(TSConfig::new().unwrap()).await;
+
+        // An announce would update the swarm, so it is not served for a HEAD.
+        let announce = "/announce?info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=bbbbbbbbbbbbbbbbbbbb\
+            &port=6881&uploaded=0&downloaded=0&left=0&compact=1";
+
+        for (path, status) in [
+            ("/ping", "HTTP/1.1 200"),
+            ("/version", "HTTP/1.1 200"),
+            (announce, "HTTP/1.1 404"),
+            ("/api/torrents/bulk", "HTTP/1.1 404"),
+        ] {
+            let request = format!(
+                "HEAD {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                path
+            );
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(request.as_bytes()).await.unwrap();
+
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).await.unwrap();
+            assert!(response.starts_with(status.as_bytes()), "HEAD {}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unknown_path_counted_as_not_found() {
+        let (addr, _, _stop_tx) = start_test_server(TSConfig::new().unwrap()).await;
+
+        let not_found = METRICS.not_found.get("DELETE");
+
+        let request =
+            "DELETE /wp-login.php HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 404"));
+
+        assert!(METRICS.not_found.get("DELETE") > not_found);
+        assert!(METRICS
+            .render()
+            .contains("ts_http_not_found_total{method=\"DELETE\"}"));
+    }
+
+    #[tokio::test]
+    async fn test_numwant_header() {
+        let (addr, _, _stop_tx) = start_test_server(TSConfig::new().unwrap()).await;
+
+        // A seeder announces, then a leecher that is served the seeder only.
+        let mut headers = Vec::new();
+        for (peer_id, left) in [("cccccccccccccccccccc", 0), ("dddddddddddddddddddd", 100)] {
+            let request = format!(
+                "GET /announce?info_hash=numwantheadertorrent&peer_id={}&port=6881\
+                &uploaded=0&downloaded=0&left={}&compact=1&numwant=5 HTTP/1.1\r\n\
+                Host: localhost\r\nConnection: close\r\n\r\n",
+                peer_id, left
+            );
+
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(request.as_bytes()).await.unwrap();
+
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).await.unwrap();
+
+            let response = String::from_utf8_lossy(&response).to_ascii_lowercase();
+            let header = response
+                .lines()
+                .find_map(|line| line.strip_prefix("x-tracker-numwant: "))
+                .map(str::to_string);
+            headers.push(header);
+        }
+
+        assert_eq!(headers[0].as_deref(), Some("0/5"));
+        assert_eq!(headers[1].as_deref(), Some("1/5"));
+    }
+
+    #[tokio::test]
+    async fn test_large_request_head_rejected() {
+        let config = TSConfig::new().unwrap();
+        let max_size = config.http_max_header_size();
+        let (addr, _, _stop_tx) = start_test_server(config).await;
+
+        // A head filling the whole read buffer without ending, all of which hyper reads.
+        let mut request = "GET /ping HTTP/1.1\r\nHost: localhost\r\nx-padding: ".to_string();
+        request.extend(std::iter::repeat('a').take(max_size - request.len()));
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        // Hyper answers before the handler sees the request, and closes the connection.
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 431"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rebind_after_drop() {
+        let listener = bind_listener("127.0.0.1:0".parse().unwrap(), 16, false).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Closing the accepted side first leaves it in TIME_WAIT.
+        let client = std::net::TcpStream::connect(addr).unwrap();
+        let (server, _) = loop {
+            match listener.accept() {
+                Ok(conn) => break conn,
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => continue,
+                Err(err) => panic!("accept failed: {}", err),
+            }
+        };
+
+        drop(server);
+        drop(client);
+        drop(listener);
+
+        assert!(bind_listener(addr, 16, false).is_ok());
+    }
+}
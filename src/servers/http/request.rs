@@ -1,11 +1,18 @@
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 
 use super::error::{HttpError, Result};
-use crate::utils::Loggable;
+use crate::constants;
+use crate::utils::{Loggable, RequestId};
 use ts_utils::{query, string::get_first_value};
 
 pub(super) struct HttpRequest<T>(pub hyper::Request<T>);
 
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+
+/// Marks a request as sent by one of the `trusted_proxies`, whose forwarded headers are honored.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct TrustedProxy;
+
 impl<T> std::ops::Deref for HttpRequest<T> {
     type Target = hyper::Request<T>;
     fn deref(&self) -> &Self::Target {
@@ -21,8 +28,17 @@ impl<Body> HttpRequest<Body> {
     /// - `Ok(T)`: If the query parameters were successfully deserialized into the specified type `T`.
     /// - `Err`: If there was an error during deserialization or if the query parameters are invalid.
     pub fn query_params<'de, T: serde::Deserialize<'de>>(&'de self) -> Result<T> {
+        self.query_params_with_binary_keys(&[])
+    }
+
+    /// Like `query_params`, but a `+` in the values of `binary_keys` is kept as is.
+    pub fn query_params_with_binary_keys<'de, T: serde::Deserialize<'de>>(
+        &'de self,
+        binary_keys: &'de [&'de str],
+    ) -> Result<T> {
         let query = self.uri().query().unwrap_or_default();
-        query::from_bytes(query.as_bytes()).map_err(|err| HttpError::BadRequest(err.into()))
+        query::from_bytes_with_binary_keys(query.as_bytes(), binary_keys)
+            .map_err(|err| HttpError::BadRequest(err.into()))
     }
 
     /// This function extracts an IP address from an HTTP header with the specified name.
@@ -39,7 +55,66 @@ impl<Body> HttpRequest<Body> {
         self.headers()
             .get(header_name)
             .and_then(|header| header.to_str().ok())
-            .and_then(|header| get_first_value(header, ',').parse().ok())
+            .and_then(|header| parse_forwarded_ip(get_first_value(header, ',')))
+    }
+
+    /// Returns the IP of the client: the one in the `header_name` header, if set, or else the
+    /// address of the connection. A request without a valid IP in the header is rejected
+    /// if `required` is set.
+    pub fn client_ip(
+        &self,
+        header_name: Option<&str>,
+        required: bool,
+        addr: SocketAddr,
+    ) -> Result<IpAddr> {
+        match header_name.and_then(|header_name| self.reverse_ip(header_name)) {
+            Some(ip) => Ok(ip),
+            None if required && header_name.is_some() => Err(HttpError::BadRequest(
+                constants::TRACKER_ERROR_MISSING_FORWARDED_IP.into(),
+            )),
+            None => Ok(addr.ip()),
+        }
+    }
+
+    /// Returns the scheme the client used to reach the tracker: the `X-Forwarded-Proto` of a
+    /// trusted proxy if it is `https`, and `http` otherwise.
+    pub fn scheme(&self) -> &str {
+        if self.extensions().get::<TrustedProxy>().is_none() {
+            return "http";
+        }
+
+        match self
+            .headers()
+            .get(X_FORWARDED_PROTO)
+            .and_then(|header| header.to_str().ok())
+            .map(|header| get_first_value(header, ','))
+        {
+            Some(proto) if proto.eq_ignore_ascii_case("https") => "https",
+            _ => "http",
+        }
+    }
+
+    /// Returns the id assigned to this request by the `Handler`, if any.
+    pub fn request_id(&self) -> Option<RequestId> {
+        self.extensions().get::<RequestId>().copied()
+    }
+
+    /// Returns `true` if the client asked to close the connection after this request.
+    pub fn is_connection_close(&self) -> bool {
+        self.headers()
+            .get(hyper::header::CONNECTION)
+            .and_then(|header| header.to_str().ok())
+            .is_some_and(|header| header.trim().eq_ignore_ascii_case("close"))
+    }
+
+    /// Counts the occurrences of the query param `name` without decoding the query.
+    pub fn query_param_count(&self, name: &str) -> usize {
+        self.uri()
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|param| get_first_value(param, '=') == name)
+            .count()
     }
 }
 
@@ -48,15 +123,41 @@ impl<T> Loggable for HttpRequest<T> {
         let uri = self.uri();
         let method = self.method();
         let version = self.version();
+        let scheme = self.scheme();
         let headers = self.headers();
 
-        format!(
-            "Request: {:?} {} {} | Headers: {:?}",
-            version, method, uri, headers
-        )
+        match self.request_id() {
+            Some(request_id) => format!(
+                "Request [{}]: {:?} {} {} | Scheme: {} | Headers: {:?}",
+                request_id, version, method, uri, scheme, headers
+            ),
+            None => format!(
+                "Request: {:?} {} {} | Scheme: {} | Headers: {:?}",
+                version, method, uri, scheme, headers
+            ),
+        }
     }
 }
 
+/// Parses an IP address forwarded by a proxy, which may be bracketed or carry a port,
+/// e.g. `192.0.2.1`, `192.0.2.1:8080`, `[2001:db8::1]` or `[2001:db8::1]:8080`.
+fn parse_forwarded_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim();
+
+    if let Ok(ip) = value.parse() {
+        return Some(ip);
+    }
+
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+
+    value
+        .strip_prefix('[')
+        .and_then(|value| value.strip_suffix(']'))
+        .and_then(|value| value.parse().ok())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -82,6 +183,53 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_client_ip() {
+        let addr: SocketAddr = "10.0.0.1:6881".parse().unwrap();
+        let request = |header: Option<&str>| {
+            let mut builder = hyper::Request::builder().uri("/announce");
+            if let Some(header) = header {
+                builder = builder.header("X-Real-IP", header);
+            }
+            HttpRequest(builder.body(()).unwrap())
+        };
+
+        let present = request(Some("192.168.1.1"));
+        for required in [false, true] {
+            let ip = present
+                .client_ip(Some("X-Real-IP"), required, addr)
+                .unwrap();
+            assert_eq!(ip, IpAddr::from([192, 168, 1, 1]));
+        }
+
+        // Without the header, a lenient config falls back to the address of the connection.
+        let absent = request(None);
+        let ip = absent.client_ip(Some("X-Real-IP"), false, addr).unwrap();
+        assert_eq!(ip, addr.ip());
+
+        let err = absent.client_ip(Some("X-Real-IP"), true, addr).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            constants::TRACKER_ERROR_MISSING_FORWARDED_IP
+        );
+
+        let err = request(Some("unknown")).client_ip(Some("X-Real-IP"), true, addr);
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_request_id_in_log() {
+        let request_id = RequestId::next();
+        let mut request = hyper::Request::builder().uri("/announce").body(()).unwrap();
+        request.extensions_mut().insert(request_id);
+
+        let request = HttpRequest(request);
+        assert_eq!(request.request_id(), Some(request_id));
+        assert!(request
+            .log()
+            .starts_with(&format!("Request [{}]:", request_id)));
+    }
+
     #[test]
     fn test_query_params() {
         let uri = "/test?foo=bar&baz=qux".parse::<Uri>().unwrap();
@@ -108,4 +256,57 @@ mod tests {
         assert_eq!(query_params.foo, "bar".to_string());
         assert_eq!(query_params.baz, "qux".to_string());
     }
+
+    #[test]
+    fn test_query_param_count() {
+        let hashes = "info_hash=aaaaaaaaaaaaaaaaaaaa&".repeat(65);
+        let uri = format!("/scrape?{}format=binary&info_hashes=x", hashes);
+        let request = HttpRequest(hyper::Request::builder().uri(uri).body(()).unwrap());
+
+        assert_eq!(request.query_param_count("info_hash"), 65);
+        assert_eq!(request.query_param_count("format"), 1);
+
+        let request = HttpRequest(hyper::Request::builder().uri("/scrape").body(()).unwrap());
+        assert_eq!(request.query_param_count("info_hash"), 0);
+    }
+
+    #[test]
+    fn test_parse_forwarded_ip() {
+        let v4 = Some(IpAddr::from([192, 0, 2, 1]));
+        let v6 = Some(IpAddr::from([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1]));
+
+        assert_eq!(parse_forwarded_ip("192.0.2.1"), v4);
+        assert_eq!(parse_forwarded_ip(" 192.0.2.1:8080"), v4);
+        assert_eq!(parse_forwarded_ip("2001:db8::1"), v6);
+        assert_eq!(parse_forwarded_ip("[2001:db8::1]"), v6);
+        assert_eq!(parse_forwarded_ip("[2001:db8::1]:8080"), v6);
+
+        assert_eq!(parse_forwarded_ip("unknown"), None);
+        assert_eq!(parse_forwarded_ip("[192.0.2.1"), None);
+    }
+
+    #[test]
+    fn test_scheme() {
+        let request = |trusted: bool| {
+            let mut request = hyper::Request::builder()
+                .uri("/announce")
+                .header("X-Forwarded-Proto", "https")
+                .body(())
+                .unwrap();
+
+            if trusted {
+                request.extensions_mut().insert(TrustedProxy);
+            }
+
+            HttpRequest(request)
+        };
+
+        assert_eq!(request(true).scheme(), "https");
+        assert_eq!(request(false).scheme(), "http");
+        assert!(request(true).log().contains("| Scheme: https |"));
+
+        let mut request = hyper::Request::builder().uri("/announce").body(()).unwrap();
+        request.extensions_mut().insert(TrustedProxy);
+        assert_eq!(HttpRequest(request).scheme(), "http");
+    }
 }
@@ -7,7 +7,11 @@ use std::{
 use bytes::Bytes;
 use futures::Stream;
 use http_body_util::{Either, Full, StreamBody};
-use hyper::{body::Frame, Response, StatusCode};
+use hyper::{
+    body::Frame,
+    header::{HeaderMap, HeaderValue, IntoHeaderName},
+    Response, StatusCode,
+};
 use ts_utils::Shared;
 
 pub(super) type Body = Either<Full<Bytes>, StreamBody<BodyStream>>;
@@ -17,12 +21,34 @@ pub(super) type Body = Either<Full<Bytes>, StreamBody<BodyStream>>;
 pub(super) struct HttpResponse {
     /// The body of the HTTP response represented as a `Body` object.
     pub body: Body,
+
+    /// The headers sent along with the body.
+    pub headers: HeaderMap,
+
+    /// The status of the response, `200 OK` unless set otherwise.
+    pub status: StatusCode,
+}
+
+impl HttpResponse {
+    /// Adds a header to the response.
+    pub fn with_header<K: IntoHeaderName>(mut self, name: K, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Sets the status of the response.
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
 }
 
 impl From<Bytes> for HttpResponse {
     fn from(data: Bytes) -> Self {
         Self {
             body: Either::Left(Full::new(data)),
+            headers: HeaderMap::new(),
+            status: StatusCode::OK,
         }
     }
 }
@@ -49,6 +75,8 @@ impl From<BodyStream> for HttpResponse {
     fn from(stream: BodyStream) -> Self {
         Self {
             body: Either::Right(stream.into()),
+            headers: HeaderMap::new(),
+            status: StatusCode::OK,
         }
     }
 }
@@ -56,37 +84,50 @@ impl From<BodyStream> for HttpResponse {
 impl Into<Response<Body>> for HttpResponse {
     fn into(self) -> Response<Body> {
         let mut response = Response::new(self.body);
-        *response.status_mut() = StatusCode::OK;
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers;
         response
     }
 }
 
+/// A response body sent in chunks sliced out of the data, without copying it.
 #[derive(Debug)]
 pub(super) struct BodyStream {
-    data: Data,
+    data: Bytes,
     buf_pos: usize,
+    chunk_size: usize,
 }
 
-#[allow(unused)]
 impl BodyStream {
-    fn new(data: Data) -> Self {
-        Self { data, buf_pos: 0 }
+    fn new(data: Bytes) -> Self {
+        Self {
+            data,
+            buf_pos: 0,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// Sets the maximum size of the chunks the data is sent in.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
     }
 }
 
 impl From<Bytes> for BodyStream {
     fn from(data: Bytes) -> Self {
-        Self::new(Data::Owned(data))
+        Self::new(data)
     }
 }
 
 impl From<Shared<Bytes>> for BodyStream {
     fn from(data: Shared<Bytes>) -> Self {
-        Self::new(Data::Shared(data))
+        // Cloning `Bytes` only increments its reference count.
+        Self::new(Bytes::clone(&data))
     }
 }
 
-const CHUNK_SIZE: usize = 4096;
+const DEFAULT_CHUNK_SIZE: usize = 4096;
 
 impl Stream for BodyStream {
     type Item = Result<Frame<Bytes>, Infallible>;
@@ -96,11 +137,10 @@ impl Stream for BodyStream {
             return Poll::Ready(None);
         }
 
-        let remaining_data = &self.data[self.buf_pos..];
-        let bytes_to_read = std::cmp::min(CHUNK_SIZE, remaining_data.len());
-        let buf: Bytes = Bytes::copy_from_slice(&remaining_data[..bytes_to_read]);
+        let end = std::cmp::min(self.buf_pos + self.chunk_size, self.data.len());
+        let buf = self.data.slice(self.buf_pos..end);
 
-        self.buf_pos += bytes_to_read;
+        self.buf_pos = end;
 
         Poll::Ready(Some(Ok(Frame::data(buf))))
     }
@@ -112,19 +152,32 @@ impl Into<StreamBody<BodyStream>> for BodyStream {
     }
 }
 
-#[derive(Debug)]
-enum Data {
-    Owned(Bytes),
-    Shared(Shared<Bytes>),
-}
-
-impl std::ops::Deref for Data {
-    type Target = [u8];
-
-    fn deref(&self) -> &Self::Target {
-        match self {
-            Data::Owned(bytes) => bytes,
-            Data::Shared(bytes) => bytes,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_body_stream_slices_source() {
+        let data = Bytes::from((0..10_000u32).map(|i| i as u8).collect::<Vec<_>>());
+        let stream = BodyStream::from(Shared::new(data.clone())).chunk_size(4096);
+
+        let chunks = stream
+            .map(|frame| frame.unwrap().into_data().unwrap())
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(
+            chunks.iter().map(Bytes::len).collect::<Vec<_>>(),
+            [4096, 4096, 1808]
+        );
+        assert_eq!(chunks.concat(), data);
+
+        // Every chunk points into the source buffer, so none was copied.
+        let mut offset = 0;
+        for chunk in &chunks {
+            assert_eq!(chunk.as_ptr(), data[offset..].as_ptr());
+            offset += chunk.len();
         }
     }
 }
@@ -0,0 +1,189 @@
+//! A check of a running tracker through its own HTTP endpoints, meant to be run on boot.
+//!
+//! It announces a synthetic peer of a synthetic torrent, scrapes that torrent, and
+//! announces the peer as stopped, then removes the torrent. A response that is not a
+//! well-formed bencoded dictionary, or is a tracker failure, points at a broken serialization
+//! or storage setup before any traffic is taken.
+
+use crate::{
+    constants,
+    models::common::InfoHash,
+    worker::{Task, Worker},
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::sleep,
+};
+
+/// The infohash of the synthetic torrent, chosen to be readable in the storage.
+const SELFTEST_INFOHASH: &str = "ts-tracker-selftest0";
+
+/// The peer id of the synthetic peer.
+const SELFTEST_PEER_ID: &str = "-TS0001-selftest0000";
+
+/// The number of times to try connecting while the server may still be binding.
+const CONNECT_ATTEMPTS: u32 = 20;
+
+const CONNECT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Runs the self-test against the HTTP server listening on `addr`, removing the synthetic
+/// torrent through `worker` afterwards. Returns a description of the first failure, if any.
+pub async fn self_test(addr: SocketAddr, worker: &Worker) -> Result<(), String> {
+    let checks = check_endpoints(loopback_if_unspecified(addr)).await;
+
+    let info_hash = InfoHash(SELFTEST_INFOHASH.as_bytes().try_into().unwrap());
+    let removal = worker
+        .work(Task::RemoveTorrent(info_hash))
+        .await
+        .map(|_| ())
+        .map_err(|err| format!("removing the synthetic torrent failed: {:?}", err));
+
+    checks.and(removal)
+}
+
+async fn check_endpoints(addr: SocketAddr) -> Result<(), String> {
+    let announce = format!(
+        "/announce?info_hash={}&peer_id={}&port=6881&uploaded=0&downloaded=0&left=0&compact=1",
+        SELFTEST_INFOHASH, SELFTEST_PEER_ID
+    );
+
+    let body = get(addr, &announce).await?;
+    check_dict(&body, "interval", "announce")?;
+
+    let body = get(addr, &format!("/scrape?info_hash={}", SELFTEST_INFOHASH)).await?;
+    check_dict(&body, "files", "scrape")?;
+
+    let body = get(addr, &format!("{}&event=stopped", announce)).await?;
+    check_dict(&body, "interval", "stopped announce")?;
+
+    Ok(())
+}
+
+/// The address to connect to for a server listening on all interfaces.
+fn loopback_if_unspecified(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => (Ipv4Addr::LOCALHOST, addr.port()).into(),
+        IpAddr::V6(ip) if ip.is_unspecified() => (Ipv6Addr::LOCALHOST, addr.port()).into(),
+        _ => addr,
+    }
+}
+
+/// Sends a `GET` request for `path` and returns the body of a `200 OK` response.
+async fn get(addr: SocketAddr, path: &str) -> Result<Vec<u8>, String> {
+    let mut stream = connect(addr).await?;
+
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, addr
+    );
+
+    let mut response = Vec::new();
+    let exchange = async {
+        stream.write_all(request.as_bytes()).await?;
+        stream.read_to_end(&mut response).await
+    };
+
+    exchange
+        .await
+        .map_err(|err| format!("GET {} failed: {}", path, err))?;
+
+    let split = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| format!("GET {} returned a malformed response", path))?;
+
+    let status_line = response[..split]
+        .split(|b| *b == b'\n')
+        .next()
+        .unwrap_or_default();
+    if !status_line.starts_with(b"HTTP/1.1 200") {
+        return Err(format!(
+            "GET {} returned {}",
+            path,
+            String::from_utf8_lossy(status_line).trim()
+        ));
+    }
+
+    Ok(response.split_off(split + 4))
+}
+
+async fn connect(addr: SocketAddr) -> Result<TcpStream, String> {
+    let mut attempts = 0;
+    loop {
+        match TcpStream::connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(err) if attempts + 1 >= CONNECT_ATTEMPTS => {
+                return Err(format!("could not connect to {}: {}", addr, err))
+            }
+            Err(_) => {
+                attempts += 1;
+                sleep(CONNECT_RETRY_DELAY).await;
+            }
+        }
+    }
+}
+
+/// Checks that `body` is a bencoded dictionary holding `key`.
+///
+/// A tracker failure is a failed check, as the handlers answer storage and worker errors
+/// with one.
+fn check_dict(body: &[u8], key: &str, name: &str) -> Result<(), String> {
+    let is_dict = body.first() == Some(&b'd') && body.last() == Some(&b'e');
+    let has_key = |key: &str| {
+        let key = format!("{}:{}", key.len(), key);
+        body.windows(key.len()).any(|w| w == key.as_bytes())
+    };
+
+    let problem = match (is_dict, has_key(constants::TRACKER_RESPONSE_FAILURE_REASON)) {
+        (true, true) => "a failure",
+        (true, false) if has_key(key) => return Ok(()),
+        _ => "a malformed response",
+    };
+
+    Err(format!(
+        "{} returned {}: {}",
+        name,
+        problem,
+        String::from_utf8_lossy(body)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::TSConfig, servers::http::start_test_server};
+
+    #[tokio::test]
+    async fn test_self_test_memory_storage() {
+        let (addr, state, _stop_tx) = start_test_server(TSConfig::new().unwrap()).await;
+
+        assert_eq!(self_test(addr, &state.worker).await, Ok(()));
+
+        // The synthetic torrent is gone afterwards.
+        let body = get(addr, &format!("/scrape?info_hash={}", SELFTEST_INFOHASH)).await;
+        assert_eq!(body.unwrap(), b"d5:filesdee");
+    }
+
+    #[tokio::test]
+    async fn test_self_test_tracker_failure() {
+        let mut config = TSConfig::new().unwrap();
+        config.tracker.auto_register_torrent = false;
+        let (addr, state, _stop_tx) = start_test_server(config).await;
+
+        let err = self_test(addr, &state.worker).await.unwrap_err();
+        assert!(err.starts_with("announce returned a failure"), "{}", err);
+    }
+
+    #[test]
+    fn test_check_dict() {
+        assert!(check_dict(b"d8:intervali1800ee", "interval", "announce").is_ok());
+        assert!(check_dict(b"d14:failure reason4:nopee", "interval", "announce").is_err());
+        assert!(check_dict(b"d5:filesdee", "interval", "announce").is_err());
+        assert!(check_dict(b"", "files", "scrape").is_err());
+    }
+}
@@ -7,7 +7,7 @@ use crate::worker::Worker;
 use std::sync::Arc;
 
 use self::cache::Cache;
-pub use self::http::HttpServer;
+pub use self::http::{self_test, HttpServer};
 pub use self::udp::UdpServer;
 
 #[derive(Clone)]
@@ -22,7 +22,7 @@ impl State {
         State {
             worker,
             config,
-            cache: Arc::new(Cache::new()),
+            cache: Arc::new(Cache::new(config.max_full_scrape_refreshes())),
         }
     }
 }
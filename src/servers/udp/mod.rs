@@ -1 +1,398 @@
-pub struct UdpServer {}
+mod protocol;
+
+use bytes::BytesMut;
+use log::{debug, error, info};
+use sha2::{Digest, Sha256};
+use socket2::{Protocol as SocketProtocol, Socket};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+use tokio::net::UdpSocket;
+use ts_utils::time::Clock;
+
+use self::protocol::{ParseError, Request, RequestHeader};
+use super::State;
+use crate::{
+    constants,
+    models::{
+        common::{IpType, Protocol},
+        tracker::AnnounceResponse,
+    },
+    signals::StopSignalRx,
+    utils::metrics::METRICS,
+    worker::Task,
+};
+
+/// The largest datagram read from the socket. Requests are much shorter, the rest is dropped.
+const MAX_DATAGRAM_SIZE: usize = 1500;
+
+/// The length of the window a connection id is issued in. An id is accepted in the window it
+/// was issued in and the next one, so for one to two minutes as recommended by BEP 15.
+const CONNECTION_ID_WINDOW_SECS: u64 = 60;
+
+pub struct UdpServer {
+    state: State,
+    socket: Arc<UdpSocket>,
+}
+
+impl UdpServer {
+    /// Binds the socket of the server, so that a failure is reported before it starts.
+    pub fn bind(state: State) -> Result<UdpServer, Box<dyn std::error::Error + Send + Sync>> {
+        let config = &state.config;
+        let ip: IpAddr = config.udp_host().parse()?;
+
+        let addr = SocketAddr::from((ip, config.udp_port()));
+        let socket = bind_socket(addr, config.udp_recv_buffer_size())?;
+        let socket = Arc::new(UdpSocket::from_std(socket)?);
+
+        info!("Listening on udp://{}", addr);
+
+        Ok(UdpServer { state, socket })
+    }
+
+    pub async fn start(
+        &self,
+        mut stop_signal_rx: StopSignalRx,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let config = &self.state.config;
+        let connection_ids = ConnectionIds::new(config.udp_secret_key().map(String::as_str));
+        let tasks: Vec<_> = (0..config.udp_workers())
+            .map(|_| {
+                let socket = self.socket.clone();
+                let state = self.state.clone();
+                let connection_ids = connection_ids.clone();
+                tokio::spawn(recv_loop(socket, state, connection_ids))
+            })
+            .collect();
+
+        let _ = stop_signal_rx.changed().await?;
+
+        info!("Shutting down udp server...");
+
+        for task in tasks {
+            task.abort();
+            let _ = task.await;
+        }
+
+        Ok(())
+    }
+}
+
+/// Creates a non-blocking socket bound to `addr`, with a receive buffer of `recv_buffer_size`
+/// bytes unless it is `0`.
+fn bind_socket(addr: SocketAddr, recv_buffer_size: usize) -> std::io::Result<std::net::UdpSocket> {
+    let domain = if addr.is_ipv6() {
+        socket2::Domain::IPV6
+    } else {
+        socket2::Domain::IPV4
+    };
+
+    let socket = Socket::new(domain, socket2::Type::DGRAM, Some(SocketProtocol::UDP))?;
+
+    if recv_buffer_size > 0 {
+        socket.set_recv_buffer_size(recv_buffer_size)?;
+    }
+
+    socket.bind(&addr.into())?;
+
+    let socket: std::net::UdpSocket = socket.into();
+    socket.set_nonblocking(true)?;
+
+    Ok(socket)
+}
+
+/// Receives datagrams and answers them one at a time. Several loops share the socket.
+async fn recv_loop(socket: Arc<UdpSocket>, state: State, connection_ids: ConnectionIds) {
+    let mut datagram = [0; MAX_DATAGRAM_SIZE];
+    let mut response = BytesMut::with_capacity(MAX_DATAGRAM_SIZE);
+
+    loop {
+        let (len, addr) = match socket.recv_from(&mut datagram).await {
+            Ok(received) => received,
+            Err(err) => {
+                error!("udp receive error: {}", err);
+                continue;
+            }
+        };
+
+        response.clear();
+        handle_datagram(
+            &datagram[..len],
+            addr,
+            &state,
+            &connection_ids,
+            &mut response,
+        )
+        .await;
+
+        if response.is_empty() {
+            continue;
+        }
+
+        if let Err(err) = socket.send_to(&response, addr).await {
+            debug!("failed to send udp response to {}: {}", addr, err);
+        }
+    }
+}
+
+/// Writes the answer to a datagram into `response`, leaving it empty if there is none.
+async fn handle_datagram(
+    datagram: &[u8],
+    addr: SocketAddr,
+    state: &State,
+    connection_ids: &ConnectionIds,
+    response: &mut BytesMut,
+) {
+    let request = match protocol::parse_request(datagram) {
+        Ok(request) => request,
+        Err(ParseError::Ignored) => return,
+        Err(ParseError::Malformed(msg)) => {
+            // A malformed request holds the whole header, with its transaction id.
+            let transaction_id = u32::from_be_bytes(datagram[12..16].try_into().unwrap());
+            protocol::write_error_response(transaction_id, msg, response);
+            return;
+        }
+    };
+
+    let (header, req) = match request {
+        Request::Connect(RequestHeader { transaction_id, .. }) => {
+            let connection_id = connection_ids.issue(&addr);
+            protocol::write_connect_response(transaction_id, connection_id, response);
+            return;
+        }
+        Request::Announce(header, req) => (header, req),
+    };
+
+    let transaction_id = header.transaction_id;
+    let refusal = if !connection_ids.is_valid(header.connection_id, &addr) {
+        Some(constants::TRACKER_ERROR_INVALID_CONNECTION_ID)
+    } else if !state.config.allow_udp_announce() {
+        Some(constants::TRACKER_ERROR_UDP_ANNOUNCE_NOT_ALLOWED)
+    } else {
+        None
+    };
+
+    if let Some(msg) = refusal {
+        protocol::write_error_response(transaction_id, msg, response);
+        return;
+    }
+
+    METRICS.announces.increment(Protocol::Udp);
+
+    let task = Task::Announce((*req, addr.ip().to_canonical()));
+    match state.worker.work(task).await {
+        Ok(output) => {
+            let announce = AnnounceResponse::from(output);
+            let ip_type = IpType::from(addr.ip().to_canonical());
+            protocol::write_announce_response(transaction_id, &announce, ip_type, response);
+        }
+        Err(err) => {
+            debug!("udp announce failed: {:?}", err);
+            let msg = err
+                .tracker_error()
+                .unwrap_or(constants::INTERNAL_SERVER_ERROR);
+            protocol::write_error_response(transaction_id, msg, response);
+        }
+    }
+}
+
+/// Issues connection ids derived from the client address and the time, so that no state is
+/// kept for them. An id is the truncated HMAC-SHA256 of both, keyed with the secret key or a
+/// random one. With a secret key, every server sharing it accepts the ids of the others.
+#[derive(Clone)]
+struct ConnectionIds {
+    key: Arc<[u8]>,
+}
+
+impl ConnectionIds {
+    fn new(secret_key: Option<&str>) -> Self {
+        let key = match secret_key {
+            Some(key) => key.as_bytes().to_vec(),
+            None => {
+                let mut key = vec![0; HMAC_BLOCK_SIZE];
+                getrandom::getrandom(&mut key).expect("failed to generate a connection id key");
+                key
+            }
+        };
+
+        Self { key: key.into() }
+    }
+
+    fn issue(&self, addr: &SocketAddr) -> u64 {
+        self.connection_id(addr, Self::current_window())
+    }
+
+    fn is_valid(&self, connection_id: u64, addr: &SocketAddr) -> bool {
+        let window = Self::current_window();
+        connection_id == self.connection_id(addr, window)
+            || connection_id == self.connection_id(addr, window.saturating_sub(1))
+    }
+
+    fn connection_id(&self, addr: &SocketAddr, window: u64) -> u64 {
+        let mut message = Vec::with_capacity(26);
+        match addr.ip().to_canonical() {
+            IpAddr::V4(ip) => message.extend_from_slice(&ip.octets()),
+            IpAddr::V6(ip) => message.extend_from_slice(&ip.octets()),
+        }
+        message.extend_from_slice(&addr.port().to_be_bytes());
+        message.extend_from_slice(&window.to_be_bytes());
+
+        let mac = hmac_sha256(&self.key, &message);
+        u64::from_be_bytes(mac[..8].try_into().unwrap())
+    }
+
+    fn current_window() -> u64 {
+        Clock::now_since_epoch().as_secs() / CONNECTION_ID_WINDOW_SECS
+    }
+}
+
+/// The block size of SHA-256, which HMAC pads its key to.
+const HMAC_BLOCK_SIZE: usize = 64;
+
+/// Computes the HMAC-SHA256 of `message` (RFC 2104).
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0; HMAC_BLOCK_SIZE];
+    if key.len() > HMAC_BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    inner.update(message);
+
+    let mut outer = Sha256::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::TSConfig, worker::Worker};
+    use bytes::{Buf, BufMut};
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_recv_buffer_size_applied() {
+        let recv_buffer_size = 32 * 1024;
+        let socket = bind_socket("127.0.0.1:0".parse().unwrap(), recv_buffer_size).unwrap();
+
+        // Linux reports twice the requested size, for its bookkeeping overhead.
+        let socket = Socket::from(socket);
+        assert!(socket.recv_buffer_size().unwrap() >= recv_buffer_size);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_workers_answer_concurrent_datagrams() {
+        let mut config = TSConfig::new().unwrap();
+        config.server.udp.workers = 4;
+        let config = Arc::new(config);
+
+        let mut worker = Worker::new(config.clone());
+        let _worker_job = worker.start();
+        let state = State::new(Arc::new(worker), config.clone());
+
+        let socket = bind_socket("127.0.0.1:0".parse().unwrap(), 0).unwrap();
+        let socket = Arc::new(UdpSocket::from_std(socket).unwrap());
+        let addr = socket.local_addr().unwrap();
+
+        let connection_ids = ConnectionIds::new(None);
+        for _ in 0..config.udp_workers() {
+            let socket = socket.clone();
+            tokio::spawn(recv_loop(socket, state.clone(), connection_ids.clone()));
+        }
+
+        // Every client gets its own connection id, answered by whichever loop received it.
+        let clients = (0..16_u32).map(|transaction_id| {
+            let connection_ids = connection_ids.clone();
+            async move {
+                let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                let mut request = BytesMut::new();
+                request.put_u64(protocol::PROTOCOL_ID);
+                request.put_u32(protocol::ACTION_CONNECT);
+                request.put_u32(transaction_id);
+                client.send_to(&request, addr).await.unwrap();
+
+                let mut buf = [0; 16];
+                let len = timeout(Duration::from_secs(5), client.recv(&mut buf))
+                    .await
+                    .expect("no connect response")
+                    .unwrap();
+
+                let mut response = &buf[..len];
+                assert_eq!(response.get_u32(), protocol::ACTION_CONNECT);
+                assert_eq!(response.get_u32(), transaction_id);
+
+                let client_addr = client.local_addr().unwrap();
+                assert!(connection_ids.is_valid(response.get_u64(), &client_addr));
+            }
+        });
+
+        futures::future::join_all(clients).await;
+    }
+
+    #[tokio::test]
+    async fn test_junk_datagrams_dropped() {
+        let config = Arc::new(TSConfig::new().unwrap());
+        let state = State::new(Arc::new(Worker::new(config.clone())), config);
+        let connection_ids = ConnectionIds::new(None);
+        let addr: SocketAddr = "127.0.0.1:6881".parse().unwrap();
+
+        // A connect without the protocol id is not answered, nor is a datagram too short.
+        let mut datagram = BytesMut::new();
+        datagram.put_u64(0x1234);
+        datagram.put_u32(protocol::ACTION_CONNECT);
+        datagram.put_u32(7);
+
+        for datagram in [&datagram[..], &datagram[..8]] {
+            let mut response = BytesMut::new();
+            handle_datagram(datagram, addr, &state, &connection_ids, &mut response).await;
+            assert!(response.is_empty());
+        }
+
+        // An unsupported action is answered with an error.
+        datagram[8..12].copy_from_slice(&5_u32.to_be_bytes());
+        let mut response = BytesMut::new();
+        handle_datagram(&datagram, addr, &state, &connection_ids, &mut response).await;
+        assert_eq!(response.get_u32(), protocol::ACTION_ERROR);
+    }
+
+    #[test]
+    fn test_connection_ids() {
+        let addr: SocketAddr = "127.0.0.1:6881".parse().unwrap();
+        let other: SocketAddr = "127.0.0.1:6882".parse().unwrap();
+
+        let connection_ids = ConnectionIds::new(Some("secret"));
+        let connection_id = connection_ids.issue(&addr);
+        assert!(connection_ids.is_valid(connection_id, &addr));
+        assert!(!connection_ids.is_valid(connection_id, &other));
+
+        // Servers sharing the secret key accept each other's ids.
+        assert!(ConnectionIds::new(Some("secret")).is_valid(connection_id, &addr));
+        assert!(!ConnectionIds::new(Some("other")).is_valid(connection_id, &addr));
+    }
+
+    #[test]
+    fn test_hmac_sha256() {
+        // Test case 1 of RFC 4231.
+        let mac = hmac_sha256(&[0x0b; 20], b"Hi There");
+        let expected = "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7";
+        let hex: String = mac.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(hex, expected);
+
+        // Keys longer than a block are hashed first (test case 6).
+        let mac = hmac_sha256(
+            &[0xaa; 131],
+            b"Test Using Larger Than Block-Size Key - Hash Key First",
+        );
+        let expected = "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54";
+        let hex: String = mac.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(hex, expected);
+    }
+}
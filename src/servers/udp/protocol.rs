@@ -0,0 +1,283 @@
+//! The packet formats of the UDP tracker protocol.
+//! More information: https://www.bittorrent.org/beps/bep_0015.html
+
+use bytes::{Buf, BufMut, BytesMut};
+
+use crate::models::{
+    common::{
+        InfoHash, IpType, NumOfBytes, PeerId, PeerKey, Port, INFOHASH_LENGTH, PEER_ID_LENGTH,
+    },
+    tracker::{AnnounceEvent, AnnounceRequest, AnnounceResponse, ResponsePeerList},
+};
+
+/// The magic `connection_id` of a connect request.
+pub const PROTOCOL_ID: u64 = 0x41727101980;
+
+pub const ACTION_CONNECT: u32 = 0;
+pub const ACTION_ANNOUNCE: u32 = 1;
+pub const ACTION_ERROR: u32 = 3;
+
+/// The length of the `connection_id`, `action` and `transaction_id` every request starts with.
+const HEADER_LENGTH: usize = 16;
+
+/// The length of an announce request, up to and including the port.
+const ANNOUNCE_LENGTH: usize = 98;
+
+/// The header shared by all requests.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct RequestHeader {
+    pub connection_id: u64,
+    pub action: u32,
+    pub transaction_id: u32,
+}
+
+#[derive(Debug)]
+pub enum Request {
+    Connect(RequestHeader),
+    Announce(RequestHeader, Box<AnnounceRequest>),
+}
+
+/// Why a datagram is not a request to answer.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// The datagram is not a request of the protocol, such as a connect without the protocol
+    /// id. It is dropped unanswered, so that the tracker doesn't reflect junk to the address
+    /// it came from.
+    Ignored,
+    /// The request is malformed, which the client is answered with.
+    Malformed(&'static str),
+}
+
+/// Parses a request datagram. Datagrams shorter than their action requires are rejected.
+///
+/// The `ip` field of an announce is ignored, the peer being registered with the address the
+/// datagram came from.
+pub fn parse_request(mut bytes: &[u8]) -> Result<Request, ParseError> {
+    if bytes.len() < HEADER_LENGTH {
+        return Err(ParseError::Ignored);
+    }
+
+    let header = RequestHeader {
+        connection_id: bytes.get_u64(),
+        action: bytes.get_u32(),
+        transaction_id: bytes.get_u32(),
+    };
+
+    match header.action {
+        ACTION_CONNECT if header.connection_id != PROTOCOL_ID => Err(ParseError::Ignored),
+        ACTION_CONNECT => Ok(Request::Connect(header)),
+        ACTION_ANNOUNCE if bytes.len() < ANNOUNCE_LENGTH - HEADER_LENGTH => {
+            Err(ParseError::Malformed("announce datagram too short"))
+        }
+        ACTION_ANNOUNCE => Ok(Request::Announce(header, Box::new(parse_announce(bytes)))),
+        _ => Err(ParseError::Malformed("unsupported action")),
+    }
+}
+
+/// Parses the body of an announce request, which must hold `ANNOUNCE_LENGTH` bytes.
+fn parse_announce(mut bytes: &[u8]) -> AnnounceRequest {
+    let mut info_hash = [0; INFOHASH_LENGTH];
+    bytes.copy_to_slice(&mut info_hash);
+
+    let mut peer_id = [0; PEER_ID_LENGTH];
+    bytes.copy_to_slice(&mut peer_id);
+
+    let downloaded = NumOfBytes(bytes.get_u64());
+    let left = Some(NumOfBytes(bytes.get_u64()));
+    let uploaded = NumOfBytes(bytes.get_u64());
+    let event = AnnounceEvent::from(bytes.get_u32());
+    let _ip = bytes.get_u32();
+    let key = bytes.get_u32().to_be_bytes();
+
+    // A numwant of -1 asks for the default.
+    let numwant = match bytes.get_i32() {
+        numwant if numwant < 0 => None,
+        numwant => Some(numwant as u32),
+    };
+
+    AnnounceRequest {
+        info_hash: InfoHash(info_hash),
+        port: Port(bytes.get_u16()),
+        peer_id: PeerId::from(peer_id),
+        uploaded,
+        downloaded,
+        left,
+        compact: true,
+        no_peer_id: true,
+        event: Some(event),
+        numwant,
+        supportcrypto: false,
+        requirecrypto: false,
+        key: PeerKey(Some(key)),
+        trackerid: None,
+        ip: None,
+    }
+}
+
+/// Writes the response to a connect request.
+pub fn write_connect_response(transaction_id: u32, connection_id: u64, buf: &mut BytesMut) {
+    buf.put_u32(ACTION_CONNECT);
+    buf.put_u32(transaction_id);
+    buf.put_u64(connection_id);
+}
+
+/// Writes the response to an announce request. The `interval`, `leechers` and `seeders`
+/// are those of the HTTP response, followed by the compact peers of `ip_type`, the IP family
+/// the request came over. The protocol has no field for the minimum interval.
+///
+/// The peers of a response are all as long as an address of the family of the request, so
+/// those of the other family, returned with `return_both_ip_families`, can't be written.
+pub fn write_announce_response(
+    transaction_id: u32,
+    response: &AnnounceResponse,
+    ip_type: IpType,
+    buf: &mut BytesMut,
+) {
+    buf.put_u32(ACTION_ANNOUNCE);
+    buf.put_u32(transaction_id);
+    buf.put_u32(response.interval);
+    buf.put_u32(response.incomplete);
+    buf.put_u32(response.complete);
+
+    let peers = match ip_type {
+        IpType::V4 => &response.peers,
+        IpType::V6 => &response.peers6,
+    };
+
+    if let Some(ResponsePeerList::Compact(peers)) = peers {
+        buf.put_slice(peers);
+    }
+}
+
+/// Writes an error response with a human-readable message.
+pub fn write_error_response(transaction_id: u32, message: &str, buf: &mut BytesMut) {
+    buf.put_u32(ACTION_ERROR);
+    buf.put_u32(transaction_id);
+    buf.put_slice(message.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::TSConfig,
+        models::peer::{PEER_ADDR_V4_LENGTH, PEER_ADDR_V6_LENGTH},
+        worker::{Task, Worker},
+    };
+    use std::sync::Arc;
+
+    fn announce_datagram(transaction_id: u32, event: u32, numwant: i32) -> BytesMut {
+        let mut buf = BytesMut::new();
+        buf.put_u64(0x1234);
+        buf.put_u32(ACTION_ANNOUNCE);
+        buf.put_u32(transaction_id);
+        buf.put_slice(&[b'a'; INFOHASH_LENGTH]);
+        buf.put_slice(&[b'b'; PEER_ID_LENGTH]);
+        buf.put_u64(10);
+        buf.put_u64(20);
+        buf.put_u64(30);
+        buf.put_u32(event);
+        buf.put_u32(0);
+        buf.put_u32(0xdeadbeef);
+        buf.put_i32(numwant);
+        buf.put_u16(6881);
+        buf
+    }
+
+    #[test]
+    fn test_parse_request_framing() {
+        let mut buf = BytesMut::new();
+        buf.put_u64(PROTOCOL_ID);
+        buf.put_u32(ACTION_CONNECT);
+        buf.put_u32(7);
+
+        match parse_request(&buf) {
+            Ok(Request::Connect(header)) => assert_eq!(header.transaction_id, 7),
+            other => panic!("expected a connect request, got {:?}", other),
+        }
+
+        assert_eq!(
+            parse_request(&buf[..HEADER_LENGTH - 1]).unwrap_err(),
+            ParseError::Ignored
+        );
+
+        // A connect without the protocol id is dropped rather than answered.
+        buf[..8].copy_from_slice(&0x1234_u64.to_be_bytes());
+        assert_eq!(parse_request(&buf).unwrap_err(), ParseError::Ignored);
+
+        let datagram = announce_datagram(7, 2, -1);
+        assert_eq!(datagram.len(), ANNOUNCE_LENGTH);
+        assert!(matches!(
+            parse_request(&datagram[..ANNOUNCE_LENGTH - 1]),
+            Err(ParseError::Malformed(_))
+        ));
+
+        let (header, req) = match parse_request(&datagram) {
+            Ok(Request::Announce(header, req)) => (header, req),
+            other => panic!("expected an announce request, got {:?}", other),
+        };
+
+        assert_eq!(header.connection_id, 0x1234);
+        assert_eq!(req.info_hash, InfoHash([b'a'; INFOHASH_LENGTH]));
+        assert_eq!(req.downloaded, NumOfBytes(10));
+        assert_eq!(req.left, Some(NumOfBytes(20)));
+        assert_eq!(req.uploaded, NumOfBytes(30));
+        assert_eq!(req.event, Some(AnnounceEvent::Started));
+        assert_eq!(req.numwant, None);
+        assert_eq!(req.port, Port(6881));
+        assert_eq!(*req.key, Some(0xdeadbeef_u32.to_be_bytes()));
+    }
+
+    #[tokio::test]
+    async fn test_announce_round_trip() {
+        let config = Arc::new(TSConfig::new().unwrap());
+        let mut worker = Worker::new(config.clone());
+        let _worker_job = worker.start();
+
+        // A seeder announces, then a leecher that gets the seeder in its response.
+        let mut responses = Vec::new();
+        for (peer_id, left) in [(b'b', 0), (b'c', 100)] {
+            let mut datagram = announce_datagram(42, 2, 10);
+            datagram[36..56].copy_from_slice(&[peer_id; PEER_ID_LENGTH]);
+            datagram[64..72].copy_from_slice(&u64::to_be_bytes(left));
+
+            let req = match parse_request(&datagram).unwrap() {
+                Request::Announce(_, req) => *req,
+                other => panic!("expected an announce request, got {:?}", other),
+            };
+
+            let task = Task::Announce((req, "127.0.0.1".parse().unwrap()));
+            let output = worker.work(task).await.unwrap();
+            responses.push(AnnounceResponse::from(output));
+        }
+
+        let mut buf = BytesMut::new();
+        write_announce_response(42, &responses[1], IpType::V4, &mut buf);
+
+        let mut bytes = &buf[..];
+        assert_eq!(bytes.get_u32(), ACTION_ANNOUNCE);
+        assert_eq!(bytes.get_u32(), 42);
+        assert_eq!(bytes.get_u32(), config.announce_interval());
+        assert_eq!(bytes.get_u32(), 1); // leechers
+        assert_eq!(bytes.get_u32(), 1); // seeders
+        assert_eq!(bytes.len(), PEER_ADDR_V4_LENGTH);
+    }
+
+    #[test]
+    fn test_announce_response_peers_of_request_family() {
+        let response = AnnounceResponse {
+            peers: Some(ResponsePeerList::Compact(vec![4; PEER_ADDR_V4_LENGTH])),
+            peers6: Some(ResponsePeerList::Compact(vec![6; PEER_ADDR_V6_LENGTH])),
+            ..Default::default()
+        };
+
+        for (ip_type, peers) in [
+            (IpType::V4, vec![4; PEER_ADDR_V4_LENGTH]),
+            (IpType::V6, vec![6; PEER_ADDR_V6_LENGTH]),
+        ] {
+            let mut buf = BytesMut::new();
+            write_announce_response(42, &response, ip_type, &mut buf);
+            assert_eq!(&buf[20..], &peers[..]);
+        }
+    }
+}
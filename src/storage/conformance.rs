@@ -0,0 +1,530 @@
+//! The behavior every `Storage` must share, as tests run against each backend by the
+//! `test_storage_conformance!` macro.
+//!
+//! The tests only assert what a fresh storage and one reused across runs, such as a Redis
+//! server, both observe: each clears its own swarms first and compares completed counts to
+//! those it started with.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+use ts_utils::time::{Clock, Duration};
+
+use super::{PeerExtractor, Processor, Storage};
+use crate::models::{
+    common::{InfoHash, IpType, PeerId, Port, INFOHASH_LENGTH, PEER_ID_LENGTH},
+    peer::{Peer, PeerAddr, PeerType},
+    torrent::{PeerDict, PeerIdKey, PeerList, Torrent, TorrentStats, TorrentStatsList},
+};
+
+/// Generates a test per function of this module, against the storage `$create` evaluates to.
+/// The attributes are added to each test.
+macro_rules! test_storage_conformance {
+    (@tests [$(#[$attr:meta])*] $create:expr, $($test:ident,)+) => {
+        $(
+            $(#[$attr])*
+            #[tokio::test]
+            async fn $test() {
+                conformance::$test($create).await;
+            }
+        )+
+    };
+
+    ($(#[$attr:meta])* $create:expr) => {
+        mod conformance {
+            use super::*;
+            use crate::storage::conformance;
+
+            test_storage_conformance!(
+                @tests [$(#[$attr])*] $create,
+                insert_torrent_idempotent,
+                insert_torrents_idempotent,
+                has_and_remove_torrent,
+                torrent_stats,
+                update_or_put_peer,
+                update_or_put_peer_type_change,
+                reannounce_updates_last_seen,
+                promote_peer,
+                remove_and_clear_peers,
+                list_swarm,
+                get_peer_with_key,
+                extract_peers,
+                all_torrent_stats,
+            );
+        }
+    };
+}
+
+pub async fn insert_torrent_idempotent(storage: impl Storage) {
+    let info_hash = InfoHash([b'i'; INFOHASH_LENGTH]);
+
+    storage
+        .insert_torrent(&info_hash, torrent(5, "first"))
+        .await
+        .unwrap();
+    let registered = storage.get_torrent(&info_hash).await.unwrap().unwrap();
+
+    storage
+        .insert_torrent(&info_hash, torrent(0, "second"))
+        .await
+        .unwrap();
+    storage.insert_torrent(&info_hash, None).await.unwrap();
+
+    let torrent = storage.get_torrent(&info_hash).await.unwrap().unwrap();
+    assert_eq!(torrent, registered);
+    assert_eq!(torrent.name.as_deref(), Some("first"));
+}
+
+pub async fn insert_torrents_idempotent(storage: impl Storage) {
+    let info_hash_a = InfoHash([b'j'; INFOHASH_LENGTH]);
+    let info_hash_b = InfoHash([b'k'; INFOHASH_LENGTH]);
+
+    storage
+        .insert_torrent(&info_hash_a, torrent(3, "a"))
+        .await
+        .unwrap();
+    let registered = storage.get_torrent(&info_hash_a).await.unwrap().unwrap();
+
+    storage
+        .insert_torrents(vec![
+            (info_hash_a.clone(), None),
+            (info_hash_b.clone(), torrent(1, "b")),
+            (info_hash_b.clone(), torrent(0, "b again")),
+        ])
+        .await
+        .unwrap();
+
+    let torrent = storage.get_torrent(&info_hash_a).await.unwrap().unwrap();
+    assert_eq!(torrent, registered);
+
+    let torrent = storage.get_torrent(&info_hash_b).await.unwrap().unwrap();
+    assert_eq!(torrent.name.as_deref(), Some("b"));
+}
+
+pub async fn has_and_remove_torrent(storage: impl Storage) {
+    let info_hash = InfoHash([b'l'; INFOHASH_LENGTH]);
+    storage.remove_torrent(&info_hash).await.unwrap();
+    assert!(!storage.has_torrent(&info_hash).await.unwrap());
+    assert!(storage.get_torrent(&info_hash).await.unwrap().is_none());
+
+    storage.insert_torrent(&info_hash, None).await.unwrap();
+    assert!(storage.has_torrent(&info_hash).await.unwrap());
+
+    storage.remove_torrent(&info_hash).await.unwrap();
+    assert!(!storage.has_torrent(&info_hash).await.unwrap());
+}
+
+pub async fn torrent_stats(storage: impl Storage) {
+    let info_hash = new_torrent(&storage, b'm').await;
+    let completed = completed(&storage, &info_hash).await;
+
+    put(&storage, &info_hash, 1, IpType::V4, PeerType::Seeder).await;
+    put(&storage, &info_hash, 2, IpType::V4, PeerType::Leecher).await;
+    put(&storage, &info_hash, 3, IpType::V4, PeerType::Partial).await;
+    put(&storage, &info_hash, 4, IpType::V6, PeerType::Leecher).await;
+
+    let stats = storage
+        .get_torrent_stats(&info_hash, IpType::V4)
+        .await
+        .unwrap();
+    assert_eq!((stats.seeders, stats.incomplete), (1, 2));
+
+    let stats = storage
+        .get_multi_torrent_stats(vec![info_hash.clone()], IpType::V6)
+        .await
+        .unwrap();
+    assert_eq!(stats.len(), 1);
+    assert_eq!((stats[0].1.seeders, stats[0].1.incomplete), (0, 1));
+
+    let stats = storage
+        .get_torrent_stats_multi_ip(vec![info_hash.clone(), info_hash.clone()])
+        .await
+        .unwrap();
+    assert_eq!(stats.len(), 2);
+    for (_, stats) in stats {
+        assert_eq!((stats.seeders, stats.incomplete), (1, 3));
+        assert_eq!(stats.completed, completed);
+    }
+}
+
+pub async fn update_or_put_peer(storage: impl Storage) {
+    let info_hash = new_torrent(&storage, b'n').await;
+
+    // Announcing again updates the peer rather than counting it twice.
+    for _ in 0..2 {
+        let (peer_id_key, peer) = peer(1, IpType::V4);
+        storage
+            .update_or_put_peer_in_swarm(&info_hash, &peer_id_key, peer, PeerType::Leecher)
+            .await
+            .unwrap();
+    }
+
+    let (peer_id_key, _) = peer(1, IpType::V4);
+    assert!(storage
+        .has_peer_in_swarm(&info_hash, &peer_id_key, IpType::V4)
+        .await
+        .unwrap());
+    assert!(!storage
+        .has_peer_in_swarm(&info_hash, &peer_id_key, IpType::V6)
+        .await
+        .unwrap());
+
+    assert_eq!(swarm_counts(&storage, &info_hash).await, (0, 1));
+}
+
+pub async fn update_or_put_peer_type_change(storage: impl Storage) {
+    let info_hash = new_torrent(&storage, b'v').await;
+    let completed = completed(&storage, &info_hash).await;
+
+    // A leecher announcing `left=0` without an event becomes a seeder, and back.
+    for (peer_type, counts) in [
+        (PeerType::Leecher, (0, 1)),
+        (PeerType::Seeder, (1, 0)),
+        (PeerType::Leecher, (0, 1)),
+    ] {
+        let (peer_id_key, peer) = peer(1, IpType::V4);
+        storage
+            .update_or_put_peer_in_swarm(&info_hash, &peer_id_key, peer, peer_type)
+            .await
+            .unwrap();
+
+        assert_eq!(swarm_counts(&storage, &info_hash).await, counts);
+
+        let swarm = storage
+            .list_swarm(&info_hash, IpType::V4, 10)
+            .await
+            .unwrap();
+        assert_eq!(swarm.len(), 1);
+        assert_eq!(swarm[0].1, peer_type);
+    }
+
+    // Only an announce with the `completed` event counts a completion.
+    assert_eq!(self::completed(&storage, &info_hash).await, completed);
+}
+
+pub async fn reannounce_updates_last_seen(storage: impl Storage) {
+    let info_hash = new_torrent(&storage, b'u').await;
+    let (peer_id_key, mut peer) = peer(1, IpType::V4);
+
+    for last_seen in [1_700_000_000, 1_700_000_030] {
+        peer.last_seen = Duration::from_secs(last_seen);
+        storage
+            .update_or_put_peer_in_swarm(&info_hash, &peer_id_key, peer.clone(), PeerType::Leecher)
+            .await
+            .unwrap();
+
+        let swarm = storage
+            .list_swarm(&info_hash, IpType::V4, 10)
+            .await
+            .unwrap();
+        assert_eq!(swarm.len(), 1);
+        assert_eq!(swarm[0].2.last_seen.as_secs(), last_seen);
+    }
+}
+
+pub async fn promote_peer(storage: impl Storage) {
+    let info_hash = new_torrent(&storage, b'o').await;
+    let completed = completed(&storage, &info_hash).await;
+
+    put(&storage, &info_hash, 1, IpType::V4, PeerType::Leecher).await;
+
+    // A repeated `completed` finds the peer already a seeder, and is not counted.
+    for _ in 0..2 {
+        let (peer_id_key, peer) = peer(1, IpType::V4);
+        storage
+            .promote_peer_in_swarm(&info_hash, &peer_id_key, peer)
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(swarm_counts(&storage, &info_hash).await, (1, 0));
+    assert_eq!(self::completed(&storage, &info_hash).await, completed + 1);
+}
+
+pub async fn remove_and_clear_peers(storage: impl Storage) {
+    let info_hash = new_torrent(&storage, b'p').await;
+    let completed = completed(&storage, &info_hash).await;
+
+    put(&storage, &info_hash, 1, IpType::V4, PeerType::Seeder).await;
+    put(&storage, &info_hash, 2, IpType::V4, PeerType::Leecher).await;
+    put(&storage, &info_hash, 3, IpType::V6, PeerType::Leecher).await;
+
+    let (peer_id_key, _) = peer(2, IpType::V4);
+    storage
+        .remove_peer_from_swarm(&info_hash, &peer_id_key, IpType::V4)
+        .await
+        .unwrap();
+    assert!(!storage
+        .has_peer_in_swarm(&info_hash, &peer_id_key, IpType::V4)
+        .await
+        .unwrap());
+    assert_eq!(swarm_counts(&storage, &info_hash).await, (1, 1));
+
+    // Seeders are removed all the same.
+    let (peer_id_key, _) = peer(1, IpType::V4);
+    storage
+        .remove_peer_from_swarm(&info_hash, &peer_id_key, IpType::V4)
+        .await
+        .unwrap();
+    assert_eq!(swarm_counts(&storage, &info_hash).await, (0, 1));
+
+    // The torrent and its completed count outlive its swarms.
+    storage.clear_swarm(&info_hash).await.unwrap();
+    assert_eq!(swarm_counts(&storage, &info_hash).await, (0, 0));
+    assert!(storage.has_torrent(&info_hash).await.unwrap());
+    assert_eq!(self::completed(&storage, &info_hash).await, completed);
+}
+
+pub async fn get_peer_with_key(storage: impl Storage) {
+    let info_hash = new_torrent(&storage, b'w').await;
+
+    let (peer_id_key, peer) = peer(1, IpType::V4);
+    let peer = Peer {
+        key: Some(*b"abcd"),
+        ..peer
+    };
+
+    storage
+        .update_or_put_peer_in_swarm(&info_hash, &peer_id_key, peer, PeerType::Seeder)
+        .await
+        .unwrap();
+
+    let stored = storage
+        .get_peer_in_swarm(&info_hash, &peer_id_key, IpType::V4)
+        .await
+        .unwrap();
+    assert_eq!(stored.unwrap().key, Some(*b"abcd"));
+
+    assert!(storage
+        .get_peer_in_swarm(&info_hash, &peer_id_key, IpType::V6)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+pub async fn list_swarm(storage: impl Storage) {
+    let info_hash = new_torrent(&storage, b'q').await;
+
+    put(&storage, &info_hash, 1, IpType::V4, PeerType::Seeder).await;
+    put(&storage, &info_hash, 2, IpType::V4, PeerType::Leecher).await;
+    put(&storage, &info_hash, 3, IpType::V6, PeerType::Partial).await;
+
+    let mut peers = storage
+        .list_swarm(&info_hash, IpType::V4, 10)
+        .await
+        .unwrap();
+    peers.sort_by_key(|(_, peer_type, _)| peer_type.as_str());
+    let types = peers
+        .iter()
+        .map(|(_, peer_type, _)| *peer_type)
+        .collect::<Vec<_>>();
+    assert_eq!(types, [PeerType::Leecher, PeerType::Seeder]);
+
+    let peers = storage
+        .list_swarm(&info_hash, IpType::V6, 10)
+        .await
+        .unwrap();
+    assert_eq!(peers.len(), 1);
+    assert_eq!(
+        (&peers[0].0, peers[0].1),
+        (&peer(3, IpType::V6).0, PeerType::Partial)
+    );
+
+    let peers = storage.list_swarm(&info_hash, IpType::V4, 1).await.unwrap();
+    assert_eq!(peers.len(), 1);
+}
+
+pub async fn extract_peers(storage: impl Storage) {
+    let info_hash = new_torrent(&storage, b'r').await;
+
+    put(&storage, &info_hash, 1, IpType::V4, PeerType::Seeder).await;
+    put(&storage, &info_hash, 2, IpType::V4, PeerType::Leecher).await;
+    put(&storage, &info_hash, 3, IpType::V4, PeerType::Partial).await;
+
+    // Leechers are given the seeders first, then the leechers and partial seeds.
+    let mut extractor = Extractor::new(usize::MAX);
+    let stats = storage
+        .extract_peers_from_swarm(&info_hash, PeerType::Leecher, IpType::V4, &mut extractor)
+        .await
+        .unwrap();
+    assert_eq!((stats.complete, stats.incomplete), (1, 2));
+    assert_eq!(extractor.peers, [1, 2, 3].map(|i| peer(i, IpType::V4).0));
+
+    // Seeders and partial seeds are given leechers only.
+    for peer_type in [PeerType::Seeder, PeerType::Partial] {
+        let mut extractor = Extractor::new(usize::MAX);
+        storage
+            .extract_peers_from_swarm(&info_hash, peer_type, IpType::V4, &mut extractor)
+            .await
+            .unwrap();
+        assert_eq!(extractor.peers, [peer(2, IpType::V4).0]);
+    }
+
+    // An extractor that is full stops the extraction.
+    let mut extractor = Extractor::new(1);
+    storage
+        .extract_peers_from_swarm(&info_hash, PeerType::Leecher, IpType::V4, &mut extractor)
+        .await
+        .unwrap();
+    assert_eq!(extractor.peers, [peer(1, IpType::V4).0]);
+
+    let mut extractor = Extractor::new(usize::MAX);
+    let stats = storage
+        .extract_peers_from_swarm(&info_hash, PeerType::Leecher, IpType::V6, &mut extractor)
+        .await
+        .unwrap();
+    assert_eq!((stats.complete, stats.incomplete), (0, 0));
+    assert!(extractor.peers.is_empty());
+}
+
+pub async fn all_torrent_stats(storage: impl Storage) {
+    let info_hash_a = new_torrent(&storage, b's').await;
+    let info_hash_b = new_torrent(&storage, b't').await;
+
+    put(&storage, &info_hash_a, 1, IpType::V4, PeerType::Seeder).await;
+    put(&storage, &info_hash_b, 2, IpType::V6, PeerType::Leecher).await;
+
+    let mut collector = Collector::default();
+    storage.get_all_torrent_stats(&mut collector).await.unwrap();
+
+    // The storage may hold other torrents, e.g. of other tests.
+    let stats = |info_hash: &InfoHash| -> TorrentStats {
+        let mut found = collector.stats.iter().filter(|(i, _)| i == info_hash);
+        found.next().map(|(_, stats)| stats.clone()).unwrap()
+    };
+
+    let [v4, v6] = stats(&info_hash_a).by_ip_type.unwrap();
+    assert_eq!(
+        (v4.complete, v4.incomplete, v6.complete, v6.incomplete),
+        (1, 0, 0, 0)
+    );
+    assert_eq!(
+        (stats(&info_hash_a).seeders, stats(&info_hash_a).incomplete),
+        (1, 0)
+    );
+
+    let [v4, v6] = stats(&info_hash_b).by_ip_type.unwrap();
+    assert_eq!(
+        (v4.complete, v4.incomplete, v6.complete, v6.incomplete),
+        (0, 0, 0, 1)
+    );
+    assert_eq!(
+        (stats(&info_hash_b).seeders, stats(&info_hash_b).incomplete),
+        (0, 1)
+    );
+}
+
+fn torrent(completed: u32, name: &str) -> Option<Torrent> {
+    Some(Torrent {
+        completed,
+        added_at: 0,
+        name: Some(name.to_string()),
+    })
+}
+
+/// Registers a torrent whose infohash is filled with `byte`, without any peers.
+async fn new_torrent(storage: &impl Storage, byte: u8) -> InfoHash {
+    let info_hash = InfoHash([byte; INFOHASH_LENGTH]);
+    storage.insert_torrent(&info_hash, None).await.unwrap();
+    storage.clear_swarm(&info_hash).await.unwrap();
+    info_hash
+}
+
+/// Returns the completed count without going through any cache of the storage.
+async fn completed(storage: &impl Storage, info_hash: &InfoHash) -> u32 {
+    let stats = storage
+        .get_torrent_stats_multi_ip(vec![info_hash.clone()])
+        .await
+        .unwrap();
+    stats[0].1.completed
+}
+
+/// Returns the (complete, incomplete) counts of both IP families of the torrent.
+async fn swarm_counts(storage: &impl Storage, info_hash: &InfoHash) -> (u32, u32) {
+    let stats = storage
+        .get_torrent_stats_multi_ip(vec![info_hash.clone()])
+        .await
+        .unwrap();
+    (stats[0].1.seeders, stats[0].1.incomplete)
+}
+
+/// Returns the `i`-th test peer of the IP family.
+fn peer(i: u8, ip_type: IpType) -> (PeerIdKey, Peer) {
+    let peer_id = PeerId::from([i; PEER_ID_LENGTH]);
+    let addr: PeerAddr = match ip_type {
+        IpType::V4 => (Ipv4Addr::new(127, 0, 0, i), Port(6881)).into(),
+        IpType::V6 => (Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, i as u16), Port(6881)).into(),
+    };
+
+    let peer = Peer {
+        expire_at: Clock::now_since_epoch() + Duration::from_secs(60),
+        ..Peer::new_test(addr)
+    };
+
+    (PeerIdKey::new(&peer_id, None), peer)
+}
+
+async fn put(
+    storage: &impl Storage,
+    info_hash: &InfoHash,
+    i: u8,
+    ip_type: IpType,
+    peer_type: PeerType,
+) {
+    let (peer_id_key, peer) = peer(i, ip_type);
+    storage
+        .put_peer_in_swarm(info_hash, &peer_id_key, peer, peer_type)
+        .await
+        .unwrap();
+}
+
+/// Collects the keys of up to `capacity` extracted peers.
+struct Extractor {
+    capacity: usize,
+    peers: Vec<PeerIdKey>,
+}
+
+impl Extractor {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            peers: Vec::new(),
+        }
+    }
+
+    fn extract<'a>(&mut self, keys: impl Iterator<Item = &'a PeerIdKey>) -> bool {
+        for key in keys {
+            if self.peers.len() >= self.capacity {
+                return false;
+            }
+
+            self.peers.push(key.clone());
+        }
+
+        self.peers.len() < self.capacity
+    }
+}
+
+impl PeerExtractor for Extractor {
+    fn from_dict(&mut self, dict: &PeerDict) -> bool {
+        self.extract(dict.keys())
+    }
+
+    fn from_list(&mut self, list: &PeerList) -> bool {
+        self.extract(list.iter().map(|(key, _)| key))
+    }
+
+    fn remaining(&self) -> usize {
+        self.capacity - self.peers.len()
+    }
+}
+
+#[derive(Default)]
+struct Collector {
+    stats: TorrentStatsList,
+}
+
+impl Processor<TorrentStatsList> for Collector {
+    fn process(&mut self, input: &TorrentStatsList) -> bool {
+        self.stats.extend_from_slice(input);
+        true
+    }
+}
@@ -1,15 +1,14 @@
-use ahash::RandomState;
+use ahash::{AHashMap, RandomState};
 use async_trait::async_trait;
 use indexmap::IndexMap;
 use tokio::sync::RwLock;
 
-use super::{PeerExtractor, Processor, Result, Storage};
-use crate::{
-    constants::TRACKER_ERROR_NOT_FOUND_TORRENT,
-    models::{
-        common::{InfoHash, IpType},
-        peer::{Peer, PeerType},
-        torrent::{PeerIdKey, SwarmStats, Torrent, TorrentStats, TorrentStatsList, TorrentSwarm},
+use super::{Error, PeerExtractor, Processor, Result, Storage};
+use crate::models::{
+    common::{InfoHash, IpType},
+    peer::{Peer, PeerType},
+    torrent::{
+        PeerIdKey, SwarmPeers, SwarmStats, Torrent, TorrentStats, TorrentStatsList, TorrentSwarm,
     },
 };
 
@@ -52,11 +51,35 @@ impl Storage for MemoryStorage {
     async fn insert_torrent(&self, info_hash: &InfoHash, torrent: Option<Torrent>) -> Result<()> {
         let shard = self.get_shard(&info_hash);
 
+        // A registered torrent is kept as is, like `HSETNX` does on Redis, so that
+        // re-registering it doesn't reset its completed count.
         shard
             .torrents
             .write()
             .await
-            .insert(info_hash.clone(), torrent.unwrap_or_default());
+            .entry(info_hash.clone())
+            .or_insert_with(|| torrent.unwrap_or_default().registered());
+
+        Ok(())
+    }
+
+    async fn insert_torrents(&self, items: Vec<(InfoHash, Option<Torrent>)>) -> Result<()> {
+        // Group the torrents by shard, so each shard is locked once.
+        let mut groups: AHashMap<usize, Vec<(InfoHash, Torrent)>> = AHashMap::new();
+        for (info_hash, torrent) in items {
+            let index = self.get_shard_index(info_hash.as_ref());
+            groups
+                .entry(index)
+                .or_default()
+                .push((info_hash, torrent.unwrap_or_default().registered()));
+        }
+
+        for (index, torrents) in groups {
+            let mut map = self.shards[index].torrents.write().await;
+            for (info_hash, torrent) in torrents {
+                map.entry(info_hash).or_insert(torrent);
+            }
+        }
 
         Ok(())
     }
@@ -80,7 +103,7 @@ impl Storage for MemoryStorage {
             .cloned())
     }
 
-    async fn remove_torrent(&mut self, info_hash: &InfoHash) -> Result<()> {
+    async fn remove_torrent(&self, info_hash: &InfoHash) -> Result<()> {
         let shard = self.get_shard(&info_hash);
         shard.swarms.write().await.remove(info_hash);
         shard.torrents.write().await.remove(info_hash);
@@ -95,25 +118,29 @@ impl Storage for MemoryStorage {
     ) -> Result<TorrentStats> {
         let shard = self.get_shard(&info_hash);
 
-        let completed = shard
-            .torrents
-            .read()
-            .await
-            .get_torrent(info_hash)
-            .map(|tor| tor.completed)?;
+        // Both locks are held together, so a concurrent promotion is seen in full or not at all.
+        let mut torrents = shard.torrents.read().await;
+        let swarms = shard.swarms.read().await;
 
-        let (seeders, incomplete) = shard
-            .swarms
-            .read()
-            .await
+        let completed = torrents.get_torrent(info_hash).map(|tor| tor.completed)?;
+
+        let (seeders, incomplete, downloaders) = swarms
             .get_swarm(info_hash, ip_type)
-            .map(|s| (s.complete_count(), s.incomplete_count()))
+            .map(|s| {
+                (
+                    s.complete_count(),
+                    s.incomplete_count(),
+                    s.downloaders_count(),
+                )
+            })
             .unwrap_or_default();
 
         Ok(TorrentStats {
             completed,
             seeders,
             incomplete,
+            downloaders: Some(downloaders),
+            by_ip_type: None,
         })
     }
 
@@ -132,36 +159,69 @@ impl Storage for MemoryStorage {
         Ok(result)
     }
 
+    async fn get_torrent_stats_multi_ip(
+        &self,
+        info_hashes: Vec<InfoHash>,
+    ) -> Result<TorrentStatsList> {
+        let mut result = Vec::with_capacity(info_hashes.len());
+        for info_hash in info_hashes {
+            let shard = self.get_shard(&info_hash);
+            let mut torrents = shard.torrents.read().await;
+            let swarms = shard.swarms.read().await;
+
+            let completed = match torrents.get_torrent(&info_hash) {
+                Ok(torrent) => torrent.completed,
+                Err(_) => continue,
+            };
+
+            let mut stats = TorrentStats::new_with_completed(completed);
+            let mut downloaders = 0;
+
+            for ip_type in [IpType::V4, IpType::V6] {
+                if let Some(s) = swarms.get(&info_hash, ip_type) {
+                    stats.seeders += s.complete_count();
+                    stats.incomplete += s.incomplete_count();
+                    downloaders += s.downloaders_count();
+                }
+            }
+
+            stats.downloaders = Some(downloaders);
+            result.push((info_hash, stats));
+        }
+
+        Ok(result)
+    }
+
     async fn get_all_torrent_stats(
         &self,
         processor: &mut dyn Processor<TorrentStatsList>,
     ) -> Result<()> {
         for shard in &self.shards {
-            let mut stats = {
-                let torrents = shard.torrents.read().await;
-                let mut stats = Vec::with_capacity(torrents.len());
-
-                for (info_hash, torrent) in torrents.iter() {
-                    stats.push((
-                        info_hash.clone(),
-                        TorrentStats::new_with_completed(torrent.completed),
-                    ));
-                }
+            let torrents = shard.torrents.read().await;
+            let swarms = shard.swarms.read().await;
 
-                stats
-            };
+            let mut stats = Vec::with_capacity(torrents.len());
+            for (info_hash, torrent) in torrents.iter() {
+                stats.push((
+                    info_hash.clone(),
+                    TorrentStats::new_with_completed(torrent.completed),
+                ));
+            }
 
-            let swarms = shard.swarms.read().await;
             for (info_hash, swarm) in stats.iter_mut() {
-                if let Some(s) = swarms.get(info_hash, IpType::V4) {
-                    swarm.seeders = s.complete_count();
-                    swarm.incomplete = s.incomplete_count();
-                }
-
-                if let Some(s) = swarms.get(info_hash, IpType::V6) {
-                    swarm.seeders += s.complete_count();
-                    swarm.incomplete += s.incomplete_count();
-                }
+                let [v4, v6] = [IpType::V4, IpType::V6].map(|ip_type| {
+                    swarms
+                        .get(info_hash, ip_type)
+                        .map(|s| SwarmStats {
+                            complete: s.complete_count(),
+                            incomplete: s.incomplete_count(),
+                        })
+                        .unwrap_or_default()
+                });
+
+                swarm.seeders = v4.complete + v6.complete;
+                swarm.incomplete = v4.incomplete + v6.incomplete;
+                swarm.by_ip_type = Some([v4, v6]);
             }
 
             if !processor.process(&stats) {
@@ -194,13 +254,19 @@ impl Storage for MemoryStorage {
         peer_id_key: &PeerIdKey,
         peer: Peer,
     ) -> Result<()> {
-        if write_swarm!(self, info_hash, peer.ip_type()).promote_peer(peer_id_key, peer) {
-            self.get_shard(info_hash)
-                .torrents
-                .write()
-                .await
-                .get_mut_torrent(info_hash)?
-                .incr_completed();
+        let shard = self.get_shard(info_hash);
+
+        // The torrent is locked first and until the swarm is updated, as the stats are read.
+        let mut torrents = shard.torrents.write().await;
+        let promoted = shard
+            .swarms
+            .write()
+            .await
+            .get_mut_or_insert_swarm(info_hash, peer.ip_type())?
+            .promote_peer(peer_id_key, peer);
+
+        if promoted {
+            torrents.get_mut_torrent(info_hash)?.incr_completed();
         }
 
         Ok(())
@@ -230,7 +296,12 @@ impl Storage for MemoryStorage {
         extractor: &mut dyn PeerExtractor,
     ) -> Result<SwarmStats> {
         let swarms = self.get_shard(info_hash).swarms.read().await;
-        let swarm = swarms.get_swarm(info_hash, peer_ip_type)?;
+
+        // A torrent has no swarm for an IP family until a peer of that family announces.
+        let swarm = match swarms.get(info_hash, peer_ip_type) {
+            Some(swarm) => swarm,
+            None => return Ok(SwarmStats::default()),
+        };
 
         let stats = SwarmStats {
             complete: swarm.complete_count(),
@@ -251,18 +322,90 @@ impl Storage for MemoryStorage {
         &self,
         info_hash: &InfoHash,
         peer_id: &PeerIdKey,
-        peer_type: PeerType,
         peer_ip_type: IpType,
     ) -> Result<()> {
         let shard = self.get_shard(info_hash);
         let mut swarm_map = shard.swarms.write().await;
 
+        // Removing a seeder also looks among the partial seeds.
         if let Some(s) = swarm_map.get_mut(info_hash, peer_ip_type) {
-            s.remove_peer(peer_id, peer_type);
+            s.remove_peer(peer_id, PeerType::Leecher);
+            s.remove_peer(peer_id, PeerType::Seeder);
         }
 
         Ok(())
     }
+
+    async fn clear_swarm(&self, info_hash: &InfoHash) -> Result<()> {
+        self.get_shard(info_hash)
+            .swarms
+            .write()
+            .await
+            .remove(info_hash);
+
+        Ok(())
+    }
+
+    async fn list_swarm(
+        &self,
+        info_hash: &InfoHash,
+        ip_type: IpType,
+        limit: usize,
+    ) -> Result<SwarmPeers> {
+        let swarms = self.get_shard(info_hash).swarms.read().await;
+        let swarm = match swarms.get(info_hash, ip_type) {
+            Some(swarm) => swarm,
+            None => return Ok(Vec::new()),
+        };
+
+        let dicts = [
+            (PeerType::Leecher, &swarm.leechers),
+            (PeerType::Seeder, &swarm.seeders),
+            (PeerType::Partial, &swarm.partial_seeds),
+        ];
+
+        Ok(dicts
+            .into_iter()
+            .flat_map(|(peer_type, dict)| {
+                dict.iter()
+                    .map(move |(key, peer)| (key.clone(), peer_type, peer.clone()))
+            })
+            .take(limit)
+            .collect())
+    }
+
+    async fn has_peer_in_swarm(
+        &self,
+        info_hash: &InfoHash,
+        peer_id_key: &PeerIdKey,
+        peer_ip_type: IpType,
+    ) -> Result<bool> {
+        let swarms = self.get_shard(info_hash).swarms.read().await;
+
+        Ok(swarms.get(info_hash, peer_ip_type).is_some_and(|swarm| {
+            swarm.leechers.contains_key(peer_id_key)
+                || swarm.seeders.contains_key(peer_id_key)
+                || swarm.partial_seeds.contains_key(peer_id_key)
+        }))
+    }
+
+    async fn get_peer_in_swarm(
+        &self,
+        info_hash: &InfoHash,
+        peer_id_key: &PeerIdKey,
+        peer_ip_type: IpType,
+    ) -> Result<Option<Peer>> {
+        let swarms = self.get_shard(info_hash).swarms.read().await;
+
+        Ok(swarms.get(info_hash, peer_ip_type).and_then(|swarm| {
+            swarm
+                .leechers
+                .get(peer_id_key)
+                .or_else(|| swarm.seeders.get(peer_id_key))
+                .or_else(|| swarm.partial_seeds.get(peer_id_key))
+                .cloned()
+        }))
+    }
 }
 
 impl MemoryStorage {
@@ -283,13 +426,37 @@ impl MemoryStorage {
         Self { shards }
     }
 
+    /// Drops the swarms left without peers and releases the capacity left over by removals,
+    /// which also keeps the iteration of a full scrape over live entries only.
+    /// Each shard is locked in turn, so announces to other shards are not held up.
+    pub async fn compact(&self) {
+        for shard in &self.shards {
+            {
+                let mut guard = shard.swarms.write().await;
+                let swarms = &mut *guard;
+                for dict in [&mut swarms.v4, &mut swarms.v6] {
+                    dict.retain(|_, swarm| !swarm.is_empty());
+                    dict.values_mut().for_each(TorrentSwarm::shrink_to_fit);
+                    dict.shrink_to_fit();
+                }
+            }
+
+            shard.torrents.write().await.shrink_to_fit();
+        }
+    }
+
     fn get_shard(&self, info_hash: &InfoHash) -> &Shard {
         &self.shards[self.get_shard_index(info_hash.as_ref())]
     }
 
+    /// Maps a key of any length to a shard. Infohashes are uniformly distributed,
+    /// so folding their bytes is enough to spread them evenly.
     fn get_shard_index(&self, data: &[u8]) -> usize {
-        let bytes = data[0..4].try_into().unwrap();
-        u32::from_be_bytes(bytes) as usize % self.shards.len()
+        let hash = data
+            .iter()
+            .fold(0u32, |hash, b| hash.rotate_left(8) ^ *b as u32);
+
+        hash as usize % self.shards.len()
     }
 }
 
@@ -347,7 +514,7 @@ impl SwarmGet for tokio::sync::RwLockReadGuard<'_, SwarmsMap> {
     fn get_swarm(&self, info_hash: &InfoHash, ip_type: IpType) -> Result<&TorrentSwarm> {
         match self.get(info_hash, ip_type) {
             Some(torrent) => Ok(torrent),
-            None => Err(TRACKER_ERROR_NOT_FOUND_TORRENT.into()),
+            None => Err(Error::not_found()),
         }
     }
 }
@@ -371,7 +538,7 @@ impl SwarmGetMut for tokio::sync::RwLockWriteGuard<'_, SwarmsMap> {
     ) -> Result<&mut TorrentSwarm> {
         match self.get_mut(info_hash, ip_type) {
             Some(torrent) => Ok(torrent),
-            None => Err(TRACKER_ERROR_NOT_FOUND_TORRENT.into()),
+            None => Err(Error::not_found()),
         }
     }
 
@@ -383,7 +550,7 @@ impl SwarmGetMut for tokio::sync::RwLockWriteGuard<'_, SwarmsMap> {
         if let Some(index) = self.get_index_of(info_hash, ip_type) {
             return match self.get_index_mut(index, ip_type) {
                 Some(torrent) => Ok(torrent),
-                None => Err(TRACKER_ERROR_NOT_FOUND_TORRENT.into()),
+                None => Err(Error::not_found()),
             };
         }
 
@@ -402,7 +569,7 @@ impl TorrentGetMut for tokio::sync::RwLockWriteGuard<'_, TorrentsMap> {
     fn get_mut_torrent(&mut self, info_hash: &InfoHash) -> Result<&mut Torrent> {
         match self.get_mut(info_hash) {
             Some(torrent) => Ok(torrent),
-            None => Err(TRACKER_ERROR_NOT_FOUND_TORRENT.into()),
+            None => Err(Error::not_found()),
         }
     }
 }
@@ -415,20 +582,21 @@ impl TorrentGet for tokio::sync::RwLockReadGuard<'_, TorrentsMap> {
     fn get_torrent(&mut self, info_hash: &InfoHash) -> Result<&Torrent> {
         match self.get(info_hash) {
             Some(torrent) => Ok(torrent),
-            None => Err(TRACKER_ERROR_NOT_FOUND_TORRENT.into()),
+            None => Err(Error::not_found()),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use ts_utils::time::Clock;
-
     use crate::models::{
-        common::{PeerId, Port},
-        peer::PeerAddr,
+        common::{PeerId, Port, INFOHASH_LENGTH, PEER_ID_LENGTH},
+        tracker::FullScrapeResponse,
+    };
+    use std::{
+        net::{Ipv4Addr, Ipv6Addr},
+        time::Duration,
     };
-    use std::net::Ipv4Addr;
 
     use super::*;
 
@@ -453,11 +621,7 @@ mod tests {
         let peer_id: PeerId = PEER_ID.as_bytes().try_into().unwrap();
         let peer_id_key: PeerIdKey = PeerIdKey::new(&peer_id, None);
 
-        let addr: PeerAddr = (Ipv4Addr::from([127, 0, 0, 1]), Port(8080)).into();
-        let peer = Peer {
-            addr,
-            expire_at: Clock::now_since_epoch(),
-        };
+        let peer = Peer::new_test((Ipv4Addr::from([127, 0, 0, 1]), Port(8080)));
 
         (peer_id_key, peer)
     }
@@ -473,6 +637,34 @@ mod tests {
             .read()
             .await
             .contains_key(&info_hash));
+
+        let torrent = storage.get_torrent(&info_hash).await.unwrap().unwrap();
+        assert!(torrent.added_at > 0);
+    }
+
+    #[tokio::test]
+    async fn test_insert_torrents() {
+        let storage = MemoryStorage::with_shards(16);
+        let info_hashes: Vec<InfoHash> = (0..300u32)
+            .map(|i| {
+                let mut bytes = [0; 20];
+                bytes[..4].copy_from_slice(&i.to_be_bytes());
+                InfoHash(bytes)
+            })
+            .collect();
+
+        let items = info_hashes.iter().map(|h| (h.clone(), None)).collect();
+        storage.insert_torrents(items).await.unwrap();
+
+        for info_hash in &info_hashes {
+            assert!(storage.has_torrent(info_hash).await.unwrap());
+        }
+
+        let mut count = 0;
+        for shard in &storage.shards {
+            count += shard.torrents.read().await.len();
+        }
+        assert_eq!(count, info_hashes.len());
     }
 
     #[tokio::test]
@@ -483,9 +675,22 @@ mod tests {
         assert!(storage.has_torrent(&info_hash).await.unwrap());
     }
 
+    #[tokio::test]
+    async fn test_get_torrent() {
+        let storage = create_storage().await;
+        let info_hash: InfoHash = INFOHASH_A.parse().unwrap();
+
+        let stored = storage.get_shard(&info_hash).torrents.read().await[&info_hash].clone();
+        let torrent = storage.get_torrent(&info_hash).await.unwrap();
+        assert_eq!(torrent, Some(stored));
+
+        let info_hash: InfoHash = INFOHASH_B.parse().unwrap();
+        assert_eq!(storage.get_torrent(&info_hash).await.unwrap(), None);
+    }
+
     #[tokio::test]
     async fn test_remove_torrent() {
-        let mut storage = create_storage().await;
+        let storage = create_storage().await;
         let info_hash: InfoHash = INFOHASH_A.parse().unwrap();
 
         storage.remove_torrent(&info_hash).await.unwrap();
@@ -513,7 +718,7 @@ mod tests {
         let info_hash: InfoHash = INFOHASH_B.parse().unwrap();
         let stats = storage.get_torrent_stats(&info_hash, IpType::V4).await;
 
-        assert!(stats.is_err());
+        assert!(stats.unwrap_err().is_not_found());
     }
 
     #[tokio::test]
@@ -561,4 +766,238 @@ mod tests {
             assert!(swarm.seeders.contains_key(&peer_id_key));
         }
     }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_torrent_stats_consistent_with_promotions() {
+        let storage = std::sync::Arc::new(create_storage().await);
+        let info_hash: InfoHash = INFOHASH_A.parse().unwrap();
+        let (_, peer) = create_test_peer();
+
+        // Every promoted peer is a new seeder that also counts a completion.
+        let promoter = {
+            let storage = storage.clone();
+            let info_hash = info_hash.clone();
+            tokio::spawn(async move {
+                for i in 0..2000_u32 {
+                    let peer_id_key = PeerIdKey::from(i.to_be_bytes().to_vec());
+                    storage
+                        .promote_peer_in_swarm(&info_hash, &peer_id_key, peer.clone())
+                        .await
+                        .unwrap();
+                }
+            })
+        };
+
+        while !promoter.is_finished() {
+            let stats = storage
+                .get_torrent_stats(&info_hash, IpType::V4)
+                .await
+                .unwrap();
+            assert_eq!(stats.seeders, stats.completed);
+
+            let stats = storage
+                .get_torrent_stats_multi_ip(vec![info_hash.clone()])
+                .await;
+            let stats = &stats.unwrap()[0].1;
+            assert_eq!(stats.seeders, stats.completed);
+        }
+
+        promoter.await.unwrap();
+        let stats = storage
+            .get_torrent_stats(&info_hash, IpType::V4)
+            .await
+            .unwrap();
+        assert_eq!((stats.seeders, stats.completed), (2000, 2000));
+    }
+
+    #[test]
+    fn test_shard_distribution() {
+        let storage = MemoryStorage::with_shards(16);
+        let mut counts = [0; 16];
+
+        for i in 0..1600u32 {
+            let mut bytes = [0xab; INFOHASH_LENGTH];
+            bytes[INFOHASH_LENGTH - 4..].copy_from_slice(&i.to_be_bytes());
+            counts[storage.get_shard_index(InfoHash(bytes).as_ref())] += 1;
+        }
+
+        assert!(counts.iter().all(|count| *count == 100));
+
+        // Keys shorter than 4 bytes no longer panic.
+        assert!(storage.get_shard_index(&[1, 2]) < 16);
+        assert!(storage.get_shard_index(&[]) < 16);
+    }
+
+    #[tokio::test]
+    async fn test_clear_swarm() {
+        let storage = create_storage().await;
+        let info_hash: InfoHash = INFOHASH_A.parse().unwrap();
+        let (peer_id_key, peer) = create_test_peer();
+
+        storage
+            .put_peer_in_swarm(&info_hash, &peer_id_key, peer.clone(), PeerType::Leecher)
+            .await
+            .unwrap();
+        storage
+            .promote_peer_in_swarm(&info_hash, &peer_id_key, peer)
+            .await
+            .unwrap();
+
+        storage.clear_swarm(&info_hash).await.unwrap();
+
+        assert!(storage.has_torrent(&info_hash).await.unwrap());
+
+        let stats = storage
+            .get_torrent_stats(&info_hash, IpType::V4)
+            .await
+            .unwrap();
+        assert_eq!(stats.seeders, 0);
+        assert_eq!(stats.incomplete, 0);
+        assert_eq!(stats.completed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_compact() {
+        let storage = MemoryStorage::with_shards(1);
+        let info_hash: InfoHash = INFOHASH_A.parse().unwrap();
+        let info_hashes = (0..1000u32)
+            .map(|i| {
+                let mut bytes = [0; INFOHASH_LENGTH];
+                bytes[..4].copy_from_slice(&i.to_be_bytes());
+                InfoHash(bytes)
+            })
+            .collect::<Vec<_>>();
+
+        for info_hash in &info_hashes {
+            storage.insert_torrent(info_hash, None).await.unwrap();
+        }
+
+        let (_, peer) = create_test_peer();
+        for i in 0..1000u32 {
+            let mut peer_id = [b'0'; PEER_ID_LENGTH];
+            peer_id[..4].copy_from_slice(&i.to_be_bytes());
+            let peer_id: PeerId = peer_id.into();
+            let peer_id_key = PeerIdKey::new(&peer_id, None);
+
+            storage
+                .put_peer_in_swarm(&info_hash, &peer_id_key, peer.clone(), PeerType::Leecher)
+                .await
+                .unwrap();
+            storage
+                .remove_peer_from_swarm(&info_hash, &peer_id_key, IpType::V4)
+                .await
+                .unwrap();
+        }
+
+        for info_hash in &info_hashes {
+            storage.remove_torrent(info_hash).await.unwrap();
+        }
+
+        let shard = &storage.shards[0];
+        assert!(shard.torrents.read().await.capacity() >= 1000);
+        assert!(shard
+            .swarms
+            .read()
+            .await
+            .get(&info_hash, IpType::V4)
+            .is_some());
+
+        storage.compact().await;
+
+        assert!(shard.torrents.read().await.capacity() < 1000);
+        assert!(shard
+            .swarms
+            .read()
+            .await
+            .get(&info_hash, IpType::V4)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reannounce_refreshes_expire_at() {
+        let storage = create_storage().await;
+        let info_hash: InfoHash = INFOHASH_A.parse().unwrap();
+        let (peer_id_key, mut peer) = create_test_peer();
+
+        storage
+            .put_peer_in_swarm(&info_hash, &peer_id_key, peer.clone(), PeerType::Leecher)
+            .await
+            .unwrap();
+
+        let expire_at = peer.expire_at + Duration::from_secs(60);
+        peer.expire_at = expire_at;
+        storage
+            .update_or_put_peer_in_swarm(&info_hash, &peer_id_key, peer, PeerType::Leecher)
+            .await
+            .unwrap();
+
+        let swarms = storage.get_shard(&info_hash).swarms.read().await;
+        let swarm = swarms.get(&info_hash, IpType::V4).unwrap();
+        let peer = swarm.get_peer(&peer_id_key, PeerType::Leecher).unwrap();
+        assert_eq!(peer.expire_at, expire_at);
+        assert_eq!(swarm.incomplete_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reinsert_torrent_keeps_completed() {
+        let storage = create_storage().await;
+        let info_hash: InfoHash = INFOHASH_A.parse().unwrap();
+        let (peer_id_key, peer) = create_test_peer();
+
+        storage
+            .promote_peer_in_swarm(&info_hash, &peer_id_key, peer)
+            .await
+            .unwrap();
+
+        storage.insert_torrent(&info_hash, None).await.unwrap();
+        let torrent = storage.get_torrent(&info_hash).await.unwrap().unwrap();
+        assert_eq!(torrent.completed, 1);
+
+        storage
+            .insert_torrents(vec![(info_hash.clone(), None)])
+            .await
+            .unwrap();
+        let torrent = storage.get_torrent(&info_hash).await.unwrap().unwrap();
+        assert_eq!(torrent.completed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_full_scrape_split_ip_types() {
+        let storage = create_storage().await;
+        let info_hash: InfoHash = INFOHASH_A.parse().unwrap();
+
+        let (peer_id_key, peer) = create_test_peer();
+        storage
+            .put_peer_in_swarm(&info_hash, &peer_id_key, peer, PeerType::Seeder)
+            .await
+            .unwrap();
+
+        let peer_id: PeerId = [b'6'; PEER_ID_LENGTH].into();
+        let peer_id_key = PeerIdKey::new(&peer_id, None);
+        let peer = Peer::new_test((Ipv6Addr::LOCALHOST, Port(8080)));
+        storage
+            .put_peer_in_swarm(&info_hash, &peer_id_key, peer, PeerType::Leecher)
+            .await
+            .unwrap();
+
+        let mut combined = FullScrapeResponse::new();
+        storage.get_all_torrent_stats(&mut combined).await.unwrap();
+
+        let mut expected = b"d5:filesd20:".to_vec();
+        expected.extend_from_slice(info_hash.as_ref());
+        expected.extend_from_slice(b"d8:completei1e10:downloadedi0e10:incompletei1eeee");
+        assert_eq!(combined.output().unwrap(), expected);
+
+        let mut split = FullScrapeResponse::with_split_ip_types();
+        storage.get_all_torrent_stats(&mut split).await.unwrap();
+
+        let mut expected = b"d5:filesd20:".to_vec();
+        expected.extend_from_slice(info_hash.as_ref());
+        expected.extend_from_slice(b"d8:completei1e10:downloadedi0e10:incompletei1e");
+        expected.extend_from_slice(b"4:ipv4d8:completei1e10:incompletei0ee");
+        expected.extend_from_slice(b"4:ipv6d8:completei0e10:incompletei1eeeee");
+        assert_eq!(split.output().unwrap(), expected);
+    }
+
+    test_storage_conformance!(MemoryStorage::new());
 }
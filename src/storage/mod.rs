@@ -1,14 +1,19 @@
 use crate::config::{StorageType, TSConfig};
+use crate::constants::TRACKER_ERROR_NOT_FOUND_TORRENT;
 use crate::models::common::{InfoHash, IpType};
-use crate::models::peer::{Peer, PeerType};
+use crate::models::peer::{InvalidPeerAddrLength, Peer, PeerType};
 use crate::models::torrent::{
-    PeerDict, PeerIdKey, PeerList, SwarmStats, Torrent, TorrentStats, TorrentStatsList,
+    PeerDict, PeerIdKey, PeerList, SwarmPeers, SwarmStats, Torrent, TorrentStats, TorrentStatsList,
 };
 use async_trait::async_trait;
 use std::error::Error as StdError;
 use std::fmt;
 use std::sync::Arc;
 
+#[cfg(test)]
+#[macro_use]
+mod conformance;
+
 mod memory;
 pub use self::memory::MemoryStorage;
 
@@ -20,8 +25,14 @@ pub use self::redis::RedisStorage;
 
 #[async_trait]
 pub trait Storage: Sync + Send {
+    /// Registers a torrent. Registering an already registered torrent is a no-op that keeps
+    /// its stats, name and registration time.
     async fn insert_torrent(&self, info_hash: &InfoHash, stats: Option<Torrent>) -> Result<()>;
-    async fn remove_torrent(&mut self, info_hash: &InfoHash) -> Result<()>;
+
+    /// Registers many torrents, with the same semantics as `insert_torrent` for each.
+    async fn insert_torrents(&self, items: Vec<(InfoHash, Option<Torrent>)>) -> Result<()>;
+
+    async fn remove_torrent(&self, info_hash: &InfoHash) -> Result<()>;
     async fn has_torrent(&self, info_hash: &InfoHash) -> Result<bool>;
     async fn get_torrent(&self, info_hash: &InfoHash) -> Result<Option<Torrent>>;
 
@@ -37,6 +48,12 @@ pub trait Storage: Sync + Send {
         ip_type: IpType,
     ) -> Result<TorrentStatsList>;
 
+    /// Returns the stats of the given torrents, counting the swarms of both IP families.
+    async fn get_torrent_stats_multi_ip(
+        &self,
+        info_hashes: Vec<InfoHash>,
+    ) -> Result<TorrentStatsList>;
+
     async fn get_all_torrent_stats(
         &self,
         processor: &mut dyn Processor<TorrentStatsList>,
@@ -73,13 +90,41 @@ pub trait Storage: Sync + Send {
         extractor: &mut dyn PeerExtractor,
     ) -> Result<SwarmStats>;
 
+    /// Removes the peer from every swarm of its IP type, as it may have been promoted since
+    /// the announce its type was derived from.
     async fn remove_peer_from_swarm(
         &self,
         info_hash: &InfoHash,
         peer_id_key: &PeerIdKey,
-        peer_type: PeerType,
         peer_ip_type: IpType,
     ) -> Result<()>;
+
+    /// Removes every peer of the torrent, in both IP families, keeping the torrent itself.
+    async fn clear_swarm(&self, info_hash: &InfoHash) -> Result<()>;
+
+    /// Returns up to `limit` peers of the torrent in one IP family, along with their types.
+    async fn list_swarm(
+        &self,
+        info_hash: &InfoHash,
+        ip_type: IpType,
+        limit: usize,
+    ) -> Result<SwarmPeers>;
+
+    /// Returns whether the peer is in the swarm of the torrent, whatever its type.
+    async fn has_peer_in_swarm(
+        &self,
+        info_hash: &InfoHash,
+        peer_id_key: &PeerIdKey,
+        peer_ip_type: IpType,
+    ) -> Result<bool>;
+
+    /// Returns the peer from the swarm of the torrent, whatever its type.
+    async fn get_peer_in_swarm(
+        &self,
+        info_hash: &InfoHash,
+        peer_id_key: &PeerIdKey,
+        peer_ip_type: IpType,
+    ) -> Result<Option<Peer>>;
 }
 
 pub fn create_new_storage(config: Arc<TSConfig>) -> Result<Box<dyn Storage>> {
@@ -116,6 +161,36 @@ impl Error {
             }),
         }
     }
+
+    /// Creates an error for data read from the storage backend that could not be decoded.
+    fn backend(msg: String) -> Self {
+        Self {
+            inner: Box::new(ErrorImpl {
+                kind: Kind::Backend(msg),
+                cause: None,
+            }),
+        }
+    }
+
+    /// Creates an error for a torrent that is not in the storage.
+    fn not_found() -> Self {
+        Self {
+            inner: Box::new(ErrorImpl {
+                kind: Kind::NotFound,
+                cause: None,
+            }),
+        }
+    }
+
+    /// Returns true if the error was caused by undecodable data in the storage backend.
+    pub fn is_backend(&self) -> bool {
+        matches!(self.inner.kind, Kind::Backend(_))
+    }
+
+    /// Returns true if the requested torrent is not in the storage.
+    pub fn is_not_found(&self) -> bool {
+        matches!(self.inner.kind, Kind::NotFound)
+    }
 }
 
 struct ErrorImpl {
@@ -127,6 +202,8 @@ struct ErrorImpl {
 #[allow(unused)]
 enum Kind {
     Runtime(Option<String>),
+    Backend(String),
+    NotFound,
     Custom(&'static str),
 }
 
@@ -143,6 +220,12 @@ impl From<&'static str> for Error {
     }
 }
 
+impl From<InvalidPeerAddrLength> for Error {
+    fn from(err: InvalidPeerAddrLength) -> Self {
+        Self::backend(err.to_string())
+    }
+}
+
 impl Error {
     /// The error's standalone message, without the message from the source.
     pub fn message(&self) -> impl fmt::Display + '_ {
@@ -153,6 +236,8 @@ impl Error {
         match self.inner.kind {
             Kind::Custom(ref msg) => msg,
             Kind::Runtime(ref msg) => msg.as_deref().unwrap_or_default(),
+            Kind::Backend(ref msg) => msg,
+            Kind::NotFound => TRACKER_ERROR_NOT_FOUND_TORRENT,
         }
     }
 }
@@ -201,4 +286,8 @@ pub trait Processor<Input>: Send {
 pub trait PeerExtractor: Send {
     fn from_dict(&mut self, dict: &PeerDict) -> bool;
     fn from_list(&mut self, list: &PeerList) -> bool;
+
+    /// The number of peers the extractor still takes, so that a storage reading the swarm in
+    /// batches needn't read more.
+    fn remaining(&self) -> usize;
 }
@@ -1,11 +1,15 @@
-use crate::models::{
-    common::{InfoHash, IpType, INFOHASH_LENGTH},
-    peer::{Peer, PeerType},
-    torrent::Torrent,
+use crate::{
+    models::{
+        common::{InfoHash, IpType, INFOHASH_LENGTH, PEERKEY_LENGTH},
+        peer::{Peer, PeerAddr, PeerType},
+        torrent::Torrent,
+    },
+    storage::{Error, Result},
 };
 use bytes::{Bytes, BytesMut};
-use redis::{FromRedisValue, RedisError, RedisResult, ToRedisArgs, Value};
-use std::{array::TryFromSliceError, mem};
+use redis::{FromRedisValue, RedisResult, ToRedisArgs, Value};
+use std::mem;
+use ts_utils::time::Duration;
 
 pub const REDIS_KEY_PREFIX: &[u8] = b"ts_";
 pub const TORRENT_KEY_PREFIX: &[u8] = REDIS_KEY_PREFIX;
@@ -18,6 +22,8 @@ pub const SWARM_KEY_LEECHER_PREFIX: &[u8] = b"_l";
 pub const SWARM_KEY_PARTIAL_SEED_PREFIX: &[u8] = b"_p";
 pub const SWARM_KEY_LEN: usize = TORRENT_KEY_LEN + TYPE_LEN + 2;
 pub const TORRENT_COMPLETED_KEY: &[u8] = b"c";
+pub const TORRENT_ADDED_AT_KEY: &[u8] = b"a";
+pub const TORRENT_NAME_KEY: &[u8] = b"n";
 
 // Define the macro for serializing fields
 macro_rules! serialize_field {
@@ -126,19 +132,57 @@ impl<'a> ToRedisArgs for SwarmKey<'a> {
 
 const EXPIRE_AT_SIZE: usize = mem::size_of::<u64>();
 
+/// The first byte of a tagged peer value. Peers stored before the tagged encoding begin with
+/// their big-endian `expire_at` secs, whose first byte is `0`.
+const PEER_VALUE_TAGGED: u8 = 1;
+
+const PEER_FIELD_CRYPTO_FLAG: u8 = 1;
+const PEER_FIELD_LAST_SEEN: u8 = 2;
+const PEER_FIELD_KEY: u8 = 3;
+
+const PEER_FIELD_HEADER_SIZE: usize = 2;
+const LAST_SEEN_SIZE: usize = mem::size_of::<u64>();
+
+/// Appends a tagged field as its tag, its length and its value.
+fn put_peer_field(bytes: &mut BytesMut, tag: u8, value: &[u8]) {
+    bytes.extend_from_slice(&[tag, value.len() as u8]);
+    bytes.extend_from_slice(value);
+}
+
 impl ToRedisArgs for Peer {
     fn write_redis_args<W>(&self, out: &mut W)
     where
         W: ?Sized + redis::RedisWrite,
     {
         let addr = self.addr.as_bytes();
-        let expire_at = self.expire_at.as_secs();
 
-        let len = EXPIRE_AT_SIZE + addr.len();
+        let len = 2
+            + EXPIRE_AT_SIZE
+            + addr.len()
+            + 3 * PEER_FIELD_HEADER_SIZE
+            + 1
+            + LAST_SEEN_SIZE
+            + PEERKEY_LENGTH;
         let mut bytes = BytesMut::with_capacity(len);
 
-        bytes.extend_from_slice(&expire_at.to_be_bytes());
-        bytes.extend_from_slice(self.addr.as_bytes());
+        bytes.extend_from_slice(&[PEER_VALUE_TAGGED]);
+        bytes.extend_from_slice(&self.expire_at.as_secs().to_be_bytes());
+        bytes.extend_from_slice(&[addr.len() as u8]);
+        bytes.extend_from_slice(addr);
+
+        if self.crypto_flag != 0 {
+            put_peer_field(&mut bytes, PEER_FIELD_CRYPTO_FLAG, &[self.crypto_flag]);
+        }
+
+        put_peer_field(
+            &mut bytes,
+            PEER_FIELD_LAST_SEEN,
+            &self.last_seen.as_secs().to_be_bytes(),
+        );
+
+        if let Some(key) = self.key {
+            put_peer_field(&mut bytes, PEER_FIELD_KEY, &key);
+        }
 
         out.write_arg(&bytes);
     }
@@ -148,19 +192,103 @@ impl ToRedisArgs for Peer {
     }
 }
 
-impl FromRedisValue for Peer {
-    fn from_redis_value(v: &Value) -> RedisResult<Self> {
-        match *v {
-            Value::Data(ref bytes) => {
-                let expire_at = ts_utils::time::Duration::from_secs(u64::from_be_bytes(
-                    bytes[..EXPIRE_AT_SIZE].try_into().map_err(from_slice_err)?,
-                ));
+fn invalid_peer_value(reason: &str) -> Error {
+    Error::backend(format!("invalid peer value: {}", reason))
+}
+
+/// Decodes a peer stored either as the big-endian `expire_at` secs followed by the compact peer
+/// address, or tagged as [`PEER_VALUE_TAGGED`], the `expire_at` secs, the address length, the
+/// address and its optional fields.
+/// Each field is its tag, its length and its value, so fields added later are skipped by binaries
+/// that don't know them, and fields that aren't stored keep their defaults.
+pub fn decode_peer(bytes: &[u8]) -> Result<Peer> {
+    if bytes.len() < EXPIRE_AT_SIZE {
+        return Err(Error::backend(format!(
+            "invalid peer value length: {}",
+            bytes.len()
+        )));
+    }
+
+    if bytes[0] != PEER_VALUE_TAGGED {
+        let (expire_at, addr) = bytes.split_at(EXPIRE_AT_SIZE);
+        let expire_at = u64::from_be_bytes(expire_at.try_into().unwrap());
+
+        return Ok(Peer {
+            addr: PeerAddr::try_from(addr)?,
+            expire_at: Duration::from_secs(expire_at),
+            last_seen: Duration::from_secs(0),
+            crypto_flag: 0,
+            key: None,
+        });
+    }
+
+    let rest = &bytes[1..];
+    if rest.len() < EXPIRE_AT_SIZE + 1 {
+        return Err(invalid_peer_value("missing address"));
+    }
 
-                let addr = bytes[EXPIRE_AT_SIZE..].try_into().map_err(from_str)?;
-                let peer = Peer { addr, expire_at };
+    let (expire_at, rest) = rest.split_at(EXPIRE_AT_SIZE);
+    let expire_at = u64::from_be_bytes(expire_at.try_into().unwrap());
 
-                Ok(peer)
+    let addr_len = rest[0] as usize;
+    let rest = &rest[1..];
+    if rest.len() < addr_len {
+        return Err(invalid_peer_value("truncated address"));
+    }
+
+    let (addr, mut fields) = rest.split_at(addr_len);
+    let mut peer = Peer {
+        addr: PeerAddr::try_from(addr)?,
+        expire_at: Duration::from_secs(expire_at),
+        last_seen: Duration::from_secs(0),
+        crypto_flag: 0,
+        key: None,
+    };
+
+    while !fields.is_empty() {
+        if fields.len() < PEER_FIELD_HEADER_SIZE
+            || fields.len() - PEER_FIELD_HEADER_SIZE < fields[1] as usize
+        {
+            return Err(invalid_peer_value("truncated field"));
+        }
+
+        let (tag, len) = (fields[0], fields[1] as usize);
+        let (value, rest) = fields[PEER_FIELD_HEADER_SIZE..].split_at(len);
+        fields = rest;
+
+        match tag {
+            PEER_FIELD_CRYPTO_FLAG => {
+                peer.crypto_flag = *value
+                    .first()
+                    .ok_or_else(|| invalid_peer_value("crypto flag"))?;
+            }
+            PEER_FIELD_LAST_SEEN => {
+                let last_seen = value
+                    .try_into()
+                    .map_err(|_| invalid_peer_value("last seen"))?;
+                peer.last_seen = Duration::from_secs(u64::from_be_bytes(last_seen));
             }
+            PEER_FIELD_KEY => {
+                peer.key = Some(value.try_into().map_err(|_| invalid_peer_value("key"))?);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(peer)
+}
+
+impl FromRedisValue for Peer {
+    fn from_redis_value(v: &Value) -> RedisResult<Self> {
+        match *v {
+            Value::Data(ref bytes) => decode_peer(bytes).map_err(|err| {
+                (
+                    redis::ErrorKind::TypeError,
+                    "Invalid peer value",
+                    err.to_string(),
+                )
+                    .into()
+            }),
             _ => Err((redis::ErrorKind::TypeError, "Unexpected type").into()),
         }
     }
@@ -172,6 +300,11 @@ impl ToRedisArgs for Torrent {
         W: ?Sized + redis::RedisWrite,
     {
         serialize_field!(out, TORRENT_COMPLETED_KEY, self.completed);
+        serialize_field!(out, TORRENT_ADDED_AT_KEY, self.added_at);
+
+        if let Some(name) = &self.name {
+            serialize_field!(out, TORRENT_NAME_KEY, name);
+        }
     }
 
     fn is_single_arg(&self) -> bool {
@@ -189,7 +322,9 @@ impl FromRedisValue for Torrent {
                 process_chunks_for_struct!(
                     chunks,
                     torrent,
-                    TORRENT_COMPLETED_KEY => completed
+                    TORRENT_COMPLETED_KEY => completed,
+                    TORRENT_ADDED_AT_KEY => added_at,
+                    TORRENT_NAME_KEY => name
                 );
 
                 Ok(torrent)
@@ -199,10 +334,160 @@ impl FromRedisValue for Torrent {
     }
 }
 
-fn from_slice_err(_: TryFromSliceError) -> RedisError {
-    (redis::ErrorKind::TypeError, "Unexpected type").into()
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::peer::{PEER_ADDR_V4_LENGTH, PEER_ADDR_V6_LENGTH};
+
+    fn encode_peer_value(expire_at: u64, addr: &[u8]) -> Vec<u8> {
+        let mut bytes = expire_at.to_be_bytes().to_vec();
+        bytes.extend_from_slice(addr);
+        bytes
+    }
+
+    fn decode_torrent(fields: &[(&[u8], &[u8])]) -> Torrent {
+        let items = fields
+            .iter()
+            .flat_map(|(field, value)| [Value::Data(field.to_vec()), Value::Data(value.to_vec())])
+            .collect();
+
+        Torrent::from_redis_value(&Value::Bulk(items)).unwrap()
+    }
+
+    #[test]
+    fn test_torrent_round_trip() {
+        let torrent = Torrent {
+            completed: 3,
+            added_at: 1_700_000_000,
+            name: Some("ubuntu.iso".to_string()),
+        };
+
+        let args = torrent.to_redis_args();
+        let fields: Vec<_> = args
+            .chunks(2)
+            .map(|pair| (pair[0].as_slice(), pair[1].as_slice()))
+            .collect();
+
+        assert_eq!(decode_torrent(&fields), torrent);
+
+        // Torrents stored before the metadata fields existed.
+        let torrent = decode_torrent(&[(TORRENT_COMPLETED_KEY, b"5".as_slice())]);
+        assert_eq!(torrent.completed, 5);
+        assert_eq!(torrent.added_at, 0);
+        assert_eq!(torrent.name, None);
+    }
+
+    #[test]
+    fn test_decode_peer() {
+        // Peers stored before the tagged encoding.
+        let bytes = encode_peer_value(10, &[127, 0, 0, 1, 0x1a, 0xe1]);
+        let peer = decode_peer(&bytes).unwrap();
+
+        assert_eq!(peer.expire_at.as_secs(), 10);
+        assert_eq!(peer.addr.as_bytes().len(), PEER_ADDR_V4_LENGTH);
+        assert_eq!(peer.last_seen.as_secs(), 0);
+        assert_eq!(peer.crypto_flag, 0);
+        assert_eq!(peer.key, None);
+
+        let bytes = encode_peer_value(1_700_000_060, &[0; PEER_ADDR_V6_LENGTH]);
+        let peer = decode_peer(&bytes).unwrap();
+
+        assert_eq!(peer.expire_at.as_secs(), 1_700_000_060);
+        assert_eq!(peer.addr.as_bytes().len(), PEER_ADDR_V6_LENGTH);
+    }
+
+    #[test]
+    fn test_peer_round_trip() {
+        let addr = PeerAddr::try_from([127, 0, 0, 1, 0x1a, 0xe1].as_slice()).unwrap();
+        let peer = Peer {
+            addr,
+            expire_at: Duration::from_secs(1_700_000_060),
+            last_seen: Duration::from_secs(1_700_000_000),
+            crypto_flag: 1,
+            key: None,
+        };
 
-fn from_str(msg: &'static str) -> RedisError {
-    (redis::ErrorKind::TypeError, msg).into()
+        let args = peer.to_redis_args();
+        let decoded = decode_peer(&args[0]).unwrap();
+        assert_eq!(decoded.addr, peer.addr);
+        assert_eq!(decoded.expire_at, peer.expire_at);
+        assert_eq!(decoded.last_seen, peer.last_seen);
+        assert_eq!(decoded.crypto_flag, 1);
+        assert_eq!(decoded.key, None);
+
+        let peer = Peer {
+            crypto_flag: 0,
+            key: Some(*b"abcd"),
+            ..peer
+        };
+
+        let args = peer.to_redis_args();
+        let decoded = decode_peer(&args[0]).unwrap();
+        assert_eq!(decoded.addr, peer.addr);
+        assert_eq!(decoded.expire_at, peer.expire_at);
+        assert_eq!(decoded.last_seen, peer.last_seen);
+        assert_eq!(decoded.crypto_flag, 0);
+        assert_eq!(decoded.key, Some(*b"abcd"));
+    }
+
+    #[test]
+    fn test_decode_peer_unknown_field() {
+        let addr = PeerAddr::try_from([127, 0, 0, 1, 0x1a, 0xe1].as_slice()).unwrap();
+        let peer = Peer {
+            addr,
+            expire_at: Duration::from_secs(10),
+            last_seen: Duration::from_secs(5),
+            crypto_flag: 1,
+            key: None,
+        };
+
+        let mut bytes = peer.to_redis_args().remove(0);
+        bytes.extend_from_slice(&[0xff, 3, 1, 2, 3]);
+
+        let decoded = decode_peer(&bytes).unwrap();
+        assert_eq!(decoded.expire_at, peer.expire_at);
+        assert_eq!(decoded.last_seen, peer.last_seen);
+        assert_eq!(decoded.crypto_flag, 1);
+
+        bytes.pop();
+        let err = decode_peer(&bytes).unwrap_err();
+        assert!(err.is_backend());
+        assert_eq!(err.to_string(), "invalid peer value: truncated field");
+    }
+
+    #[test]
+    fn test_decode_peer_invalid_length() {
+        for len in [7, 17] {
+            let bytes = encode_peer_value(10, &vec![0; len]);
+            let err = decode_peer(&bytes).unwrap_err();
+
+            assert!(err.is_backend());
+            assert_eq!(
+                err.to_string(),
+                format!("invalid peer address length: {}", len)
+            );
+        }
+
+        let err = decode_peer(&[0; 4]).unwrap_err();
+        assert!(err.is_backend());
+    }
+
+    #[test]
+    fn test_get_all_swarm_keys() {
+        let info_hash = InfoHash([0; 20]);
+        let torrent_key = TorrentKey(&info_hash).encode();
+
+        let (leecher, seeder, partial) =
+            SwarmKey::get_all_swarm_keys(torrent_key.as_ref(), IpType::V4);
+
+        assert_eq!(leecher.peer_type, PeerType::Leecher);
+        assert_eq!(seeder.peer_type, PeerType::Seeder);
+        assert_eq!(partial.peer_type, PeerType::Partial);
+
+        let keys = [leecher.encode(), seeder.encode(), partial.encode()];
+        for (i, key) in keys.iter().enumerate() {
+            assert!(key.starts_with(torrent_key.as_ref()));
+            assert!(keys[i + 1..].iter().all(|other| other != key));
+        }
+    }
 }
@@ -1,18 +1,85 @@
 use super::Result;
 use crate::storage::Error;
 use async_trait::async_trait;
-use redis::{Client, IntoConnectionInfo, RedisError};
+use redis::{Client, ErrorKind, IntoConnectionInfo, RedisError, RedisResult};
+use std::{future::Future, time::Duration};
 use ts_pool::{ManageConnection, Pool, PoolError, PooledConnection};
 
+/// The most attempts at connecting, e.g. while Redis restarts, before a connect fails.
+const CONNECT_ATTEMPTS: u32 = 5;
+
+/// The wait after the first failed attempt, doubled after each of the next ones.
+const INITIAL_CONNECT_BACKOFF: Duration = Duration::from_millis(50);
+
 #[derive(Clone, Debug)]
 pub struct RedisConnectionManager {
     client: Client,
+    retry: RetryPolicy,
 }
 
 impl RedisConnectionManager {
-    pub fn new<T: IntoConnectionInfo>(params: T) -> Self {
+    /// Creates a manager whose connects, retries included, give up after `connect_timeout`.
+    pub fn new<T: IntoConnectionInfo>(params: T, connect_timeout: Duration) -> Self {
         let client = Client::open(params).expect("Invalid connection URL");
-        Self { client }
+        let retry = RetryPolicy {
+            attempts: CONNECT_ATTEMPTS,
+            initial_backoff: INITIAL_CONNECT_BACKOFF,
+            timeout: connect_timeout,
+        };
+
+        Self { client, retry }
+    }
+}
+
+/// Retries failed connects that may succeed later, waiting exponentially longer in between.
+#[derive(Clone, Copy, Debug)]
+struct RetryPolicy {
+    attempts: u32,
+    initial_backoff: Duration,
+
+    /// The longest time spent on all the attempts together.
+    timeout: Duration,
+}
+
+impl RetryPolicy {
+    async fn run<T, F, Fut>(&self, mut connect: F) -> RedisResult<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = RedisResult<T>>,
+    {
+        let attempts = async {
+            let mut backoff = self.initial_backoff;
+            let mut attempt = 1;
+
+            loop {
+                match connect().await {
+                    Err(err) if attempt < self.attempts && is_reconnectable(&err) => {
+                        tokio::time::sleep(backoff).await;
+                        backoff *= 2;
+                        attempt += 1;
+                    }
+                    result => return result,
+                }
+            }
+        };
+
+        match tokio::time::timeout(self.timeout, attempts).await {
+            Ok(result) => result,
+            Err(_) => Err((ErrorKind::IoError, "timed out connecting to redis").into()),
+        }
+    }
+}
+
+/// Returns whether the error is one of the connection, which a new connection may not have.
+fn is_reconnectable(err: &RedisError) -> bool {
+    err.is_io_error() || err.is_connection_refusal() || err.is_connection_dropped()
+}
+
+async fn ping(conn: &mut redis::aio::Connection) -> RedisResult<()> {
+    let pong: String = redis::cmd("PING").query_async(conn).await?;
+    match pong.as_str() {
+        "PONG" => Ok(()),
+        _ => Err((ErrorKind::ResponseError, "ping request").into()),
     }
 }
 
@@ -22,14 +89,17 @@ impl ManageConnection for RedisConnectionManager {
     type Error = RedisError;
 
     async fn connect(&self) -> std::result::Result<Self::Connection, Self::Error> {
-        self.client.get_tokio_connection().await
+        self.retry.run(|| self.client.get_tokio_connection()).await
     }
 
+    /// A connection dropped by the server, e.g. on a restart, is replaced with a new one.
     async fn is_valid(&self, conn: &mut Self::Connection) -> std::result::Result<(), Self::Error> {
-        let pong: String = redis::cmd("PING").query_async(conn).await?;
-        match pong.as_str() {
-            "PONG" => Ok(()),
-            _ => Err((redis::ErrorKind::ResponseError, "ping request").into()),
+        match ping(conn).await {
+            Err(err) if is_reconnectable(&err) => {
+                *conn = self.connect().await?;
+                ping(conn).await
+            }
+            result => result,
         }
     }
 
@@ -59,3 +129,98 @@ impl From<PoolError> for Error {
         Self::runtime(Box::new(err))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tokio::time::Instant;
+
+    /// Fails the first `failures` connects with `err`, and succeeds after.
+    struct FlakyConnector {
+        failures: u32,
+        calls: AtomicU32,
+        err: fn() -> RedisError,
+    }
+
+    impl FlakyConnector {
+        fn new(failures: u32, err: fn() -> RedisError) -> Self {
+            Self {
+                failures,
+                calls: AtomicU32::new(0),
+                err,
+            }
+        }
+
+        async fn connect(&self) -> RedisResult<u32> {
+            let call = self.calls.fetch_add(1, Ordering::Relaxed) + 1;
+            match call > self.failures {
+                true => Ok(call),
+                false => Err((self.err)()),
+            }
+        }
+    }
+
+    fn refused() -> RedisError {
+        std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused").into()
+    }
+
+    fn auth_failed() -> RedisError {
+        (ErrorKind::AuthenticationFailed, "invalid password").into()
+    }
+
+    fn policy(timeout: Duration) -> RetryPolicy {
+        RetryPolicy {
+            attempts: 4,
+            initial_backoff: Duration::from_millis(10),
+            timeout,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_retried_with_backoff() {
+        let connector = FlakyConnector::new(3, refused);
+        let start = Instant::now();
+
+        let result = policy(Duration::from_secs(5))
+            .run(|| connector.connect())
+            .await;
+        assert_eq!(result.unwrap(), 4);
+
+        // Waited 10, 20 and 40 ms between the attempts.
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(70));
+        assert!(elapsed < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_connect_attempts_bounded() {
+        let connector = FlakyConnector::new(10, refused);
+        let result = policy(Duration::from_secs(5))
+            .run(|| connector.connect())
+            .await;
+        assert!(result.unwrap_err().is_connection_refusal());
+        assert_eq!(connector.calls.load(Ordering::Relaxed), 4);
+
+        // An error that another connection would get as well is not retried.
+        let connector = FlakyConnector::new(1, auth_failed);
+        let result = policy(Duration::from_secs(5))
+            .run(|| connector.connect())
+            .await;
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::AuthenticationFailed);
+        assert_eq!(connector.calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout() {
+        let connector = FlakyConnector::new(10, refused);
+        let start = Instant::now();
+
+        let result = policy(Duration::from_millis(25))
+            .run(|| connector.connect())
+            .await;
+        assert!(result.unwrap_err().is_io_error());
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(connector.calls.load(Ordering::Relaxed) < 4);
+    }
+}
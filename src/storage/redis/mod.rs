@@ -3,9 +3,9 @@ mod loader;
 mod manager;
 
 use async_trait::async_trait;
-use log::debug;
 use redis::{cmd, AsyncCommands, RedisResult, Script};
 use std::{
+    collections::HashMap,
     ops::DerefMut,
     sync::Arc,
     time::{self, Duration},
@@ -14,7 +14,10 @@ use ts_cache::{Cache, Policy};
 use ts_pool::{Pool, PooledConnection};
 
 use self::{
-    args::{SwarmKey, TorrentKey, TORRENT_COMPLETED_KEY},
+    args::{
+        SwarmKey, TorrentKey, TORRENT_ADDED_AT_KEY, TORRENT_COMPLETED_KEY, TORRENT_KEY_LEN,
+        TORRENT_KEY_PREFIX, TORRENT_NAME_KEY,
+    },
     loader::{SwarmStatsKey, SwarmStatsLoader, TorrentLoader},
     manager::{get_connection, RedisConnectionManager},
 };
@@ -24,14 +27,18 @@ use crate::{
     models::{
         common::{InfoHash, IpType},
         peer::{Peer, PeerType},
-        torrent::{PeerIdKey, SwarmStats, Torrent, TorrentStats, TorrentStatsList},
+        torrent::{
+            PeerIdKey, PeerList, SwarmPeers, SwarmStats, Torrent, TorrentStats, TorrentStatsList,
+        },
     },
+    utils::metrics::METRICS,
 };
 
 #[derive(Debug)]
 pub struct RedisStorage {
     pool: Arc<Pool<RedisConnectionManager>>,
     peer_idle_time_secs: usize,
+    pipeline_batch_size: usize,
     torrent_cache: Cache<InfoHash, Torrent>,
     swarm_stats_cache: Cache<SwarmStatsKey, SwarmStats>,
 }
@@ -44,7 +51,10 @@ impl RedisStorage {
             .as_ref()
             .expect("Redis config is not set");
 
-        let manager = RedisConnectionManager::new(redis_config.url.clone());
+        let manager = RedisConnectionManager::new(
+            redis_config.url.clone(),
+            redis_config.max_connection_wait_time,
+        );
 
         let pool = Pool::builder()
             .max_size(redis_config.max_connections)
@@ -67,11 +77,17 @@ impl RedisStorage {
         let swarm_stats_cache = Cache::<InfoHash, SwarmStats>::builder()
             .expiry(Duration::from_secs(15))
             .policy(Policy::RefreshAfterAccess)
+            .serve_stale(true)
+            .stale_max(Duration::from_secs(60))
             .build(SwarmStatsLoader::new(pool.clone()));
 
+        METRICS.register_cache("torrent", torrent_cache.stats());
+        METRICS.register_cache("swarm_stats", swarm_stats_cache.stats());
+
         Self {
             pool,
             peer_idle_time_secs,
+            pipeline_batch_size: redis_config.pipeline_batch_size,
             torrent_cache,
             swarm_stats_cache,
         }
@@ -80,15 +96,57 @@ impl RedisStorage {
     pub async fn get_connection(&self) -> Result<PooledConnection<'_, RedisConnectionManager>> {
         get_connection(&self.pool).await
     }
+
+    /// Applies a change of the swarm counts to the cached swarm stats, so that a peer sees its
+    /// own announce before the entry is refreshed. An uncached entry is left to the loader.
+    async fn update_swarm_stats(
+        &self,
+        info_hash: &InfoHash,
+        ip_type: IpType,
+        complete: i32,
+        incomplete: i32,
+    ) {
+        if complete == 0 && incomplete == 0 {
+            return;
+        }
+
+        self.swarm_stats_cache
+            .update((info_hash.clone(), ip_type), move |stats| {
+                stats.complete = stats.complete.saturating_add_signed(complete);
+                stats.incomplete = stats.incomplete.saturating_add_signed(incomplete);
+            })
+            .await;
+    }
 }
 
 #[async_trait]
 impl Storage for RedisStorage {
     async fn insert_torrent(&self, info_hash: &InfoHash, torrent: Option<Torrent>) -> Result<()> {
-        let _ = cmd("HSETNX")
-            .arg(TorrentKey(info_hash))
-            .arg(TORRENT_COMPLETED_KEY)
-            .arg(torrent.map(|v| v.completed).unwrap_or_default())
+        let mut pipe = redis::pipe();
+        insert_torrent_fields(
+            &mut pipe,
+            info_hash,
+            torrent.unwrap_or_default().registered(),
+        );
+
+        let _: () = pipe
+            .query_async(self.get_connection().await?.deref_mut())
+            .await?;
+
+        Ok(())
+    }
+
+    async fn insert_torrents(&self, items: Vec<(InfoHash, Option<Torrent>)>) -> Result<()> {
+        let mut pipe = redis::pipe();
+        for (info_hash, torrent) in items {
+            insert_torrent_fields(
+                &mut pipe,
+                &info_hash,
+                torrent.unwrap_or_default().registered(),
+            );
+        }
+
+        let _: () = pipe
             .query_async(self.get_connection().await?.deref_mut())
             .await?;
 
@@ -103,7 +161,7 @@ impl Storage for RedisStorage {
         Ok(self.torrent_cache.get(info_hash.into()).await.is_some())
     }
 
-    async fn remove_torrent(&mut self, info_hash: &InfoHash) -> Result<()> {
+    async fn remove_torrent(&self, info_hash: &InfoHash) -> Result<()> {
         self.get_connection()
             .await?
             .del(TorrentKey(info_hash))
@@ -128,10 +186,13 @@ impl Storage for RedisStorage {
         let swarm_stats = swarm_stats.unwrap_or_default();
         let torrent = torrent.unwrap_or_default();
 
+        // The cached swarm stats don't tell the leechers apart from the partial seeds.
         Ok(TorrentStats {
             seeders: swarm_stats.complete,
             completed: torrent.completed,
             incomplete: swarm_stats.incomplete,
+            downloaders: None,
+            by_ip_type: None,
         })
     }
 
@@ -140,43 +201,123 @@ impl Storage for RedisStorage {
         info_hashes: Vec<InfoHash>,
         ip_type: IpType,
     ) -> Result<TorrentStatsList> {
-        let mut pipe = redis::pipe();
-
-        for info_hash in &info_hashes {
-            let torrent_key = TorrentKey(info_hash).encode();
-            let (swarm_key_leecher, swarm_key_seeder, swarm_key_partial) =
-                SwarmKey::get_all_swarm_keys(torrent_key.as_ref(), ip_type);
+        let (unique, positions) = dedup_info_hashes(&info_hashes);
 
-            pipe.hget(torrent_key.as_ref(), TORRENT_COMPLETED_KEY)
-                .hlen(swarm_key_leecher)
-                .hlen(swarm_key_seeder)
-                .hlen(swarm_key_partial);
+        // A single pipeline for a large multi-scrape would hold the connection for too long.
+        let mut conn = self.get_connection().await?;
+        let mut results: Vec<TorrentStatsTuple> = Vec::with_capacity(unique.len());
+        for batch in unique.chunks(self.pipeline_batch_size) {
+            let batch_results: Vec<TorrentStatsTuple> = torrent_stats_pipeline(batch, &[ip_type])
+                .query_async(conn.deref_mut())
+                .await?;
+            results.extend(batch_results);
         }
 
-        let mut conn = self.get_connection().await?;
-        let results: Vec<TorrentStatsTuple> = pipe.query_async(conn.deref_mut()).await?;
+        let stats = results
+            .into_iter()
+            .map(|v| {
+                let (completed, leechers, seeders, partial_seeds) = v;
+                let incomplete = leechers + partial_seeds;
+                let completed = completed.unwrap_or_default();
+
+                TorrentStats {
+                    completed,
+                    seeders,
+                    incomplete,
+                    downloaders: Some(leechers),
+                    by_ip_type: None,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Ok(map_to_positions(info_hashes, &positions, &stats))
+    }
 
-        let results = info_hashes.into_iter().zip(results.into_iter().map(|v| {
-            let (completed, leechers, seeders, partial_seeds) = v;
-            let incomplete = leechers + partial_seeds;
-            let completed = completed.unwrap_or_default();
+    async fn get_torrent_stats_multi_ip(
+        &self,
+        info_hashes: Vec<InfoHash>,
+    ) -> Result<TorrentStatsList> {
+        let ip_types = [IpType::V4, IpType::V6];
 
-            TorrentStats {
-                completed,
-                seeders,
-                incomplete,
-            }
-        }));
+        let (unique, positions) = dedup_info_hashes(&info_hashes);
+
+        let mut conn = self.get_connection().await?;
+        let mut results: Vec<MultiIpTorrentStatsTuple> = Vec::with_capacity(unique.len());
+        for batch in unique.chunks(self.pipeline_batch_size) {
+            let batch_results: Vec<MultiIpTorrentStatsTuple> =
+                torrent_stats_pipeline(batch, &ip_types)
+                    .query_async(conn.deref_mut())
+                    .await?;
+            results.extend(batch_results);
+        }
 
-        Ok(results.collect::<Vec<_>>())
+        let stats = results
+            .into_iter()
+            .map(|v| {
+                let (completed, leechers4, seeders4, partial4, leechers6, seeders6, partial6) = v;
+                let leechers = leechers4 + leechers6;
+
+                TorrentStats {
+                    completed: completed.unwrap_or_default(),
+                    seeders: seeders4 + seeders6,
+                    incomplete: leechers + partial4 + partial6,
+                    downloaders: Some(leechers),
+                    by_ip_type: None,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Ok(map_to_positions(info_hashes, &positions, &stats))
     }
 
     async fn get_all_torrent_stats(
         &self,
-        _processor: &mut dyn Processor<TorrentStatsList>,
+        processor: &mut dyn Processor<TorrentStatsList>,
     ) -> Result<()> {
-        debug!("get_all_torrent_stats is not implemented for redis storage");
-        Ok(())
+        let ip_types = [IpType::V4, IpType::V6];
+        let pattern = [TORRENT_KEY_PREFIX, b"*"].concat();
+
+        // SCAN may return a key more than once, and the torrent then appears twice.
+        let mut conn = self.get_connection().await?;
+        let mut cursor = 0u64;
+        loop {
+            let (next, keys): (u64, Vec<Vec<u8>>) = cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(self.pipeline_batch_size)
+                .query_async(conn.deref_mut())
+                .await?;
+
+            let info_hashes = keys
+                .iter()
+                .filter_map(|key| parse_torrent_key(key))
+                .collect::<Vec<_>>();
+
+            if !info_hashes.is_empty() {
+                let results: Vec<MultiIpTorrentStatsTuple> =
+                    torrent_stats_pipeline(&info_hashes, &ip_types)
+                        .query_async(conn.deref_mut())
+                        .await?;
+
+                let stats = info_hashes
+                    .into_iter()
+                    .zip(results)
+                    .map(|(info_hash, v)| (info_hash, full_scrape_stats(v)))
+                    .collect::<TorrentStatsList>();
+
+                if !processor.process(&stats) {
+                    return Ok(());
+                }
+            }
+
+            if next == 0 {
+                return Ok(());
+            }
+
+            cursor = next;
+        }
     }
 
     async fn put_peer_in_swarm(
@@ -209,23 +350,25 @@ impl Storage for RedisStorage {
             peer_ip_type: peer.ip_type(),
         };
 
-        let mut insert_peer = cmd("HMSET");
+        // HDEL returns whether the peer was a leecher, and HSET whether it is a new seeder.
+        // A peer promoted without a prior leecher entry is still inserted and counted, while a
+        // repeated `completed` finds it already a seeder and is not.
+        let mut insert_peer = cmd("HSET");
         insert_peer
             .arg(&new_swarm_key)
             .arg(peer_id_key.as_ref())
             .arg(peer);
 
         let mut conn = self.get_connection().await?;
-        let (is_completed,): (bool,) = redis::pipe()
+        let (was_leecher, is_new_seeder): (bool, bool) = redis::pipe()
             .hdel(old_swarm_key, peer_id_key.as_ref())
             .add_command(insert_peer)
-            .ignore()
             .expire(&new_swarm_key, self.peer_idle_time_secs)
             .ignore()
             .query_async(conn.deref_mut())
             .await?;
 
-        if is_completed {
+        if was_leecher || is_new_seeder {
             let _: RedisResult<usize> = cmd("HINCRBY")
                 .arg(torrent_key.as_ref())
                 .arg(TORRENT_COMPLETED_KEY)
@@ -234,6 +377,14 @@ impl Storage for RedisStorage {
                 .await;
         }
 
+        self.update_swarm_stats(
+            info_hash,
+            peer.ip_type(),
+            is_new_seeder as i32,
+            -(was_leecher as i32),
+        )
+        .await;
+
         Ok(())
     }
 
@@ -245,25 +396,54 @@ impl Storage for RedisStorage {
         peer_type: PeerType,
     ) -> Result<()> {
         let torrent_key = TorrentKey(info_hash).encode();
+        let ip_type = peer.ip_type();
         let swarm_key = SwarmKey {
             peer_type,
             torrent_key: torrent_key.as_ref(),
-            peer_ip_type: peer.ip_type(),
+            peer_ip_type: ip_type,
         };
 
-        let mut insert_peer = cmd("HMSET");
+        // The peer is removed from the swarms of the other types, in case its type changed
+        // since its last announce. HDEL returns whether it was there, and HSET whether the
+        // peer is new to its swarm, as opposed to updated.
+        let other_types: Vec<PeerType> = [PeerType::Leecher, PeerType::Seeder, PeerType::Partial]
+            .into_iter()
+            .filter(|other| *other != peer_type)
+            .collect();
+
+        let mut pipe = redis::pipe();
+        for other in &other_types {
+            let other_swarm_key = SwarmKey {
+                peer_type: *other,
+                torrent_key: torrent_key.as_ref(),
+                peer_ip_type: ip_type,
+            };
+            pipe.hdel(other_swarm_key, peer_id_key.as_ref());
+        }
+
+        let mut insert_peer = cmd("HSET");
         insert_peer
             .arg(&swarm_key)
             .arg(peer_id_key.as_ref())
             .arg(peer);
 
-        redis::pipe()
+        let (was_other_a, was_other_b, is_new): (bool, bool, bool) = pipe
             .add_command(insert_peer)
             .expire(&swarm_key, self.peer_idle_time_secs)
             .ignore()
             .query_async(self.get_connection().await?.deref_mut())
             .await?;
 
+        let (mut complete, mut incomplete) = swarm_stats_delta(peer_type, is_new as i32);
+        for (other, removed) in other_types.into_iter().zip([was_other_a, was_other_b]) {
+            let delta = swarm_stats_delta(other, -(removed as i32));
+            complete += delta.0;
+            incomplete += delta.1;
+        }
+
+        self.update_swarm_stats(info_hash, ip_type, complete, incomplete)
+            .await;
+
         Ok(())
     }
 
@@ -272,17 +452,44 @@ impl Storage for RedisStorage {
         info_hash: &InfoHash,
         peer_type: PeerType,
         ip_type: IpType,
-        _extractor: &mut dyn PeerExtractor,
+        extractor: &mut dyn PeerExtractor,
     ) -> Result<SwarmStats> {
+        // The loader only yields no stats when redis fails, and the cache keeps serving the
+        // last known stats over failed refreshes. Reporting an empty swarm would mislead peers.
         let stats = self
             .swarm_stats_cache
             .get((info_hash.clone(), ip_type).into())
             .await
-            .unwrap_or_default();
+            .ok_or("Failed to extract peers: swarm stats unavailable")?;
 
-        match peer_type {
-            PeerType::Leecher => {}
-            _ => {}
+        let torrent_key = TorrentKey(info_hash).encode();
+        let (swarm_key_leecher, swarm_key_seeder, swarm_key_partial) =
+            SwarmKey::get_all_swarm_keys(torrent_key.as_ref(), ip_type);
+
+        // As on memory, leechers are given peers of every type, and the others only leechers.
+        let swarm_keys = match peer_type {
+            PeerType::Leecher => vec![swarm_key_seeder, swarm_key_leecher, swarm_key_partial],
+            _ => vec![swarm_key_leecher],
+        };
+
+        // The swarms are read in batches of what the extractor still takes, so that a large
+        // swarm isn't read whole for a few peers.
+        let mut conn = self.get_connection().await?;
+        for swarm_key in swarm_keys {
+            let mut cursor = 0u64;
+            while extractor.remaining() > 0 {
+                let count = extractor.remaining();
+                let (next, peers) = scan_swarm(conn.deref_mut(), &swarm_key, cursor, count).await?;
+
+                if !peers.is_empty() && !extractor.from_list(&peers) {
+                    return Ok(stats);
+                }
+
+                match next {
+                    0 => break,
+                    next => cursor = next,
+                }
+            }
         }
 
         Ok(stats)
@@ -292,30 +499,226 @@ impl Storage for RedisStorage {
         &self,
         info_hash: &InfoHash,
         peer_id_key: &PeerIdKey,
-        peer_type: PeerType,
         peer_ip_type: IpType,
     ) -> Result<()> {
         let torrent_key = TorrentKey(info_hash).encode();
-        let swarm_key = SwarmKey {
-            torrent_key: torrent_key.as_ref(),
-            peer_type,
-            peer_ip_type,
-        };
+
+        let (swarm_key_leecher, swarm_key_seeder, swarm_key_partial) =
+            SwarmKey::get_all_swarm_keys(torrent_key.as_ref(), peer_ip_type);
 
         let mut conn = self.get_connection().await?;
-        cmd("hdel")
-            .arg(swarm_key)
-            .arg(peer_id_key.as_ref())
+        let (leecher, seeder, partial): (bool, bool, bool) = redis::pipe()
+            .hdel(swarm_key_leecher, peer_id_key.as_ref())
+            .hdel(swarm_key_seeder, peer_id_key.as_ref())
+            .hdel(swarm_key_partial, peer_id_key.as_ref())
             .query_async(conn.deref_mut())
             .await?;
 
+        let complete = -(seeder as i32);
+        let incomplete = -(leecher as i32) - (partial as i32);
+        self.update_swarm_stats(info_hash, peer_ip_type, complete, incomplete)
+            .await;
+
+        Ok(())
+    }
+
+    async fn clear_swarm(&self, info_hash: &InfoHash) -> Result<()> {
+        let torrent_key = TorrentKey(info_hash).encode();
+        let mut pipe = redis::pipe();
+
+        // The torrent hash, and with it the completed count, is left untouched.
+        for ip_type in [IpType::V4, IpType::V6] {
+            let (swarm_key_leecher, swarm_key_seeder, swarm_key_partial) =
+                SwarmKey::get_all_swarm_keys(torrent_key.as_ref(), ip_type);
+
+            pipe.del(swarm_key_leecher)
+                .ignore()
+                .del(swarm_key_seeder)
+                .ignore()
+                .del(swarm_key_partial)
+                .ignore();
+        }
+
+        let mut conn = self.get_connection().await?;
+        pipe.query_async(conn.deref_mut()).await?;
+
+        for ip_type in [IpType::V4, IpType::V6] {
+            let _ = self
+                .swarm_stats_cache
+                .invalidate((info_hash.clone(), ip_type).into())
+                .await;
+        }
+
         Ok(())
     }
+
+    async fn list_swarm(
+        &self,
+        info_hash: &InfoHash,
+        ip_type: IpType,
+        limit: usize,
+    ) -> Result<SwarmPeers> {
+        let torrent_key = TorrentKey(info_hash).encode();
+        let (swarm_key_leecher, swarm_key_seeder, swarm_key_partial) =
+            SwarmKey::get_all_swarm_keys(torrent_key.as_ref(), ip_type);
+
+        let swarms = [
+            (PeerType::Leecher, swarm_key_leecher),
+            (PeerType::Seeder, swarm_key_seeder),
+            (PeerType::Partial, swarm_key_partial),
+        ];
+
+        // HSCAN may return a peer more than once, and the peer then appears twice.
+        let mut conn = self.get_connection().await?;
+        let mut peers = SwarmPeers::new();
+        for (peer_type, swarm_key) in swarms {
+            let mut cursor = 0u64;
+            while peers.len() < limit {
+                let count = limit - peers.len();
+                let (next, entries) =
+                    scan_swarm(conn.deref_mut(), &swarm_key, cursor, count).await?;
+
+                peers.extend(
+                    entries
+                        .into_iter()
+                        .take(count)
+                        .map(|(key, peer)| (key, peer_type, peer)),
+                );
+
+                match next {
+                    0 => break,
+                    next => cursor = next,
+                }
+            }
+        }
+
+        Ok(peers)
+    }
+
+    async fn has_peer_in_swarm(
+        &self,
+        info_hash: &InfoHash,
+        peer_id_key: &PeerIdKey,
+        peer_ip_type: IpType,
+    ) -> Result<bool> {
+        let torrent_key = TorrentKey(info_hash).encode();
+        let (swarm_key_leecher, swarm_key_seeder, swarm_key_partial) =
+            SwarmKey::get_all_swarm_keys(torrent_key.as_ref(), peer_ip_type);
+
+        let mut conn = self.get_connection().await?;
+        let (leecher, seeder, partial): (bool, bool, bool) = redis::pipe()
+            .hexists(swarm_key_leecher, peer_id_key.as_ref())
+            .hexists(swarm_key_seeder, peer_id_key.as_ref())
+            .hexists(swarm_key_partial, peer_id_key.as_ref())
+            .query_async(conn.deref_mut())
+            .await?;
+
+        Ok(leecher || seeder || partial)
+    }
+
+    async fn get_peer_in_swarm(
+        &self,
+        info_hash: &InfoHash,
+        peer_id_key: &PeerIdKey,
+        peer_ip_type: IpType,
+    ) -> Result<Option<Peer>> {
+        let torrent_key = TorrentKey(info_hash).encode();
+        let (swarm_key_leecher, swarm_key_seeder, swarm_key_partial) =
+            SwarmKey::get_all_swarm_keys(torrent_key.as_ref(), peer_ip_type);
+
+        type SwarmEntry = Option<Peer>;
+
+        let mut conn = self.get_connection().await?;
+        let (leecher, seeder, partial): (SwarmEntry, SwarmEntry, SwarmEntry) = redis::pipe()
+            .hget(swarm_key_leecher, peer_id_key.as_ref())
+            .hget(swarm_key_seeder, peer_id_key.as_ref())
+            .hget(swarm_key_partial, peer_id_key.as_ref())
+            .query_async(conn.deref_mut())
+            .await?;
+
+        Ok(leecher.or(seeder).or(partial))
+    }
+}
+
+/// Returns the distinct infohashes, in order of first occurrence, and for each of the given
+/// infohashes the index of its stats among them. Repeated infohashes share a single lookup.
+fn dedup_info_hashes(info_hashes: &[InfoHash]) -> (Vec<InfoHash>, Vec<usize>) {
+    let mut indexes: HashMap<&InfoHash, usize> = HashMap::with_capacity(info_hashes.len());
+    let mut unique = Vec::new();
+
+    let positions = info_hashes
+        .iter()
+        .map(|info_hash| {
+            *indexes.entry(info_hash).or_insert_with(|| {
+                unique.push(info_hash.clone());
+                unique.len() - 1
+            })
+        })
+        .collect();
+
+    (unique, positions)
+}
+
+/// Pairs each of the given infohashes with the stats at its position.
+fn map_to_positions(
+    info_hashes: Vec<InfoHash>,
+    positions: &[usize],
+    stats: &[TorrentStats],
+) -> TorrentStatsList {
+    info_hashes
+        .into_iter()
+        .zip(positions)
+        .filter_map(|(info_hash, i)| Some((info_hash, stats.get(*i)?.clone())))
+        .collect()
+}
+
+/// The change of the (complete, incomplete) swarm counts for `count` peers of `peer_type`.
+fn swarm_stats_delta(peer_type: PeerType, count: i32) -> (i32, i32) {
+    match peer_type {
+        PeerType::Seeder => (count, 0),
+        _ => (0, count),
+    }
+}
+
+/// Returns the infohash of a torrent key, or `None` for the other keys, e.g. of swarms.
+fn parse_torrent_key(key: &[u8]) -> Option<InfoHash> {
+    if key.len() != TORRENT_KEY_LEN || !key.starts_with(TORRENT_KEY_PREFIX) {
+        return None;
+    }
+
+    std::str::from_utf8(&key[TORRENT_KEY_PREFIX.len()..])
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// The stats of a torrent in a full scrape, with the counts of each IP family, as on memory.
+fn full_scrape_stats(v: MultiIpTorrentStatsTuple) -> TorrentStats {
+    let (completed, leechers4, seeders4, partial4, leechers6, seeders6, partial6) = v;
+    let v4 = SwarmStats {
+        complete: seeders4,
+        incomplete: leechers4 + partial4,
+    };
+    let v6 = SwarmStats {
+        complete: seeders6,
+        incomplete: leechers6 + partial6,
+    };
+
+    TorrentStats {
+        completed: completed.unwrap_or_default(),
+        seeders: v4.complete + v6.complete,
+        incomplete: v4.incomplete + v6.incomplete,
+        downloaders: None,
+        by_ip_type: Some([v4, v6]),
+    }
 }
 
 /// The tuple of torrent stats. (completed, leechers, seeders, partial seeds)
 type TorrentStatsTuple = (Option<u32>, u32, u32, u32);
 
+/// The tuple of torrent stats with the swarm counts of IPv4 followed by IPv6.
+type MultiIpTorrentStatsTuple = (Option<u32>, u32, u32, u32, u32, u32, u32);
+
 // let result = SCRIPT
 //     .key(info_hash)
 //     .arg(1)
@@ -332,3 +735,118 @@ lazy_static! {
         "
     );
 }
+
+/// Adds the fields of a newly registered torrent to `pipe`, keeping those of a registered one.
+fn insert_torrent_fields(pipe: &mut redis::Pipeline, info_hash: &InfoHash, torrent: Torrent) {
+    let torrent_key = TorrentKey(info_hash).encode();
+
+    pipe.hset_nx(
+        torrent_key.as_ref(),
+        TORRENT_COMPLETED_KEY,
+        torrent.completed,
+    )
+    .ignore()
+    .hset_nx(torrent_key.as_ref(), TORRENT_ADDED_AT_KEY, torrent.added_at)
+    .ignore();
+
+    if let Some(name) = torrent.name {
+        pipe.hset_nx(torrent_key.as_ref(), TORRENT_NAME_KEY, name)
+            .ignore();
+    }
+}
+
+/// Reads about `count` peers of the swarm at `swarm_key` with HSCAN from `cursor`. Returns the
+/// cursor to continue from, `0` once the whole swarm was read, along with the peers.
+async fn scan_swarm(
+    conn: &mut redis::aio::Connection,
+    swarm_key: &SwarmKey<'_>,
+    cursor: u64,
+    count: usize,
+) -> Result<(u64, PeerList)> {
+    let (cursor, entries): (u64, Vec<(Vec<u8>, Peer)>) = cmd("HSCAN")
+        .arg(swarm_key)
+        .arg(cursor)
+        .arg("COUNT")
+        .arg(count)
+        .query_async(conn)
+        .await?;
+
+    let peers = entries
+        .into_iter()
+        .map(|(key, peer)| (key.into(), peer))
+        .collect();
+
+    Ok((cursor, peers))
+}
+
+/// Builds a pipeline of the commands returning the completed count of each of the torrents,
+/// followed by its leecher, seeder and partial seed counts for each of the IP types.
+fn torrent_stats_pipeline(info_hashes: &[InfoHash], ip_types: &[IpType]) -> redis::Pipeline {
+    let mut pipe = redis::pipe();
+
+    for info_hash in info_hashes {
+        let torrent_key = TorrentKey(info_hash).encode();
+        pipe.hget(torrent_key.as_ref(), TORRENT_COMPLETED_KEY);
+
+        for ip_type in ip_types {
+            let (swarm_key_leecher, swarm_key_seeder, swarm_key_partial) =
+                SwarmKey::get_all_swarm_keys(torrent_key.as_ref(), *ip_type);
+
+            pipe.hlen(swarm_key_leecher)
+                .hlen(swarm_key_seeder)
+                .hlen(swarm_key_partial);
+        }
+    }
+
+    pipe
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::common::INFOHASH_LENGTH;
+
+    #[test]
+    fn test_torrent_stats_pipeline_batches() {
+        let info_hashes = (0..5u8)
+            .map(|i| InfoHash([i; INFOHASH_LENGTH]))
+            .collect::<Vec<_>>();
+
+        let pipeline = torrent_stats_pipeline(&info_hashes, &[IpType::V4]).get_packed_pipeline();
+        let batches = info_hashes
+            .chunks(2)
+            .flat_map(|batch| torrent_stats_pipeline(batch, &[IpType::V4]).get_packed_pipeline())
+            .collect::<Vec<_>>();
+
+        // The batches issue the same commands, in the same order, as a single pipeline.
+        assert_eq!(batches, pipeline);
+    }
+
+    #[test]
+    fn test_dedup_info_hashes() {
+        let a = InfoHash([b'a'; INFOHASH_LENGTH]);
+        let b = InfoHash([b'b'; INFOHASH_LENGTH]);
+        let info_hashes = vec![a.clone(), b.clone(), a.clone()];
+
+        let (unique, positions) = dedup_info_hashes(&info_hashes);
+        assert_eq!(unique, vec![a.clone(), b]);
+        assert_eq!(positions, vec![0, 1, 0]);
+
+        let stats = [5, 7].map(|seeders| TorrentStats {
+            seeders,
+            ..Default::default()
+        });
+
+        let results = map_to_positions(info_hashes, &positions, &stats);
+        assert_eq!(results.len(), 3);
+        assert_eq!((&results[0].0, results[0].1.seeders), (&a, 5));
+        assert_eq!((&results[2].0, results[2].1.seeders), (&a, 5));
+        assert_eq!(results[1].1.seeders, 7);
+    }
+
+    // There is no mock of Redis, so these run against the server of the default config.
+    test_storage_conformance!(
+        #[ignore = "requires a Redis server"]
+        RedisStorage::new(std::sync::Arc::new(crate::config::TSConfig::new().unwrap()))
+    );
+}
@@ -0,0 +1,273 @@
+//! Process-wide counters, rendered in the Prometheus text format.
+
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use ts_cache::CacheStats;
+
+use crate::models::common::Protocol;
+
+/// The upper bounds, in bytes, of the response size histogram buckets.
+const SIZE_BUCKETS: [u64; 8] = [64, 128, 256, 512, 1024, 4096, 16384, 65536];
+
+/// The HTTP methods counted by a `MethodCounter`. Any other method is counted as `other`.
+const METHODS: [&str; 8] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "OPTIONS", "PATCH", "other",
+];
+
+pub static METRICS: Metrics = Metrics::new();
+
+pub struct Metrics {
+    /// The announce requests received, by protocol.
+    pub announces: ProtocolCounter,
+
+    /// The scrape requests received, by protocol.
+    pub scrapes: ProtocolCounter,
+
+    /// The sizes of bencoded announce responses.
+    pub announce_response_size: Histogram,
+
+    /// The sizes of bencoded scrape responses.
+    pub scrape_response_size: Histogram,
+
+    /// The sizes of generated full scrape responses.
+    pub full_scrape_response_size: Histogram,
+
+    /// The HTTP requests to an unknown route, by method. A sign of scanning or misconfiguration.
+    pub not_found: MethodCounter,
+
+    /// The lookup counters of the registered caches, by name.
+    caches: Mutex<Vec<(&'static str, Arc<CacheStats>)>>,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            announces: ProtocolCounter::new(),
+            scrapes: ProtocolCounter::new(),
+            announce_response_size: Histogram::new(),
+            scrape_response_size: Histogram::new(),
+            full_scrape_response_size: Histogram::new(),
+            not_found: MethodCounter::new(),
+            caches: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Adds the counters of a cache to the rendered metrics.
+    pub fn register_cache(&self, name: &'static str, stats: Arc<CacheStats>) {
+        if let Ok(mut caches) = self.caches.lock() {
+            caches.push((name, stats));
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        self.announces.render(&mut out, "ts_announces_total");
+        self.scrapes.render(&mut out, "ts_scrapes_total");
+        self.not_found.render(&mut out, "ts_http_not_found_total");
+
+        let histograms = [
+            (&self.announce_response_size, "ts_announce_response_bytes"),
+            (&self.scrape_response_size, "ts_scrape_response_bytes"),
+            (
+                &self.full_scrape_response_size,
+                "ts_full_scrape_response_bytes",
+            ),
+        ];
+
+        for (histogram, name) in histograms {
+            histogram.render(&mut out, name);
+        }
+
+        if let Ok(caches) = self.caches.lock() {
+            let _ = writeln!(out, "# TYPE ts_cache_hits_total counter");
+            for (name, stats) in caches.iter() {
+                let _ = writeln!(
+                    out,
+                    "ts_cache_hits_total{{cache=\"{}\"}} {}",
+                    name,
+                    stats.hits()
+                );
+            }
+
+            let _ = writeln!(out, "# TYPE ts_cache_misses_total counter");
+            for (name, stats) in caches.iter() {
+                let _ = writeln!(
+                    out,
+                    "ts_cache_misses_total{{cache=\"{}\"}} {}",
+                    name,
+                    stats.misses()
+                );
+            }
+        }
+
+        out
+    }
+}
+
+/// A counter with a value per protocol.
+pub struct ProtocolCounter {
+    counts: [AtomicU64; Protocol::ALL.len()],
+}
+
+impl ProtocolCounter {
+    const fn new() -> Self {
+        Self {
+            counts: [const { AtomicU64::new(0) }; Protocol::ALL.len()],
+        }
+    }
+
+    pub fn increment(&self, protocol: Protocol) {
+        self.counts[protocol as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, protocol: Protocol) -> u64 {
+        self.counts[protocol as usize].load(Ordering::Relaxed)
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for protocol in Protocol::ALL {
+            let (label, count) = (protocol.as_str(), self.get(protocol));
+            let _ = writeln!(out, "{}{{protocol=\"{}\"}} {}", name, label, count);
+        }
+    }
+}
+
+/// A counter with a value per HTTP method, of which there are only `METHODS`.
+pub struct MethodCounter {
+    counts: [AtomicU64; METHODS.len()],
+}
+
+impl MethodCounter {
+    const fn new() -> Self {
+        Self {
+            counts: [const { AtomicU64::new(0) }; METHODS.len()],
+        }
+    }
+
+    pub fn increment(&self, method: &str) {
+        self.counts[Self::index(method)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, method: &str) -> u64 {
+        self.counts[Self::index(method)].load(Ordering::Relaxed)
+    }
+
+    fn index(method: &str) -> usize {
+        METHODS[..METHODS.len() - 1]
+            .iter()
+            .position(|known| *known == method)
+            .unwrap_or(METHODS.len() - 1)
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (method, count) in METHODS.iter().zip(&self.counts) {
+            let count = count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}{{method=\"{}\"}} {}", name, method, count);
+        }
+    }
+}
+
+/// A histogram of byte sizes over `SIZE_BUCKETS`.
+pub struct Histogram {
+    /// The number of observations per bucket, the last one holding those above every bound.
+    buckets: [AtomicU64; SIZE_BUCKETS.len() + 1],
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; SIZE_BUCKETS.len() + 1],
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, size: usize) {
+        let size = size as u64;
+        let index = SIZE_BUCKETS
+            .iter()
+            .position(|bound| size <= *bound)
+            .unwrap_or(SIZE_BUCKETS.len());
+
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(size, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+
+        // Prometheus buckets are cumulative.
+        let mut count = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            count += bucket.load(Ordering::Relaxed);
+            let _ = match SIZE_BUCKETS.get(i) {
+                Some(bound) => writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count),
+                None => writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count),
+            };
+        }
+
+        let sum = self.sum.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol_counter_render() {
+        let counter = ProtocolCounter::new();
+        counter.increment(Protocol::Udp);
+        counter.increment(Protocol::Udp);
+
+        let mut out = String::new();
+        counter.render(&mut out, "requests_total");
+
+        assert!(out.contains("requests_total{protocol=\"http\"} 0\n"));
+        assert!(out.contains("requests_total{protocol=\"udp\"} 2\n"));
+    }
+
+    #[test]
+    fn test_method_counter_render() {
+        let counter = MethodCounter::new();
+        counter.increment("GET");
+        counter.increment("PROPFIND");
+        counter.increment("BREW");
+
+        let mut out = String::new();
+        counter.render(&mut out, "not_found_total");
+
+        assert!(out.contains("not_found_total{method=\"GET\"} 1\n"));
+        assert!(out.contains("not_found_total{method=\"POST\"} 0\n"));
+        assert!(out.contains("not_found_total{method=\"other\"} 2\n"));
+        assert!(!out.contains("PROPFIND"));
+    }
+
+    #[test]
+    fn test_histogram_render() {
+        let histogram = Histogram::new();
+        histogram.observe(10);
+        histogram.observe(100);
+        histogram.observe(100_000);
+
+        let mut out = String::new();
+        histogram.render(&mut out, "size");
+
+        assert!(out.contains("size_bucket{le=\"64\"} 1\n"));
+        assert!(out.contains("size_bucket{le=\"128\"} 2\n"));
+        assert!(out.contains("size_bucket{le=\"65536\"} 2\n"));
+        assert!(out.contains("size_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("size_sum 100110\n"));
+        assert!(out.contains("size_count 3\n"));
+    }
+}
@@ -1,3 +1,28 @@
+pub mod metrics;
+
+use crate::config::TSConfig;
+use log::LevelFilter;
+use std::{
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Returns a logger builder at the configured `log_level`, with the levels of `log_filters`
+/// applied to their modules.
+pub fn logger_builder(config: &TSConfig) -> env_logger::Builder {
+    let log_level = LevelFilter::from_str(config.log_level()).unwrap();
+
+    let mut builder = env_logger::builder();
+    builder.filter_level(log_level).format_timestamp(None);
+
+    for (module, level) in config.log_filters().unwrap_or_default() {
+        builder.filter_module(module, level);
+    }
+
+    builder
+}
+
 /// The `Loggable` trait represents an interface for objects that can be logged.
 pub trait Loggable {
     /// Logs information about the object and returns a formatted log message as a String.
@@ -8,3 +33,70 @@ pub trait Loggable {
     /// The log message as a String containing the information about the object.
     fn log(&self) -> String;
 }
+
+/// An id that correlates the log lines of a single request across the server and the worker.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct RequestId(u64);
+
+impl RequestId {
+    /// Returns the next id of a process-wide monotonic counter.
+    pub fn next() -> Self {
+        static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+        Self(NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:08x}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::{Level, Log, Metadata};
+
+    #[test]
+    fn test_logger_builder_module_levels() {
+        let mut config = TSConfig::new().unwrap();
+        config.log_level = "warn".to_string();
+        config.log_filters = "ts_tracker=debug, ts_pool = error".to_string();
+
+        let logger = logger_builder(&config).build();
+        let enabled = |target: &str, level: Level| {
+            logger.enabled(&Metadata::builder().target(target).level(level).build())
+        };
+
+        assert!(enabled("ts_tracker::worker", Level::Debug));
+        assert!(!enabled("ts_tracker::worker", Level::Trace));
+        assert!(!enabled("ts_pool", Level::Warn));
+        assert!(enabled("ts_pool", Level::Error));
+
+        // Modules without a filter fall back to `log_level`.
+        assert!(enabled("ts_cache", Level::Warn));
+        assert!(!enabled("ts_cache", Level::Info));
+    }
+
+    #[test]
+    fn test_invalid_log_filters() {
+        let mut config = TSConfig::new().unwrap();
+        for filters in [
+            "ts_tracker",
+            "ts_tracker=loud",
+            "=debug",
+            "ts_tracker=debug,,=info",
+        ] {
+            config.log_filters = filters.to_string();
+            assert!(config.log_filters().is_err(), "{}", filters);
+            assert!(config
+                .validate()
+                .unwrap_err()
+                .to_string()
+                .contains("log_filters"));
+        }
+
+        config.log_filters = " , ".to_string();
+        assert_eq!(config.log_filters().unwrap(), vec![]);
+    }
+}
@@ -2,7 +2,7 @@ use std::error::Error as StdError;
 use std::fmt;
 
 use super::TaskPacket;
-use crate::storage;
+use crate::{constants, storage};
 use tokio::sync::{mpsc, oneshot};
 
 type Cause = Box<dyn StdError + Send + Sync>;
@@ -20,6 +20,7 @@ struct ErrorImpl {
 #[derive(Debug)]
 enum Kind {
     Send,
+    Saturated,
     Recv,
     Storage,
     Custom(&'static str),
@@ -36,6 +37,22 @@ impl From<mpsc::error::SendError<TaskPacket>> for Error {
     }
 }
 
+impl From<mpsc::error::TrySendError<TaskPacket>> for Error {
+    fn from(err: mpsc::error::TrySendError<TaskPacket>) -> Self {
+        let kind = match err {
+            mpsc::error::TrySendError::Full(_) => Kind::Saturated,
+            mpsc::error::TrySendError::Closed(_) => Kind::Send,
+        };
+
+        Self {
+            inner: Box::new(ErrorImpl {
+                kind,
+                cause: Some(Box::new(err)),
+            }),
+        }
+    }
+}
+
 impl From<oneshot::error::RecvError> for Error {
     fn from(err: oneshot::error::RecvError) -> Self {
         Self {
@@ -70,14 +87,47 @@ impl From<storage::Error> for Error {
 }
 
 impl Error {
+    /// Creates an error for a task rejected because every task permit is taken.
+    pub(super) fn saturated() -> Self {
+        Self {
+            inner: Box::new(ErrorImpl {
+                kind: Kind::Saturated,
+                cause: None,
+            }),
+        }
+    }
+
     /// The error's standalone message, without the message from the source.
     pub fn message(&self) -> impl fmt::Display + '_ {
         self.description()
     }
 
+    /// Returns true if the task was rejected because the worker queue is full, or too many
+    /// tasks are executing.
+    pub fn is_saturated(&self) -> bool {
+        matches!(self.inner.kind, Kind::Saturated)
+    }
+
+    /// The tracker-level failure reason for the client, if the task was refused by the tracker
+    /// rather than failing internally.
+    pub fn tracker_error(&self) -> Option<&'static str> {
+        match self.inner.kind {
+            Kind::Custom(msg) => Some(msg),
+            Kind::Storage => self
+                .inner
+                .cause
+                .as_ref()
+                .and_then(|cause| cause.downcast_ref::<storage::Error>())
+                .filter(|err| err.is_not_found())
+                .map(|_| constants::TRACKER_ERROR_NOT_FOUND_TORRENT),
+            _ => None,
+        }
+    }
+
     fn description(&self) -> &str {
         match self.inner.kind {
             Kind::Send => "failed to send message to task handler",
+            Kind::Saturated => "worker saturated",
             Kind::Recv => "failed to receive message from worker",
             Kind::Storage => "storage error",
             Kind::Custom(str) => str,
@@ -1,14 +1,21 @@
 mod error;
 pub use error::{Error, Result};
 mod tasks;
+pub use tasks::announce::{AnnounceObserver, TransferCounters};
 pub use tasks::full_scrape::FullScrapeProcessor;
 
-use self::tasks::{announce, full_scrape, scrape, State, TaskExecutor};
-use crate::{config::TSConfig, storage::create_new_storage};
+use self::tasks::{
+    announce, full_scrape, insert_torrents, list_swarm, remove_torrent, scrape, State, TaskExecutor,
+};
+use crate::{
+    config::TSConfig,
+    storage::{create_new_storage, Storage},
+    utils::RequestId,
+};
 use log::{debug, info};
 use std::sync::Arc;
 use tokio::{
-    sync::{mpsc, oneshot},
+    sync::{mpsc, oneshot, Semaphore},
     task::JoinHandle,
 };
 
@@ -18,17 +25,26 @@ pub struct Worker {
     state: Option<State>,
 }
 
-const WORKER_POOL_SIZE: usize = 10_000;
-
 impl Worker {
     /// Create a new `Worker`.
     pub fn new(config: Arc<TSConfig>) -> Worker {
-        let storage = create_new_storage(config.clone())
+        let storage: Arc<dyn Storage> = create_new_storage(config.clone())
             .expect("Failed to create storage")
             .into();
 
-        let (sender, receiver) = mpsc::channel::<TaskPacket>(WORKER_POOL_SIZE);
-        let state = State { storage, config };
+        let scrape_permits = Some(Arc::new(Semaphore::new(config.max_concurrent_scrapes())));
+        let scrape_cache =
+            scrape::create_scrape_cache(&config, storage.clone(), scrape_permits.clone());
+
+        let (sender, receiver) = mpsc::channel::<TaskPacket>(config.max_queued_tasks());
+        let state = State {
+            storage,
+            config,
+            scrape_cache,
+            announce_observer: None,
+            inflight_announces: Default::default(),
+            scrape_permits,
+        };
         Self {
             sender,
             receiver: Some(receiver),
@@ -36,22 +52,53 @@ impl Worker {
         }
     }
 
+    /// Sets the observer notified of every successful announce.
+    /// Must be called before the worker is started.
+    pub fn set_announce_observer(&mut self, observer: Arc<dyn AnnounceObserver>) {
+        let state = self.state.as_mut().expect("Worker loop already started");
+        state.announce_observer = Some(observer);
+    }
+
     /// Start the `WorkerLoop` to handle incoming tasks.
     pub fn start(&mut self) -> JoinHandle<()> {
         let receiver = self.receiver.take().expect("Worker loop already started");
         let state = self.state.take().unwrap();
+        let semaphore = Arc::new(Semaphore::new(state.config.max_concurrent_tasks()));
 
         tokio::spawn(async move {
-            let mut worker_loop = WorkerLoop { receiver, state };
+            let mut worker_loop = WorkerLoop {
+                receiver,
+                state,
+                semaphore,
+            };
             worker_loop.run().await
         })
     }
 
     /// Send a task to the `Worker` for execution.
+    ///
+    /// Fails with a saturated error instead of waiting if the task queue is full.
     pub async fn work(&self, task: Task) -> Result<TaskOutput> {
+        self.submit(task, None)?.await?
+    }
+
+    /// Send a task to the `Worker` for execution, tagging the worker logs with `request_id`.
+    pub async fn work_with_id(
+        &self,
+        task: Task,
+        request_id: Option<RequestId>,
+    ) -> Result<TaskOutput> {
+        self.submit(task, request_id)?.await?
+    }
+
+    fn submit(
+        &self,
+        task: Task,
+        request_id: Option<RequestId>,
+    ) -> Result<oneshot::Receiver<Result<TaskOutput>>> {
         let (sender, receiver) = oneshot::channel::<Result<TaskOutput>>();
-        self.sender.send((task, sender)).await?;
-        receiver.await?
+        self.sender.try_send((task, sender, request_id))?;
+        Ok(receiver)
     }
 }
 
@@ -59,7 +106,9 @@ impl Drop for Worker {
     fn drop(&mut self) {
         let sender = self.sender.clone();
         tokio::spawn(async move {
-            let _ = sender.send((Task::Shutdown, oneshot::channel().0)).await;
+            let _ = sender
+                .send((Task::Shutdown, oneshot::channel().0, None))
+                .await;
         });
     }
 }
@@ -68,6 +117,9 @@ pub enum Task {
     Announce(announce::Input),
     Scrape(scrape::Input),
     FullScrape(full_scrape::Input),
+    InsertTorrents(insert_torrents::Input),
+    ListSwarm(list_swarm::Input),
+    RemoveTorrent(remove_torrent::Input),
     UpdateState(State),
     Shutdown,
 }
@@ -76,37 +128,64 @@ pub enum TaskOutput {
     Announce(announce::Output),
     Scrape(scrape::Output),
     FullScrape(full_scrape::Output),
+    Swarm(list_swarm::Output),
     None,
 }
 
 type TaskSender = oneshot::Sender<Result<TaskOutput>>;
-type TaskPacket = (Task, TaskSender);
+type TaskPacket = (Task, TaskSender, Option<RequestId>);
 
 struct WorkerLoop {
     receiver: mpsc::Receiver<TaskPacket>,
     state: State,
+    /// Limits the number of concurrently executing tasks. A task received while no permit is
+    /// available is rejected as saturated, so the loop keeps handling state updates and shutdown.
+    semaphore: Arc<Semaphore>,
 }
 
 impl WorkerLoop {
     pub(super) async fn run(&mut self) {
-        let executor = Executor;
+        let executor = Executor {
+            semaphore: self.semaphore.clone(),
+        };
         info!("Worker loop started");
 
         while let Some(msg) = self.receiver.recv().await {
-            let (task, sender) = msg;
-            debug!("Worker loop received task {:?}", task);
+            let (task, sender, request_id) = msg;
+            match request_id {
+                Some(request_id) => debug!("Worker loop received task {:?} [{}]", task, request_id),
+                None => debug!("Worker loop received task {:?}", task),
+            }
 
             match task {
                 Task::Announce(input) => {
-                    executor.execute(announce::TaskExecutor, input, sender, self.state.clone())
+                    let state = self.state.clone();
+                    executor.execute(announce::TaskExecutor, input, sender, state)
                 }
 
                 Task::Scrape(input) => {
-                    executor.execute(scrape::TaskExecutor, input, sender, self.state.clone())
+                    let state = self.state.clone();
+                    executor.execute(scrape::TaskExecutor, input, sender, state)
                 }
 
                 Task::FullScrape(input) => {
-                    executor.execute(full_scrape::TaskExecutor, input, sender, self.state.clone())
+                    let state = self.state.clone();
+                    executor.execute(full_scrape::TaskExecutor, input, sender, state)
+                }
+
+                Task::InsertTorrents(input) => {
+                    let state = self.state.clone();
+                    executor.execute(insert_torrents::TaskExecutor, input, sender, state)
+                }
+
+                Task::ListSwarm(input) => {
+                    let state = self.state.clone();
+                    executor.execute(list_swarm::TaskExecutor, input, sender, state)
+                }
+
+                Task::RemoveTorrent(input) => {
+                    let state = self.state.clone();
+                    executor.execute(remove_torrent::TaskExecutor, input, sender, state)
                 }
 
                 Task::UpdateState(state) => {
@@ -125,17 +204,29 @@ impl WorkerLoop {
     }
 }
 
-struct Executor;
+struct Executor {
+    semaphore: Arc<Semaphore>,
+}
 
 impl Executor {
+    /// Spawns the task holding a permit until it completes, or rejects it if none is available.
     fn execute<E, I, O>(&self, executor: E, input: I, sender: TaskSender, state: State)
     where
         E: TaskExecutor<Input = I, Output = O> + 'static,
         I: Send + 'static,
     {
+        let permit = match self.semaphore.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                let _ = sender.send(Err(Error::saturated()));
+                return;
+            }
+        };
+
         tokio::spawn(async move {
             let response = executor.execute(input, state).await;
             let _ = sender.send(response);
+            drop(permit);
         });
     }
 }
@@ -146,8 +237,59 @@ impl std::fmt::Debug for Task {
             Task::Announce(_) => write!(f, "Announce"),
             Task::Scrape(_) => write!(f, "Scrape"),
             Task::FullScrape(_) => write!(f, "FullScrape"),
+            Task::InsertTorrents(_) => write!(f, "InsertTorrents"),
+            Task::ListSwarm(_) => write!(f, "ListSwarm"),
+            Task::RemoveTorrent(_) => write!(f, "RemoveTorrent"),
             Task::UpdateState(_) => write!(f, "UpdateState"),
             Task::Shutdown => write!(f, "Shutdown"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_work_rejects_when_saturated() {
+        let mut config = TSConfig::new().unwrap();
+        config.tracker.max_queued_tasks = 4;
+        let worker = Worker::new(Arc::new(config));
+
+        // The worker loop is not started, so nothing drains the queue.
+        let receivers: Vec<_> = (0..4)
+            .map(|_| worker.submit(Task::Shutdown, None).unwrap())
+            .collect();
+
+        let err = worker.work(Task::Shutdown).await.unwrap_err();
+        assert!(err.is_saturated());
+        assert_eq!(receivers.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_work_rejects_while_permits_held() {
+        let config = Arc::new(TSConfig::new().unwrap());
+        let mut worker = Worker::new(config);
+
+        // Every task permit is held, as by tasks stalled on the storage.
+        let semaphore = Arc::new(Semaphore::new(1));
+        let _permit = semaphore.clone().try_acquire_owned().unwrap();
+
+        let mut worker_loop = WorkerLoop {
+            receiver: worker.receiver.take().unwrap(),
+            state: worker.state.take().unwrap(),
+            semaphore,
+        };
+        let worker_job = tokio::spawn(async move { worker_loop.run().await });
+
+        let err = worker
+            .work(Task::InsertTorrents(Vec::new()))
+            .await
+            .unwrap_err();
+        assert!(err.is_saturated());
+
+        // The loop still handles control messages.
+        worker.work(Task::Shutdown).await.unwrap();
+        worker_job.await.unwrap();
+    }
+}
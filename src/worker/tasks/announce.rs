@@ -1,10 +1,10 @@
 use super::{err, State};
 use crate::{
-    config::TSConfig,
+    config::{CompactOnlyMode, IpParamMode, PeerSelection, TSConfig},
     constants,
     models::{
-        common::{IpType, NumOfBytes, PEER_ID_LENGTH},
-        peer::{Peer, PeerType, PEER_ADDR_V4_LENGTH, PEER_ADDR_V6_LENGTH},
+        common::{InfoHash, IpType, NumOfBytes, PeerId, PEER_ID_LENGTH},
+        peer::{peer_addr_length, Peer, PeerAddr, PeerType},
         torrent::{PeerDict, PeerIdKey, PeerList},
         tracker::{
             AnnounceEvent, AnnounceRequest, AnnounceResponse, NonCompactPeer, ResponsePeerList,
@@ -13,10 +13,17 @@ use crate::{
     storage::PeerExtractor,
     worker::{Result, TaskOutput},
 };
+use ahash::{AHashMap, AHashSet};
 use async_trait::async_trait;
-use bytes::BytesMut;
-use std::{cmp, net::IpAddr};
-use ts_utils::time::Clock;
+use bytes::{BufMut, Bytes, BytesMut};
+use std::{
+    cmp,
+    future::Future,
+    net::IpAddr,
+    sync::{Mutex, PoisonError},
+};
+use tokio::sync::watch;
+use ts_utils::{cmp::ct_eq, time::Clock};
 
 pub struct TaskExecutor;
 
@@ -25,6 +32,26 @@ pub type Output = AnnounceResponse;
 
 const NUM_ZERO: NumOfBytes = NumOfBytes(0);
 
+/// The most peers the response buffers are allocated for up front. A `numwant` up to a huge
+/// `max_numwant` then only grows them as far as the swarm actually fills them.
+const MAX_PREALLOCATED_PEERS: usize = 200;
+
+/// The transfer counters reported by a client in an announce.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TransferCounters {
+    pub uploaded: NumOfBytes,
+    pub downloaded: NumOfBytes,
+    /// `None` if the client didn't report it.
+    pub left: Option<NumOfBytes>,
+}
+
+/// An extension point for recording what clients report in their announces,
+/// e.g. to keep per user stats on a private tracker.
+pub trait AnnounceObserver: Send + Sync {
+    /// Called after the swarm was updated for an announce. Does nothing by default.
+    fn on_announce(&self, _info_hash: &InfoHash, _peer_id: &PeerId, _counters: TransferCounters) {}
+}
+
 #[async_trait]
 impl super::TaskExecutor for TaskExecutor {
     type Input = Input;
@@ -32,123 +59,392 @@ impl super::TaskExecutor for TaskExecutor {
 
     async fn execute(&self, input: Self::Input, state: State) -> Result<TaskOutput> {
         let (req, sender_addr) = input;
-        let storage = state.storage;
-        let config = state.config;
 
-        if config.infohash_blocklist.contains(&req.info_hash) {
-            return err(constants::TRACKER_ERROR_BLOCKED_INFOHASH);
-        }
+        let key = (
+            peer_id_key(&req, &state.config),
+            req.info_hash.clone(),
+            req.event,
+        );
 
-        let info_hash = &req.info_hash;
-        if !storage.has_torrent(info_hash).await? {
-            match config.auto_register_torrent() {
-                true => storage.insert_torrent(info_hash, None).await?,
-                false => return err(constants::TRACKER_ERROR_NOT_FOUND_TORRENT),
-            };
-        }
+        let inflight = state.inflight_announces.clone();
+        let response = inflight.run(key, announce(req, sender_addr, state)).await?;
+
+        Ok(TaskOutput::Announce(response))
+    }
+}
 
-        let peer: Peer = (&req, sender_addr, &config.tracker).into();
+async fn announce(
+    mut req: AnnounceRequest,
+    sender_addr: IpAddr,
+    state: State,
+) -> Result<AnnounceResponse> {
+    let storage = state.storage;
+    let config = state.config;
 
-        let mut peer_type = {
-            if req.left == NUM_ZERO {
-                PeerType::Seeder
-            } else {
-                PeerType::Leecher
+    if !req.compact && config.compact_only() {
+        match config.compact_only_mode() {
+            CompactOnlyMode::Coerce => req.compact = true,
+            CompactOnlyMode::Reject => {
+                return err(constants::TRACKER_ERROR_PEER_LIST_NOT_SUPPORTED)
             }
+        }
+    }
+
+    if config.require_key() && !req.key.is_usable() {
+        return err(constants::TRACKER_ERROR_MISSING_KEY);
+    }
+
+    if config.infohash_blocklist.contains(&req.info_hash) {
+        return err(constants::TRACKER_ERROR_BLOCKED_INFOHASH);
+    }
+
+    let info_hash = &req.info_hash;
+    if !storage.has_torrent(info_hash).await? {
+        match config.auto_register_torrent() {
+            true => storage.insert_torrent(info_hash, None).await?,
+            false => return err(constants::TRACKER_ERROR_NOT_FOUND_TORRENT),
         };
+    }
 
-        let user_key = req.key.as_ref().map(|k| k.as_ref());
-        let peer_id_key = PeerIdKey::new(&req.peer_id, user_key);
+    let peer_ip = peer_ip(&req, sender_addr, &config)?;
+    let mut peer: Peer = (&req, peer_ip, &config.tracker).into();
 
-        match req.event {
-            Some(AnnounceEvent::Started) => {
-                storage
-                    .put_peer_in_swarm(info_hash, &peer_id_key, peer, peer_type)
-                    .await?;
-            }
+    let mut peer_type = match req.left {
+        Some(NUM_ZERO) => PeerType::Seeder,
+        _ => PeerType::Leecher,
+    };
 
-            Some(AnnounceEvent::Stopped) => {
-                storage
-                    .remove_peer_from_swarm(info_hash, &peer_id_key, peer_type, peer.ip_type())
-                    .await?;
-            }
+    let peer_id_key = peer_id_key(&req, &config);
+    let peer_addr = peer.addr.clone();
 
-            Some(AnnounceEvent::Completed) => {
-                if !matches!(peer_type, PeerType::Seeder) {
-                    return err(constants::TRACKER_ERROR_INVALID_ANNOUNCE_REQUEST);
-                }
+    // Without the key in the peer's identity, the key the peer announced with is kept in its
+    // entry instead, and a different one is refused. An announce without one keeps the entry's.
+    if config.peer_id_only() {
+        let stored_key = storage
+            .get_peer_in_swarm(info_hash, &peer_id_key, peer.ip_type())
+            .await?
+            .and_then(|stored| stored.key);
 
-                storage
-                    .promote_peer_in_swarm(info_hash, &peer_id_key, peer)
-                    .await?;
+        match (stored_key, peer.key) {
+            (Some(stored_key), Some(key)) if !ct_eq(&stored_key, &key) => {
+                return err(constants::TRACKER_ERROR_KEY_MISMATCH);
             }
+            (Some(stored_key), None) => peer.key = Some(stored_key),
+            _ => {}
+        }
+    }
 
-            Some(AnnounceEvent::Paused) => {
-                peer_type = PeerType::Partial;
-                storage
-                    .update_or_put_peer_in_swarm(info_hash, &peer_id_key, peer, peer_type)
-                    .await?;
+    match req.event {
+        Some(AnnounceEvent::Started) => {
+            storage
+                .put_peer_in_swarm(info_hash, &peer_id_key, peer, peer_type)
+                .await?;
+        }
+
+        Some(AnnounceEvent::Stopped) => {
+            storage
+                .remove_peer_from_swarm(info_hash, &peer_id_key, peer.ip_type())
+                .await?;
+        }
+
+        Some(AnnounceEvent::Completed) => {
+            if !matches!(peer_type, PeerType::Seeder) {
+                return err(constants::TRACKER_ERROR_INVALID_ANNOUNCE_REQUEST);
             }
 
-            _ => {
-                storage
-                    .update_or_put_peer_in_swarm(info_hash, &peer_id_key, peer, peer_type)
-                    .await?;
+            if !config.lenient_completed()
+                && !storage
+                    .has_peer_in_swarm(info_hash, &peer_id_key, peer.ip_type())
+                    .await?
+            {
+                return err(constants::TRACKER_ERROR_INVALID_ANNOUNCE_REQUEST);
             }
+
+            storage
+                .promote_peer_in_swarm(info_hash, &peer_id_key, peer)
+                .await?;
         }
 
-        let response = {
-            let mut peers = None;
-            let mut peers6 = None;
-            let mut complete = 0;
-            let mut incomplete = 0;
+        Some(AnnounceEvent::Paused) => {
+            peer_type = PeerType::Partial;
+            storage
+                .update_or_put_peer_in_swarm(info_hash, &peer_id_key, peer, peer_type)
+                .await?;
+        }
 
-            if req.event != Some(AnnounceEvent::Stopped) {
-                let peer_ip_type = if sender_addr.is_ipv4() {
-                    IpType::V4
-                } else {
-                    IpType::V6
+        _ => {
+            storage
+                .update_or_put_peer_in_swarm(info_hash, &peer_id_key, peer, peer_type)
+                .await?;
+        }
+    }
+
+    if let Some(observer) = state.announce_observer {
+        let counters = TransferCounters {
+            uploaded: req.uploaded,
+            downloaded: req.downloaded,
+            left: req.left,
+        };
+
+        observer.on_announce(info_hash, &req.peer_id, counters);
+    }
+
+    let response = {
+        let mut peers = None;
+        let mut peers6 = None;
+        let mut crypto_flags = None;
+        let mut complete = 0;
+        let mut incomplete = 0;
+        let mut peer_count = 0;
+        let mut wants_peers = false;
+
+        if req.event != Some(AnnounceEvent::Stopped) {
+            let peer_ip_type = IpType::from(peer_ip);
+
+            let mut processor =
+                ResponsePeersExtractor::new(&req, &peer_id_key, peer_ip_type, &config)
+                    .without_addr(peer_addr);
+
+            let stats = storage
+                .extract_peers_from_swarm(info_hash, peer_type, peer_ip_type, &mut processor)
+                .await?;
+
+            wants_peers = processor.numwant > 0;
+            peer_count = processor.peer_count;
+            let remaining = processor.numwant - peer_count;
+            (peers, peers6, crypto_flags) = processor.into_output();
+
+            complete = stats.complete;
+            incomplete = stats.incomplete;
+
+            // Fill the rest of `numwant` from the swarm of the other IP family.
+            if config.return_both_ip_families() {
+                let other_ip_type = match peer_ip_type {
+                    IpType::V4 => IpType::V6,
+                    IpType::V6 => IpType::V4,
                 };
 
-                let mut processor =
-                    ResponsePeersExtractor::new(&req, &peer_id_key, peer_ip_type, &config);
+                let mut processor = ResponsePeersExtractor::with_numwant(
+                    &req,
+                    &peer_id_key,
+                    other_ip_type,
+                    remaining,
+                    &config,
+                );
 
                 let stats = storage
-                    .extract_peers_from_swarm(info_hash, peer_type, peer_ip_type, &mut processor)
+                    .extract_peers_from_swarm(info_hash, peer_type, other_ip_type, &mut processor)
                     .await?;
 
-                (peers, peers6) = processor.into_output();
+                peer_count += processor.peer_count;
+                let (other_peers, other_peers6, other_crypto_flags) = processor.into_output();
+                peers = peers.or(other_peers);
+                peers6 = peers6.or(other_peers6);
+
+                // The flags follow the peers, those of `peers` before those of `peers6`.
+                crypto_flags = match peer_ip_type {
+                    IpType::V4 => concat_crypto_flags(crypto_flags, other_crypto_flags),
+                    IpType::V6 => concat_crypto_flags(other_crypto_flags, crypto_flags),
+                };
 
-                complete = stats.complete;
-                incomplete = stats.incomplete;
+                complete += stats.complete;
+                incomplete += stats.incomplete;
             }
+        }
+
+        if config.clamp_peer_counts() {
+            (complete, incomplete) = clamp_peer_counts(complete, incomplete, peer_count);
+        }
+
+        let interval = config.announce_interval();
+        let min_interval = config.min_announce_interval();
+
+        // A configured tracker id takes precedence over the one sent by the client.
+        let tracker_id = config
+            .tracker_id()
+            .cloned()
+            .or_else(|| req.trackerid.clone());
+
+        AnnounceResponse {
+            peers,
+            peers6,
+            crypto_flags,
+            incomplete,
+            complete,
+            interval,
+            min_interval,
+            tracker_id,
+            warning_message: warning_message(&req, &config, wants_peers && peer_count == 0),
+        }
+    };
+
+    Ok(response)
+}
+
+/// Joins the crypto flags of two peer lists, either of which may be absent.
+fn concat_crypto_flags(first: Option<Bytes>, second: Option<Bytes>) -> Option<Bytes> {
+    match (first, second) {
+        (Some(first), Some(second)) => Some([first, second].concat().into()),
+        (first, second) => first.or(second),
+    }
+}
+
+/// Returns the key identifying the announcing peer in the swarm.
+fn peer_id_key(req: &AnnounceRequest, config: &TSConfig) -> PeerIdKey {
+    let user_key = match config.peer_id_only() {
+        true => None,
+        false => req.key.as_ref().map(|k| k.as_ref()),
+    };
+
+    PeerIdKey::new(&req.peer_id, user_key)
+}
+
+/// Returns the address the peer is stored under: the one it connected from, or the `ip` it
+/// declared if `ip_param_mode` allows. Only a client connected from a local network may declare
+/// an ip, so that one on the internet can't register the address of another host.
+fn peer_ip(req: &AnnounceRequest, sender_addr: IpAddr, config: &TSConfig) -> Result<IpAddr> {
+    let ip = match req.ip {
+        Some(ip) if config.ip_param_mode() != IpParamMode::Ignore && is_local(sender_addr) => {
+            ip.to_canonical()
+        }
+        _ => return Ok(sender_addr),
+    };
+
+    if config.ip_param_mode() == IpParamMode::Strict
+        && IpType::from(ip) != IpType::from(sender_addr)
+    {
+        return err(constants::TRACKER_ERROR_IP_FAMILY_MISMATCH);
+    }
+
+    Ok(ip)
+}
+
+/// Returns whether `ip` is a loopback, private or link-local address.
+fn is_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+        IpAddr::V6(ip) => {
+            let segment = ip.segments()[0];
+            // Unique local addresses are in fc00::/7, and link-local ones in fe80::/10.
+            ip.is_loopback() || segment & 0xfe00 == 0xfc00 || segment & 0xffc0 == 0xfe80
+        }
+    }
+}
+
+type InflightKey = (PeerIdKey, InfoHash, Option<AnnounceEvent>);
+type InflightResponse = watch::Receiver<Option<AnnounceResponse>>;
 
-            let interval = config.announce_interval();
-            let min_interval = config.min_announce_interval();
-
-            AnnounceResponse {
-                peers,
-                peers6,
-                incomplete,
-                complete,
-                interval,
-                min_interval,
-                warning_message: None,
+/// The maximum number of announces tracked for deduplication at once.
+/// Announces beyond it are processed without deduplication.
+const MAX_INFLIGHT_ANNOUNCES: usize = 4096;
+
+/// Deduplicates identical announces processed concurrently, e.g. the retries of an impatient
+/// client. The first one is processed and the others wait for its response.
+/// Entries only live while their announce is being processed.
+#[derive(Default)]
+pub struct InflightAnnounces {
+    map: Mutex<AHashMap<InflightKey, InflightResponse>>,
+}
+
+enum InflightRole {
+    Leader(watch::Sender<Option<AnnounceResponse>>),
+    Follower(InflightResponse),
+    Bypass,
+}
+
+impl InflightAnnounces {
+    async fn run<F>(&self, key: InflightKey, announce: F) -> Result<AnnounceResponse>
+    where
+        F: Future<Output = Result<AnnounceResponse>>,
+    {
+        let role = {
+            let mut map = self.map.lock().unwrap_or_else(PoisonError::into_inner);
+            match map.get(&key) {
+                Some(rx) => InflightRole::Follower(rx.clone()),
+                None if map.len() >= MAX_INFLIGHT_ANNOUNCES => InflightRole::Bypass,
+                None => {
+                    let (tx, rx) = watch::channel(None);
+                    map.insert(key.clone(), rx);
+                    InflightRole::Leader(tx)
+                }
             }
         };
 
-        Ok(TaskOutput::Announce(response))
+        match role {
+            InflightRole::Leader(tx) => {
+                let _entry = InflightEntry {
+                    map: &self.map,
+                    key,
+                };
+                let result = announce.await;
+                if let Ok(ref response) = result {
+                    let _ = tx.send(Some(response.clone()));
+                }
+
+                result
+            }
+
+            InflightRole::Follower(mut rx) => {
+                // The leader failed if it dropped its sender without a response,
+                // in which case this announce is processed on its own.
+                if rx.changed().await.is_ok() {
+                    if let Some(response) = rx.borrow().clone() {
+                        return Ok(response);
+                    }
+                }
+
+                announce.await
+            }
+
+            InflightRole::Bypass => announce.await,
+        }
+    }
+}
+
+/// Removes the entry of a leader once it finished, even if it failed or was cancelled.
+struct InflightEntry<'a> {
+    map: &'a Mutex<AHashMap<InflightKey, InflightResponse>>,
+    key: InflightKey,
+}
+
+impl Drop for InflightEntry<'_> {
+    fn drop(&mut self) {
+        let mut map = self.map.lock().unwrap_or_else(PoisonError::into_inner);
+        map.remove(&self.key);
+    }
+}
+
+/// Returns a warning for soft limits hit by the request, which don't fail the announce,
+/// or for a response without any of the peers the request wanted.
+fn warning_message(req: &AnnounceRequest, config: &TSConfig, no_peers: bool) -> Option<String> {
+    match req.numwant {
+        Some(numwant) if numwant > config.max_numwant() => {
+            Some(constants::TRACKER_WARNING_NUMWANT_EXCEEDED.to_string())
+        }
+        _ if no_peers && config.warn_no_peers() => {
+            Some(constants::TRACKER_WARNING_NO_PEERS.to_string())
+        }
+        _ => None,
     }
 }
 
+/// Raises `incomplete` so that `complete` + `incomplete` is at least `peer_count`.
+fn clamp_peer_counts(complete: u32, incomplete: u32, peer_count: usize) -> (u32, u32) {
+    let peer_count = peer_count as u32;
+    let incomplete = cmp::max(incomplete, peer_count.saturating_sub(complete));
+    (complete, incomplete)
+}
+
 struct ResponsePeersExtractor<'a> {
     req: &'a AnnounceRequest,
     peer_id_key: &'a PeerIdKey,
     peer_ip_type: IpType,
     numwant: usize,
     peers: PeersOutput,
+    crypto_flags: Option<BytesMut>,
     peer_count: usize,
     random_val: usize,
+    seen_addrs: Option<AHashSet<PeerAddr>>,
 }
 
 impl<'a> ResponsePeersExtractor<'a> {
@@ -159,12 +455,33 @@ impl<'a> ResponsePeersExtractor<'a> {
         config: &TSConfig,
     ) -> Self {
         let numwant = cmp::min(
-            req.numwant.unwrap_or(config.default_numwant()),
+            req.numwant
+                .unwrap_or_else(|| config.numwant_for_event(req.event)),
             config.max_numwant(),
         ) as usize;
 
-        let peers = PeersOutput::new(req.compact, numwant, peer_ip_type);
-        let random_val = Clock::recent_since_epoch().as_secs() as usize;
+        Self::with_numwant(req, peer_id_key, peer_ip_type, numwant, config)
+    }
+
+    fn with_numwant(
+        req: &'a AnnounceRequest,
+        peer_id_key: &'a PeerIdKey,
+        peer_ip_type: IpType,
+        numwant: usize,
+        config: &TSConfig,
+    ) -> Self {
+        let capacity = cmp::min(numwant, MAX_PREALLOCATED_PEERS);
+        let peers = PeersOutput::new(req.compact, capacity, peer_ip_type);
+        let crypto_flags = req
+            .wants_crypto_flags()
+            .then(|| BytesMut::with_capacity(capacity));
+        let random_val = match config.peer_selection() {
+            PeerSelection::Random => Clock::recent_since_epoch().as_secs() as usize,
+            PeerSelection::Stable => 0,
+        };
+        let seen_addrs = config
+            .dedup_peer_endpoints()
+            .then(|| AHashSet::with_capacity(capacity));
 
         Self {
             numwant,
@@ -172,11 +489,22 @@ impl<'a> ResponsePeersExtractor<'a> {
             peer_id_key,
             peer_ip_type,
             peers,
+            crypto_flags,
             random_val,
+            seen_addrs,
             peer_count: 0,
         }
     }
 
+    /// Leaves out the peers at the requester's own `addr` too, if endpoints are deduplicated.
+    fn without_addr(mut self, addr: PeerAddr) -> Self {
+        if let Some(ref mut seen_addrs) = self.seen_addrs {
+            seen_addrs.insert(addr);
+        }
+
+        self
+    }
+
     fn extract<'b>(&mut self, iter: impl Iterator<Item = (&'b PeerIdKey, &'b Peer)>) -> bool {
         for (peer_id_key, peer) in iter {
             if self.peer_count >= self.numwant {
@@ -188,23 +516,41 @@ impl<'a> ResponsePeersExtractor<'a> {
             }
 
             self.peers.insert(peer_id_key, peer, self.req.no_peer_id);
+            if let Some(ref mut crypto_flags) = self.crypto_flags {
+                crypto_flags.put_u8(peer.crypto_flag);
+            }
+
             self.peer_count += 1;
         }
 
         return true;
     }
 
-    fn predicate(&self, _peer: &Peer, peer_id_key: &PeerIdKey) -> bool {
+    fn predicate(&mut self, peer: &Peer, peer_id_key: &PeerIdKey) -> bool {
         if peer_id_key == self.peer_id_key {
             return false;
         }
 
+        if let Some(ref mut seen_addrs) = self.seen_addrs {
+            if !seen_addrs.insert(peer.addr.clone()) {
+                return false;
+            }
+        }
+
         return true;
     }
 
-    fn into_output(self) -> (Option<ResponsePeerList>, Option<ResponsePeerList>) {
+    fn into_output(
+        self,
+    ) -> (
+        Option<ResponsePeerList>,
+        Option<ResponsePeerList>,
+        Option<Bytes>,
+    ) {
         let peers = match self.peers {
             PeersOutput::Compact(bytes) => {
+                debug_assert_eq!(bytes.len() % peer_addr_length(self.peer_ip_type), 0);
+
                 if bytes.is_empty() {
                     None
                 } else {
@@ -220,9 +566,14 @@ impl<'a> ResponsePeersExtractor<'a> {
             }
         };
 
+        let crypto_flags = match peers {
+            Some(_) => self.crypto_flags.map(BytesMut::freeze),
+            None => None,
+        };
+
         match self.peer_ip_type {
-            IpType::V4 => (peers, None),
-            IpType::V6 => (None, peers),
+            IpType::V4 => (peers, None, crypto_flags),
+            IpType::V6 => (None, peers, crypto_flags),
         }
     }
 }
@@ -250,7 +601,7 @@ impl<'a> PeerExtractor for ResponsePeersExtractor<'a> {
         }
 
         let start = self.random_val % total_peers;
-        let iter = list[0..total_peers].iter().map(|(k, v)| (k, v));
+        let iter = list[start..total_peers].iter().map(|(k, v)| (k, v));
 
         match self.extract(iter) {
             true => {
@@ -260,6 +611,10 @@ impl<'a> PeerExtractor for ResponsePeersExtractor<'a> {
             false => false,
         }
     }
+
+    fn remaining(&self) -> usize {
+        self.numwant - self.peer_count
+    }
 }
 
 enum PeersOutput {
@@ -268,17 +623,14 @@ enum PeersOutput {
 }
 
 impl PeersOutput {
-    fn new(is_compact: bool, numwant: usize, ip_type: IpType) -> Self {
+    /// Creates an empty output with room for `capacity` peers.
+    fn new(is_compact: bool, capacity: usize, ip_type: IpType) -> Self {
         match is_compact {
             true => {
-                let capacity = match ip_type {
-                    IpType::V4 => numwant * PEER_ADDR_V4_LENGTH,
-                    IpType::V6 => numwant * PEER_ADDR_V6_LENGTH,
-                };
-
+                let capacity = capacity * peer_addr_length(ip_type);
                 PeersOutput::Compact(BytesMut::with_capacity(capacity))
             }
-            false => PeersOutput::NonCompact(Vec::with_capacity(numwant)),
+            false => PeersOutput::NonCompact(Vec::with_capacity(capacity)),
         }
     }
 
@@ -300,3 +652,708 @@ impl PeersOutput {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::{test_state, TaskExecutor as _};
+    use super::*;
+    use crate::models::{
+        common::{PeerId, Port, INFOHASH_LENGTH},
+        peer::{PEER_ADDR_V4_LENGTH, PEER_ADDR_V6_LENGTH},
+    };
+    use crate::storage::{MemoryStorage, Storage};
+    use std::{
+        net::{Ipv4Addr, Ipv6Addr},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+    use ts_utils::{bencode::Bencode, query};
+
+    const QUERY: &str = "info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=01234567890123456789&port=6881";
+
+    fn create_peer_list(count: u8) -> PeerList {
+        (0..count)
+            .map(|i| {
+                let peer_id: PeerId = [i; PEER_ID_LENGTH].into();
+                let peer = Peer {
+                    crypto_flag: i % 2,
+                    ..Peer::new_test((Ipv4Addr::new(127, 0, 0, i), Port(6881)))
+                };
+
+                (PeerIdKey::new(&peer_id, None), peer)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_huge_numwant_not_preallocated() {
+        let mut config = TSConfig::new().unwrap();
+        config.tracker.max_numwant = u32::MAX;
+
+        for compact in [0, 1] {
+            let query = format!("{}&numwant={}&compact={}", QUERY, u32::MAX, compact);
+            let req: AnnounceRequest = query::from_bytes(query.as_bytes()).unwrap();
+            let peer_id_key = PeerIdKey::new(&req.peer_id, None);
+
+            let mut extractor =
+                ResponsePeersExtractor::new(&req, &peer_id_key, IpType::V4, &config);
+            assert_eq!(extractor.numwant, u32::MAX as usize);
+            let capacity = match &extractor.peers {
+                PeersOutput::Compact(bytes) => bytes.capacity() / PEER_ADDR_V4_LENGTH,
+                PeersOutput::NonCompact(peers) => peers.capacity(),
+            };
+            assert!(capacity <= MAX_PREALLOCATED_PEERS);
+
+            // The buffers still grow to take every peer of the swarm.
+            extractor.from_list(&create_peer_list(3));
+            assert_eq!(extractor.peer_count, 3);
+        }
+    }
+
+    #[test]
+    fn test_crypto_flags_match_compact_peers() {
+        let config = TSConfig::new().unwrap();
+        let query = format!("{}&supportcrypto=1", QUERY);
+        let req: AnnounceRequest = query::from_bytes(query.as_bytes()).unwrap();
+        let peer_id_key = PeerIdKey::new(&req.peer_id, None);
+
+        let mut extractor = ResponsePeersExtractor::new(&req, &peer_id_key, IpType::V4, &config);
+        extractor.from_list(&create_peer_list(3));
+
+        let (peers, _, crypto_flags) = extractor.into_output();
+        let peers = match peers {
+            Some(ResponsePeerList::Compact(peers)) => peers,
+            _ => panic!("expected compact peers"),
+        };
+
+        let crypto_flags = crypto_flags.unwrap();
+        assert_eq!(crypto_flags.len(), peers.len() / PEER_ADDR_V4_LENGTH);
+        assert_eq!(crypto_flags.as_ref(), &[0, 1, 0]);
+    }
+
+    #[test]
+    fn test_crypto_flags_not_requested() {
+        let config = TSConfig::new().unwrap();
+        let req: AnnounceRequest = query::from_bytes(QUERY.as_bytes()).unwrap();
+        let peer_id_key = PeerIdKey::new(&req.peer_id, None);
+
+        let mut extractor = ResponsePeersExtractor::new(&req, &peer_id_key, IpType::V4, &config);
+        extractor.from_list(&create_peer_list(3));
+
+        let (peers, _, crypto_flags) = extractor.into_output();
+        assert!(peers.is_some());
+        assert!(crypto_flags.is_none());
+    }
+
+    #[test]
+    fn test_stable_peer_selection() {
+        let mut config = TSConfig::new().unwrap();
+        config.tracker.peer_selection = PeerSelection::Stable;
+
+        let query = format!("{}&numwant=2", QUERY);
+        let req: AnnounceRequest = query::from_bytes(query.as_bytes()).unwrap();
+        let peer_id_key = PeerIdKey::new(&req.peer_id, None);
+        let dict: PeerDict = create_peer_list(5).into_iter().collect();
+
+        let announce = || {
+            let mut extractor =
+                ResponsePeersExtractor::new(&req, &peer_id_key, IpType::V4, &config);
+            extractor.from_dict(&dict);
+            match extractor.into_output() {
+                (Some(ResponsePeerList::Compact(peers)), _, _) => peers,
+                _ => panic!("expected compact peers"),
+            }
+        };
+
+        let peers = announce();
+        assert_eq!(peers, announce());
+
+        let expected: Vec<u8> = dict[0..2]
+            .iter()
+            .flat_map(|(_, peer)| peer.addr.as_bytes().to_vec())
+            .collect();
+        assert_eq!(peers, expected);
+    }
+
+    #[test]
+    fn test_random_start_from_list() {
+        let query = format!("{}&numwant=2", QUERY);
+        let req: AnnounceRequest = query::from_bytes(query.as_bytes()).unwrap();
+        let peer_id_key = PeerIdKey::new(&req.peer_id, None);
+        let config = TSConfig::new().unwrap();
+        let list = create_peer_list(5);
+
+        let mut extractor = ResponsePeersExtractor::new(&req, &peer_id_key, IpType::V4, &config);
+        extractor.random_val = 3;
+        assert_eq!(extractor.remaining(), 2);
+        extractor.from_list(&list);
+        assert_eq!(extractor.remaining(), 0);
+
+        let expected: Vec<u8> = list[3..5]
+            .iter()
+            .flat_map(|(_, peer)| peer.addr.as_bytes().to_vec())
+            .collect();
+
+        match extractor.into_output() {
+            (Some(ResponsePeerList::Compact(peers)), _, _) => assert_eq!(peers, expected),
+            _ => panic!("expected compact peers"),
+        }
+    }
+
+    #[test]
+    fn test_dedup_peer_endpoints() {
+        let mut config = TSConfig::new().unwrap();
+        let req: AnnounceRequest = query::from_bytes(QUERY.as_bytes()).unwrap();
+        let peer_id_key = PeerIdKey::new(&req.peer_id, None);
+
+        // Two entries of the same client, reconnected under a different peer id.
+        let mut peers = create_peer_list(2);
+        peers[1].1.addr = peers[0].1.addr.clone();
+
+        let mut extractor = ResponsePeersExtractor::new(&req, &peer_id_key, IpType::V4, &config);
+        extractor.from_list(&peers);
+        assert_eq!(extractor.peer_count, 2);
+
+        config.tracker.dedup_peer_endpoints = true;
+        let mut extractor = ResponsePeersExtractor::new(&req, &peer_id_key, IpType::V4, &config);
+        extractor.from_list(&peers);
+        assert_eq!(extractor.peer_count, 1);
+
+        match extractor.into_output() {
+            (Some(ResponsePeerList::Compact(bytes)), _, _) => {
+                assert_eq!(bytes, peers[0].1.addr.as_bytes())
+            }
+            _ => panic!("expected compact peers"),
+        }
+
+        // Nor is the requester, reconnected under another peer id, returned to itself.
+        let mut extractor = ResponsePeersExtractor::new(&req, &peer_id_key, IpType::V4, &config)
+            .without_addr(peers[0].1.addr.clone());
+        extractor.from_list(&peers);
+        assert_eq!(extractor.peer_count, 0);
+    }
+
+    #[test]
+    fn test_default_numwant_by_event() {
+        let mut config = TSConfig::new().unwrap();
+        config.tracker.started_numwant = 5;
+        config.tracker.periodic_numwant = 2;
+
+        let peers = create_peer_list(10);
+        let peer_count = |query: &str| {
+            let req: AnnounceRequest = query::from_bytes(query.as_bytes()).unwrap();
+            let peer_id_key = PeerIdKey::new(&req.peer_id, None);
+
+            let mut extractor =
+                ResponsePeersExtractor::new(&req, &peer_id_key, IpType::V4, &config);
+            extractor.from_list(&peers);
+            extractor.peer_count
+        };
+
+        assert_eq!(peer_count(&format!("{}&event=started", QUERY)), 5);
+        assert_eq!(peer_count(QUERY), 2);
+        assert_eq!(peer_count(&format!("{}&event=started&numwant=3", QUERY)), 3);
+    }
+
+    #[tokio::test]
+    async fn test_numwant_exceeded_warning() {
+        let config = Arc::new(TSConfig::new().unwrap());
+        let state = test_state(config.clone(), Arc::new(MemoryStorage::new()));
+
+        let query = format!("{}&numwant={}", QUERY, config.max_numwant() + 1);
+        let req: AnnounceRequest = query::from_bytes(query.as_bytes()).unwrap();
+        let input = (req, IpAddr::from([127, 0, 0, 1]));
+
+        let response: AnnounceResponse = TaskExecutor.execute(input, state).await.unwrap().into();
+
+        assert_eq!(
+            response.warning_message.as_deref(),
+            Some(constants::TRACKER_WARNING_NUMWANT_EXCEEDED)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_intervals_from_config() {
+        let mut config = TSConfig::new().unwrap();
+        config.tracker.announce_interval = 1234;
+        config.tracker.min_announce_interval = 567;
+
+        let state = test_state(Arc::new(config), Arc::new(MemoryStorage::new()));
+
+        let req: AnnounceRequest = query::from_bytes(QUERY.as_bytes()).unwrap();
+        let input = (req, IpAddr::from([127, 0, 0, 1]));
+
+        let response: AnnounceResponse = TaskExecutor.execute(input, state).await.unwrap().into();
+        assert_eq!((response.interval, response.min_interval), (1234, 567));
+
+        let bytes = response.bencode().unwrap();
+        let contains = |key: &[u8]| bytes.windows(key.len()).any(|w| w == key);
+        assert!(contains(b"8:intervali1234e"));
+        assert!(contains(b"12:min intervali567e"));
+    }
+
+    #[tokio::test]
+    async fn test_peer_id_only() {
+        for peer_id_only in [false, true] {
+            let mut config = TSConfig::new().unwrap();
+            config.tracker.peer_id_only = peer_id_only;
+
+            let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+            let state = test_state(Arc::new(config), storage.clone());
+
+            // The peer announces without a key, then with one.
+            for query in [QUERY.to_string(), format!("{}&key=abcd", QUERY)] {
+                let req: AnnounceRequest = query::from_bytes(query.as_bytes()).unwrap();
+                let input = (req, IpAddr::from([127, 0, 0, 1]));
+                TaskExecutor.execute(input, state.clone()).await.unwrap();
+            }
+
+            let info_hash = InfoHash([b'a'; INFOHASH_LENGTH]);
+            let swarm = storage
+                .list_swarm(&info_hash, IpType::V4, 10)
+                .await
+                .unwrap();
+            assert_eq!(swarm.len(), if peer_id_only { 1 } else { 2 });
+
+            // Another key is refused, and an announce without one keeps the stored key.
+            let result = announce(&format!("{}&key=dcba", QUERY), &state).await;
+            assert_eq!(result.is_err(), peer_id_only);
+            announce(QUERY, &state).await.unwrap();
+
+            if peer_id_only {
+                let result = announce(&format!("{}&key=dcba", QUERY), &state).await;
+                assert!(result.is_err());
+
+                let swarm = storage
+                    .list_swarm(&info_hash, IpType::V4, 10)
+                    .await
+                    .unwrap();
+                assert_eq!(swarm[0].2.key, Some(*b"abcd"));
+            }
+        }
+
+        async fn announce(query: &str, state: &State) -> Result<TaskOutput> {
+            let req: AnnounceRequest = query::from_bytes(query.as_bytes()).unwrap();
+            let input = (req, IpAddr::from([127, 0, 0, 1]));
+            TaskExecutor.execute(input, state.clone()).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_require_key() {
+        let mut config = TSConfig::new().unwrap();
+        config.tracker.require_key = true;
+
+        let state = test_state(Arc::new(config), Arc::new(MemoryStorage::new()));
+
+        let input = |query: String| {
+            let req: AnnounceRequest = query::from_bytes(query.as_bytes()).unwrap();
+            (req, IpAddr::from([127, 0, 0, 1]))
+        };
+
+        for query in [QUERY.to_string(), format!("{}&key=00000000", QUERY)] {
+            let err = TaskExecutor
+                .execute(input(query), state.clone())
+                .await
+                .unwrap_err();
+            assert_eq!(
+                err.tracker_error(),
+                Some(constants::TRACKER_ERROR_MISSING_KEY)
+            );
+        }
+
+        let query = format!("{}&key=1a2b3c4d", QUERY);
+        assert!(TaskExecutor.execute(input(query), state).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_ip_param_family_mismatch() {
+        let info_hash = InfoHash([b'a'; INFOHASH_LENGTH]);
+        let sender_addr = IpAddr::from(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1));
+        let query = format!("{}&ip=1.2.3.4&event=started", QUERY);
+
+        // An `ip` that is not an address, such as a DNS name, is ignored.
+        let name = format!("{}&ip=tracker.example", QUERY);
+        let req: AnnounceRequest = query::from_bytes(name.as_bytes()).unwrap();
+        assert_eq!(req.ip, None);
+
+        for mode in [
+            IpParamMode::Ignore,
+            IpParamMode::Accept,
+            IpParamMode::Strict,
+        ] {
+            let mut config = TSConfig::new().unwrap();
+            config.tracker.ip_param_mode = mode;
+
+            let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+            let state = test_state(Arc::new(config), storage.clone());
+
+            // A client connected over IPv6 from a local network declares an IPv4 address.
+            let req: AnnounceRequest = query::from_bytes(query.as_bytes()).unwrap();
+            assert_eq!(req.ip, Some(IpAddr::from([1, 2, 3, 4])));
+            let result = TaskExecutor.execute((req, sender_addr), state).await;
+
+            let v4 = storage
+                .list_swarm(&info_hash, IpType::V4, 10)
+                .await
+                .unwrap();
+            let v6 = storage
+                .list_swarm(&info_hash, IpType::V6, 10)
+                .await
+                .unwrap();
+
+            match mode {
+                IpParamMode::Ignore => {
+                    assert!(result.is_ok());
+                    assert_eq!((v4.len(), v6.len()), (0, 1));
+                }
+                IpParamMode::Accept => {
+                    assert!(result.is_ok());
+                    assert_eq!((v4.len(), v6.len()), (1, 0));
+                    let (_, _, peer) = &v4[0];
+                    assert_eq!(peer.ip_type(), IpType::V4);
+                }
+                IpParamMode::Strict => {
+                    let err = result.unwrap_err();
+                    let msg = constants::TRACKER_ERROR_IP_FAMILY_MISMATCH;
+                    assert_eq!(err.tracker_error(), Some(msg));
+                    assert_eq!((v4.len(), v6.len()), (0, 0));
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ip_param_ignored_from_global_addr() {
+        let info_hash = InfoHash([b'a'; INFOHASH_LENGTH]);
+        let query = format!("{}&ip=10.0.0.1&event=started", QUERY);
+
+        for mode in [IpParamMode::Accept, IpParamMode::Strict] {
+            let mut config = TSConfig::new().unwrap();
+            config.tracker.ip_param_mode = mode;
+
+            let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+            let state = test_state(Arc::new(config), storage.clone());
+
+            // A client on the internet is stored under the address it connected from.
+            let req: AnnounceRequest = query::from_bytes(query.as_bytes()).unwrap();
+            let sender_addr = IpAddr::from([203, 0, 113, 7]);
+            assert!(TaskExecutor
+                .execute((req, sender_addr), state)
+                .await
+                .is_ok());
+
+            let peers = storage
+                .list_swarm(&info_hash, IpType::V4, 10)
+                .await
+                .unwrap();
+            assert_eq!(peers.len(), 1);
+            let (_, _, peer) = &peers[0];
+            let addr: std::net::SocketAddr = (&peer.addr).into();
+            assert_eq!(addr.ip(), sender_addr);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_no_peers_warning() {
+        for warn_no_peers in [false, true] {
+            let mut config = TSConfig::new().unwrap();
+            config.tracker.warn_no_peers = warn_no_peers;
+
+            let state = test_state(Arc::new(config), Arc::new(MemoryStorage::new()));
+
+            // The requesting peer is the only member of the swarm.
+            let req: AnnounceRequest = query::from_bytes(QUERY.as_bytes()).unwrap();
+            let input = (req, IpAddr::from([127, 0, 0, 1]));
+            let response: AnnounceResponse =
+                TaskExecutor.execute(input, state).await.unwrap().into();
+
+            assert!(response.peers.is_none());
+            assert_eq!(response.complete + response.incomplete, 1);
+
+            let expected = warn_no_peers.then_some(constants::TRACKER_WARNING_NO_PEERS);
+            assert_eq!(response.warning_message.as_deref(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_return_both_ip_families() {
+        let mut config = TSConfig::new().unwrap();
+        config.tracker.return_both_ip_families = true;
+
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let query = format!("{}&numwant=2&supportcrypto=1", QUERY);
+        let req: AnnounceRequest = query::from_bytes(query.as_bytes()).unwrap();
+        storage.insert_torrent(&req.info_hash, None).await.unwrap();
+
+        for i in 1..=3u8 {
+            let peer_id: PeerId = [i; PEER_ID_LENGTH].into();
+            let addr: PeerAddr = match i {
+                1 => (Ipv4Addr::new(127, 0, 0, i), Port(6881)).into(),
+                _ => (Ipv6Addr::from([i as u16; 8]), Port(6881)).into(),
+            };
+
+            let peer = Peer {
+                crypto_flag: i % 2,
+                ..Peer::new_test(addr)
+            };
+
+            storage
+                .put_peer_in_swarm(
+                    &req.info_hash,
+                    &PeerIdKey::new(&peer_id, None),
+                    peer,
+                    PeerType::Leecher,
+                )
+                .await
+                .unwrap();
+        }
+
+        let state = test_state(Arc::new(config), storage);
+
+        let input = (req, IpAddr::from([127, 0, 0, 1]));
+        let response: AnnounceResponse = TaskExecutor.execute(input, state).await.unwrap().into();
+
+        match (response.peers, response.peers6) {
+            (Some(ResponsePeerList::Compact(peers)), Some(ResponsePeerList::Compact(peers6))) => {
+                // `numwant` is shared by both lists.
+                assert_eq!(peers.len(), PEER_ADDR_V4_LENGTH);
+                assert_eq!(peers6.len(), PEER_ADDR_V6_LENGTH);
+            }
+            _ => panic!("expected compact peers and peers6"),
+        }
+
+        // One flag for each list, the IPv4 peer's first.
+        let crypto_flags = response.crypto_flags.unwrap();
+        assert_eq!(crypto_flags.len(), 2);
+        assert_eq!(crypto_flags[0], 1);
+
+        assert_eq!(response.incomplete, 3);
+    }
+
+    #[test]
+    fn test_clamp_peer_counts() {
+        // Stats that lag behind the swarm report no peers at all.
+        assert_eq!(clamp_peer_counts(0, 0, 3), (0, 3));
+        assert_eq!(clamp_peer_counts(1, 0, 3), (1, 2));
+
+        // Counts that already cover the returned peers are untouched.
+        assert_eq!(clamp_peer_counts(5, 1, 3), (5, 1));
+        assert_eq!(clamp_peer_counts(0, 0, 0), (0, 0));
+    }
+
+    #[derive(Default)]
+    struct RecordingObserver {
+        counters: std::sync::Mutex<Vec<TransferCounters>>,
+    }
+
+    impl AnnounceObserver for RecordingObserver {
+        fn on_announce(&self, _info_hash: &InfoHash, peer_id: &PeerId, counters: TransferCounters) {
+            assert_eq!(peer_id.0, *b"01234567890123456789");
+            self.counters.lock().unwrap().push(counters);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_announce_observer() {
+        let observer = Arc::new(RecordingObserver::default());
+        let state = State {
+            announce_observer: Some(observer.clone()),
+            ..test_state(
+                Arc::new(TSConfig::new().unwrap()),
+                Arc::new(MemoryStorage::new()),
+            )
+        };
+
+        for (uploaded, downloaded, left) in [(0, 100, 900), (50, 400, 600)] {
+            let query = format!(
+                "{}&uploaded={}&downloaded={}&left={}",
+                QUERY, uploaded, downloaded, left
+            );
+            let req: AnnounceRequest = query::from_bytes(query.as_bytes()).unwrap();
+            let input = (req, IpAddr::from([127, 0, 0, 1]));
+            TaskExecutor.execute(input, state.clone()).await.unwrap();
+        }
+
+        let counters = observer.counters.lock().unwrap();
+        assert_eq!(counters.len(), 2);
+        assert_eq!(counters[1].uploaded.0 - counters[0].uploaded.0, 50);
+        assert_eq!(counters[1].downloaded.0 - counters[0].downloaded.0, 300);
+        assert_eq!(counters[1].left, Some(NumOfBytes(600)));
+    }
+
+    #[tokio::test]
+    async fn test_peer_type_by_left() {
+        let state = test_state(
+            Arc::new(TSConfig::new().unwrap()),
+            Arc::new(MemoryStorage::new()),
+        );
+
+        // An omitted `left` is no sign of a complete download.
+        for (peer_id, left) in [('a', ""), ('b', "&left=0"), ('c', "&left=100")] {
+            let query = format!(
+                "info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id={}&port=6881{}",
+                peer_id.to_string().repeat(PEER_ID_LENGTH),
+                left
+            );
+            let req: AnnounceRequest = query::from_bytes(query.as_bytes()).unwrap();
+            let input = (req, IpAddr::from([127, 0, 0, 1]));
+            TaskExecutor.execute(input, state.clone()).await.unwrap();
+        }
+
+        let info_hash = InfoHash([b'a'; INFOHASH_LENGTH]);
+        let stats = state
+            .storage
+            .get_torrent_stats(&info_hash, IpType::V4)
+            .await
+            .unwrap();
+        assert_eq!(stats.seeders, 1);
+        assert_eq!(stats.incomplete, 2);
+    }
+
+    #[tokio::test]
+    async fn test_leecher_becomes_seeder_without_event() {
+        let state = test_state(
+            Arc::new(TSConfig::new().unwrap()),
+            Arc::new(MemoryStorage::new()),
+        );
+
+        let info_hash = InfoHash([b'a'; INFOHASH_LENGTH]);
+        for (left, counts) in [("100", (0, 1)), ("0", (1, 0))] {
+            let query = format!("{}&left={}", QUERY, left);
+            let req: AnnounceRequest = query::from_bytes(query.as_bytes()).unwrap();
+            let input = (req, IpAddr::from([127, 0, 0, 1]));
+            TaskExecutor.execute(input, state.clone()).await.unwrap();
+
+            let stats = state
+                .storage
+                .get_torrent_stats(&info_hash, IpType::V4)
+                .await
+                .unwrap();
+            assert_eq!((stats.seeders, stats.incomplete), counts);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_identical_announces_deduplicated() {
+        let inflight = InflightAnnounces::default();
+        let counter = AtomicUsize::new(0);
+        let writes = &counter;
+
+        let key = || {
+            let peer_id: PeerId = [1; PEER_ID_LENGTH].into();
+            let info_hash = InfoHash([2; INFOHASH_LENGTH]);
+            (PeerIdKey::new(&peer_id, None), info_hash, None)
+        };
+
+        let announce = move || async move {
+            writes.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(AnnounceResponse {
+                complete: 1,
+                ..Default::default()
+            })
+        };
+
+        let (first, second) = tokio::join!(
+            inflight.run(key(), announce()),
+            inflight.run(key(), announce())
+        );
+
+        assert_eq!(first.unwrap().complete, 1);
+        assert_eq!(second.unwrap().complete, 1);
+        assert_eq!(writes.load(Ordering::SeqCst), 1);
+
+        // The entry is gone once the announce finished.
+        inflight.run(key(), announce()).await.unwrap();
+        assert_eq!(writes.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_completed_without_started() {
+        let request = |peer_id: &str, params: &str| -> AnnounceRequest {
+            let query = format!(
+                "info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id={}&port=6881&{}",
+                peer_id, params
+            );
+            query::from_bytes(query.as_bytes()).unwrap()
+        };
+
+        let sender_addr = IpAddr::from([127, 0, 0, 1]);
+
+        for lenient in [false, true] {
+            let mut config = TSConfig::new().unwrap();
+            config.tracker.lenient_completed = lenient;
+
+            let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+            let state = test_state(Arc::new(config), storage.clone());
+
+            let req = request("01234567890123456789", "left=0&event=completed");
+            let info_hash = req.info_hash.clone();
+
+            let input = (req, sender_addr);
+            let result = TaskExecutor.execute(input, state.clone()).await;
+            assert_eq!(result.is_ok(), lenient);
+
+            let torrent = storage.get_torrent(&info_hash).await.unwrap().unwrap();
+            assert_eq!(torrent.completed, lenient as u32);
+
+            // A peer that sent `started` completes in both modes.
+            let input = (
+                request("98765432109876543210", "left=10&event=started"),
+                sender_addr,
+            );
+            TaskExecutor.execute(input, state.clone()).await.unwrap();
+
+            let input = (
+                request("98765432109876543210", "left=0&event=completed"),
+                sender_addr,
+            );
+            TaskExecutor.execute(input, state).await.unwrap();
+
+            let torrent = storage.get_torrent(&info_hash).await.unwrap().unwrap();
+            assert_eq!(torrent.completed, lenient as u32 + 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compact_only() {
+        let sender_addr = IpAddr::from([127, 0, 0, 1]);
+        let seeder = "info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=98765432109876543210&port=6881&left=0";
+        let leecher = format!("{}&left=10&compact=0", QUERY);
+
+        for mode in [CompactOnlyMode::Coerce, CompactOnlyMode::Reject] {
+            let mut config = TSConfig::new().unwrap();
+            config.tracker.compact_only = true;
+            config.tracker.compact_only_mode = mode;
+
+            let state = test_state(Arc::new(config), Arc::new(MemoryStorage::new()));
+
+            let req: AnnounceRequest = query::from_bytes(seeder.as_bytes()).unwrap();
+            TaskExecutor
+                .execute((req, sender_addr), state.clone())
+                .await
+                .unwrap();
+
+            let req: AnnounceRequest = query::from_bytes(leecher.as_bytes()).unwrap();
+            let result = TaskExecutor.execute((req, sender_addr), state).await;
+
+            match mode {
+                CompactOnlyMode::Coerce => {
+                    let response: AnnounceResponse = result.unwrap().into();
+                    assert!(matches!(
+                        response.peers,
+                        Some(ResponsePeerList::Compact(peers)) if peers.len() == PEER_ADDR_V4_LENGTH
+                    ));
+                }
+                CompactOnlyMode::Reject => assert_eq!(
+                    result.unwrap_err().to_string(),
+                    constants::TRACKER_ERROR_PEER_LIST_NOT_SUPPORTED
+                ),
+            }
+        }
+    }
+}
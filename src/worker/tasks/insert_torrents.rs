@@ -0,0 +1,23 @@
+use async_trait::async_trait;
+
+use super::State;
+use crate::{
+    models::{common::InfoHash, torrent::Torrent},
+    worker::{Result, TaskOutput},
+};
+
+pub type Input = Vec<(InfoHash, Option<Torrent>)>;
+pub type Output = ();
+
+pub struct TaskExecutor;
+
+#[async_trait]
+impl super::TaskExecutor for TaskExecutor {
+    type Input = Input;
+    type Output = Output;
+
+    async fn execute(&self, input: Self::Input, state: State) -> Result<TaskOutput> {
+        state.storage.insert_torrents(input).await?;
+        Ok(TaskOutput::None)
+    }
+}
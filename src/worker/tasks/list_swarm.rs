@@ -0,0 +1,51 @@
+use async_trait::async_trait;
+
+use super::State;
+use crate::{
+    models::{
+        common::{InfoHash, IpType},
+        torrent::SwarmPeers,
+    },
+    worker::{Result, TaskOutput},
+};
+
+/// The torrent and the most peers to list.
+pub type Input = (InfoHash, usize);
+pub type Output = SwarmPeers;
+
+pub struct TaskExecutor;
+
+#[async_trait]
+impl super::TaskExecutor for TaskExecutor {
+    type Input = Input;
+    type Output = Output;
+
+    /// Lists the IPv4 peers of the torrent, then the IPv6 ones while below the limit.
+    async fn execute(&self, input: Self::Input, state: State) -> Result<TaskOutput> {
+        let (info_hash, limit) = input;
+
+        let mut peers = state
+            .storage
+            .list_swarm(&info_hash, IpType::V4, limit)
+            .await?;
+        if peers.len() < limit {
+            let remaining = limit - peers.len();
+            let peers6 = state
+                .storage
+                .list_swarm(&info_hash, IpType::V6, remaining)
+                .await?;
+            peers.extend(peers6);
+        }
+
+        Ok(TaskOutput::Swarm(peers))
+    }
+}
+
+impl From<TaskOutput> for SwarmPeers {
+    fn from(output: TaskOutput) -> Self {
+        match output {
+            TaskOutput::Swarm(peers) => peers,
+            _ => unreachable!(),
+        }
+    }
+}
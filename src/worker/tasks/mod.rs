@@ -1,11 +1,19 @@
 pub(super) mod announce;
 pub(super) mod full_scrape;
+pub(super) mod insert_torrents;
+pub(super) mod list_swarm;
+pub(super) mod remove_torrent;
 pub(super) mod scrape;
 
+use self::{
+    announce::{AnnounceObserver, InflightAnnounces},
+    scrape::ScrapeCache,
+};
 use super::{Result, TaskOutput};
 use crate::{config::TSConfig, storage::Storage};
 use async_trait::async_trait;
 use std::sync::Arc;
+use tokio::sync::Semaphore;
 
 pub(super) fn err<T>(msg: &'static str) -> Result<T> {
     Err(msg.into())
@@ -23,4 +31,21 @@ pub(super) trait TaskExecutor: Send + Sync {
 pub struct State {
     pub storage: Arc<dyn Storage>,
     pub config: Arc<TSConfig>,
+    pub scrape_cache: Option<Arc<ScrapeCache>>,
+    pub announce_observer: Option<Arc<dyn AnnounceObserver>>,
+    pub inflight_announces: Arc<InflightAnnounces>,
+    pub scrape_permits: Option<Arc<Semaphore>>,
+}
+
+/// Builds a task state around `config` and `storage` with every optional part disabled.
+#[cfg(test)]
+pub(super) fn test_state(config: Arc<TSConfig>, storage: Arc<dyn Storage>) -> State {
+    State {
+        storage,
+        config,
+        scrape_cache: None,
+        announce_observer: None,
+        inflight_announces: Default::default(),
+        scrape_permits: None,
+    }
 }
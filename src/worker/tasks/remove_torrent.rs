@@ -0,0 +1,30 @@
+use async_trait::async_trait;
+
+use super::State;
+use crate::{
+    models::common::InfoHash,
+    worker::{Result, TaskOutput},
+};
+
+pub type Input = InfoHash;
+pub type Output = ();
+
+pub struct TaskExecutor;
+
+#[async_trait]
+impl super::TaskExecutor for TaskExecutor {
+    type Input = Input;
+    type Output = Output;
+
+    async fn execute(&self, info_hash: Self::Input, state: State) -> Result<TaskOutput> {
+        state.storage.clear_swarm(&info_hash).await?;
+        state.storage.remove_torrent(&info_hash).await?;
+
+        // The stats of the torrent would otherwise still be scraped until they expire.
+        if let Some(cache) = state.scrape_cache {
+            cache.invalidate((&info_hash).into()).await;
+        }
+
+        Ok(TaskOutput::None)
+    }
+}
@@ -1,15 +1,22 @@
 use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use ts_cache::{Cache, CacheLoader, Policy};
 
 use super::State;
 use crate::{
+    config::TSConfig,
     models::{
-        common::IpType,
+        common::InfoHash,
+        torrent::{TorrentStats, TorrentStatsList},
         tracker::{ScrapeRequest, ScrapeResponse},
     },
-    worker::{Result, TaskOutput},
+    storage::Storage,
+    utils::metrics::METRICS,
+    worker::{Error, Result, TaskOutput},
 };
 
-pub type Input = (ScrapeRequest, IpType);
+pub type Input = ScrapeRequest;
 pub type Output = ScrapeResponse;
 
 pub struct TaskExecutor;
@@ -19,15 +26,362 @@ impl super::TaskExecutor for TaskExecutor {
     type Input = Input;
     type Output = Output;
 
-    async fn execute(&self, input: Self::Input, state: State) -> Result<TaskOutput> {
-        let (req, ip_type) = input;
-        let files = state
-            .storage
-            .get_multi_torrent_stats(req.info_hashes, ip_type)
-            .await?;
+    async fn execute(&self, req: Self::Input, state: State) -> Result<TaskOutput> {
+        let cache = state.scrape_cache.as_deref();
+        let permits = state.scrape_permits.as_deref();
+        let mut files = get_stats(&state.storage, cache, permits, req.info_hashes).await?;
+
+        if !state.config.report_downloaders() {
+            for (_, stats) in files.iter_mut() {
+                stats.downloaders = None;
+            }
+        }
 
-        let output = ScrapeResponse::new(files);
+        let output =
+            ScrapeResponse::new(files).with_request_order(state.config.scrape_request_order());
 
         Ok(TaskOutput::Scrape(output))
     }
 }
+
+/// Returns the stats of the given torrents, counting the peers of both IP families.
+/// Single-torrent scrapes are served from the scrape cache, if enabled. Others, and those the
+/// cache failed to load, take one of the `permits` to read the storage, and are rejected as
+/// saturated if none is left rather than waiting for one while holding a worker slot.
+async fn get_stats(
+    storage: &Arc<dyn Storage>,
+    cache: Option<&ScrapeCache>,
+    permits: Option<&Semaphore>,
+    info_hashes: Vec<InfoHash>,
+) -> Result<TorrentStatsList> {
+    if let (Some(cache), [info_hash]) = (cache, info_hashes.as_slice()) {
+        // Without a value the storage failed, and reading it again reports the error.
+        if let Some(stats) = cache.get(info_hash.into()).await {
+            return Ok(stats
+                .map(|stats| vec![(info_hash.clone(), stats)])
+                .unwrap_or_default());
+        }
+    }
+
+    let _permit = match permits.map(Semaphore::try_acquire) {
+        Some(Ok(permit)) => Some(permit),
+        Some(Err(_)) => return Err(Error::saturated()),
+        None => None,
+    };
+
+    Ok(storage.get_torrent_stats_multi_ip(info_hashes).await?)
+}
+
+/// Scrapes count the peers of both IP families, so their stats don't depend on the `IpType` of
+/// the requester and are cached by infohash alone.
+pub type ScrapeCacheKey = InfoHash;
+
+/// A cache for the stats of individually scraped torrents, `None` for unknown torrents.
+pub type ScrapeCache = Cache<ScrapeCacheKey, Option<TorrentStats>>;
+
+/// Creates the scrape cache, or `None` if it is disabled by a zero `scrape_cache_ttl`.
+/// Its loads take one of the `permits` as well.
+pub fn create_scrape_cache(
+    config: &TSConfig,
+    storage: Arc<dyn Storage>,
+    permits: Option<Arc<Semaphore>>,
+) -> Option<Arc<ScrapeCache>> {
+    let ttl = config.scrape_cache_ttl();
+    if ttl.is_zero() {
+        return None;
+    }
+
+    let cache = ScrapeCache::builder()
+        .expiry(ttl)
+        .policy(Policy::RefreshAfterAccess)
+        .build(ScrapeLoader::new(storage, permits));
+
+    METRICS.register_cache("scrape", cache.stats());
+
+    Some(Arc::new(cache))
+}
+
+/// Loads the stats of a single torrent from storage into the scrape cache.
+pub struct ScrapeLoader {
+    storage: Arc<dyn Storage>,
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl ScrapeLoader {
+    pub fn new(
+        storage: Arc<dyn Storage>,
+        permits: Option<Arc<Semaphore>>,
+    ) -> Arc<dyn CacheLoader<Key = ScrapeCacheKey, Value = Option<TorrentStats>>> {
+        Arc::new(Self { storage, permits })
+    }
+}
+
+#[async_trait]
+impl CacheLoader for ScrapeLoader {
+    type Key = ScrapeCacheKey;
+    type Value = Option<TorrentStats>;
+
+    /// Loads nothing on a storage error, so that it is not cached as an unknown torrent, nor
+    /// without a permit to read the storage.
+    async fn load(&self, info_hash: &Self::Key) -> Option<Self::Value> {
+        let _permit = match self.permits.as_deref().map(Semaphore::try_acquire) {
+            Some(permit) => Some(permit.ok()?),
+            None => None,
+        };
+
+        let stats = self
+            .storage
+            .get_torrent_stats_multi_ip(vec![info_hash.clone()])
+            .await
+            .ok()?;
+
+        Some(stats.into_iter().next().map(|(_, stats)| stats))
+    }
+
+    async fn load_all(&self, keys: &[Self::Key]) -> Vec<(Self::Key, Self::Value)> {
+        let mut results = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(stats) = self.load(key).await {
+                results.push((key.clone(), stats));
+            }
+        }
+
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{test_state, TaskExecutor as _};
+    use super::*;
+    use crate::{
+        models::{
+            common::{PeerId, Port, INFOHASH_LENGTH},
+            peer::{Peer, PeerAddr, PeerType},
+            torrent::PeerIdKey,
+        },
+        storage::MemoryStorage,
+    };
+    use std::{
+        net::{Ipv4Addr, Ipv6Addr},
+        time::Duration,
+    };
+    use ts_utils::{bencode::Bencode, query};
+
+    const INFOHASH: &str = "2a7b9e1f5c8d3a6b0f2e4c5a9b7d1e3a6c8b5d99";
+    const PEER_ID: &str = "01234567890123456789";
+
+    #[tokio::test]
+    async fn test_scrape_cache_hit_within_ttl() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let info_hash: InfoHash = INFOHASH.parse().unwrap();
+        storage.insert_torrent(&info_hash, None).await.unwrap();
+
+        let cache = ScrapeCache::builder()
+            .expiry(Duration::from_secs(60))
+            .build(ScrapeLoader::new(storage.clone(), None));
+
+        let info_hashes = vec![info_hash.clone()];
+        let files = get_stats(&storage, Some(&cache), None, info_hashes.clone())
+            .await
+            .unwrap();
+        assert_eq!(files[0].1.seeders, 0);
+
+        let peer_id: PeerId = PEER_ID.as_bytes().try_into().unwrap();
+        let peer = Peer::new_test((Ipv4Addr::LOCALHOST, Port(6881)));
+
+        storage
+            .put_peer_in_swarm(
+                &info_hash,
+                &PeerIdKey::new(&peer_id, None),
+                peer,
+                PeerType::Seeder,
+            )
+            .await
+            .unwrap();
+
+        // The second scrape within the ttl does not reach the storage.
+        let files = get_stats(&storage, Some(&cache), None, info_hashes.clone())
+            .await
+            .unwrap();
+        assert_eq!(files[0].1.seeders, 0);
+
+        let files = get_stats(&storage, None, None, info_hashes).await.unwrap();
+        assert_eq!(files[0].1.seeders, 1);
+    }
+
+    #[tokio::test]
+    async fn test_scrape_cache_unknown_torrent() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let info_hash: InfoHash = INFOHASH.parse().unwrap();
+
+        let cache = ScrapeCache::builder()
+            .expiry(Duration::from_secs(60))
+            .build(ScrapeLoader::new(storage.clone(), None));
+
+        // An unknown torrent is loaded, and cached, as such rather than as a failed load.
+        let info_hashes = vec![info_hash.clone()];
+        let files = get_stats(&storage, Some(&cache), None, info_hashes.clone())
+            .await
+            .unwrap();
+        assert!(files.is_empty());
+        assert!(matches!(cache.get((&info_hash).into()).await, Some(None)));
+    }
+
+    #[tokio::test]
+    async fn test_scrape_downloaders() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let info_hash: InfoHash = INFOHASH.parse().unwrap();
+        storage.insert_torrent(&info_hash, None).await.unwrap();
+
+        for (peer_id, peer_type) in [
+            ("01234567890123456789", PeerType::Leecher),
+            ("98765432109876543210", PeerType::Partial),
+        ] {
+            let peer_id: PeerId = peer_id.as_bytes().try_into().unwrap();
+            let peer = Peer::new_test((Ipv4Addr::LOCALHOST, Port(6881)));
+
+            storage
+                .put_peer_in_swarm(&info_hash, &PeerIdKey::new(&peer_id, None), peer, peer_type)
+                .await
+                .unwrap();
+        }
+
+        let files = get_stats(&storage, None, None, vec![info_hash])
+            .await
+            .unwrap();
+        assert_eq!(files[0].1.incomplete, 2);
+        assert_eq!(files[0].1.downloaders, Some(1));
+
+        let bytes = ScrapeResponse::new(files).bencode().unwrap();
+        let expected = b"d8:completei0e10:downloadedi0e11:downloadersi1e10:incompletei2ee";
+        assert!(bytes.ends_with(&[expected.as_slice(), b"ee"].concat()));
+    }
+
+    #[tokio::test]
+    async fn test_scrape_files_order() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let info_hash_a = InfoHash([b'a'; INFOHASH_LENGTH]);
+        let info_hash_b = InfoHash([b'b'; INFOHASH_LENGTH]);
+        for info_hash in [&info_hash_a, &info_hash_b] {
+            storage.insert_torrent(info_hash, None).await.unwrap();
+        }
+
+        let position = |bytes: &[u8], info_hash: &InfoHash| {
+            let key = [b"20:".as_slice(), info_hash.as_ref()].concat();
+            bytes.windows(key.len()).position(|w| w == key)
+        };
+
+        for request_order in [false, true] {
+            let mut config = TSConfig::new().unwrap();
+            config.tracker.scrape_request_order = request_order;
+
+            let state = test_state(Arc::new(config), storage.clone());
+
+            let query = b"info_hash=bbbbbbbbbbbbbbbbbbbb&info_hash=aaaaaaaaaaaaaaaaaaaa";
+            let req: ScrapeRequest = query::from_bytes(query).unwrap();
+
+            let response: ScrapeResponse = TaskExecutor.execute(req, state).await.unwrap().into();
+            let bytes = response.bencode().unwrap();
+
+            let a = position(&bytes, &info_hash_a).unwrap();
+            let b = position(&bytes, &info_hash_b).unwrap();
+            assert_eq!(b < a, request_order);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scrapes_beyond_permits_rejected() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let info_hash: InfoHash = INFOHASH.parse().unwrap();
+        storage.insert_torrent(&info_hash, None).await.unwrap();
+
+        let permits = Arc::new(Semaphore::new(1));
+        let state = State {
+            scrape_permits: Some(permits.clone()),
+            ..test_state(Arc::new(TSConfig::new().unwrap()), storage.clone())
+        };
+
+        let cache = ScrapeCache::builder()
+            .expiry(Duration::from_secs(60))
+            .build(ScrapeLoader::new(storage.clone(), Some(permits.clone())));
+
+        let req = || ScrapeRequest {
+            info_hashes: vec![info_hash.clone()],
+            format: None,
+        };
+
+        // The only permit is held, as by a scrape reading the storage.
+        let permit = permits.acquire().await.unwrap();
+
+        // A scrape is rejected rather than waiting for the permit in a worker slot, and so is
+        // one the cache can't load.
+        let err = TaskExecutor
+            .execute(req(), state.clone())
+            .await
+            .unwrap_err();
+        assert!(err.is_saturated());
+
+        let info_hashes = vec![info_hash.clone()];
+        let err = get_stats(&storage, Some(&cache), Some(&permits), info_hashes.clone())
+            .await
+            .unwrap_err();
+        assert!(err.is_saturated());
+        assert!(cache.get((&info_hash).into()).await.is_none());
+
+        drop(permit);
+        let response: ScrapeResponse = TaskExecutor.execute(req(), state).await.unwrap().into();
+        assert_eq!(response.files.len(), 1);
+
+        let files = get_stats(&storage, Some(&cache), Some(&permits), info_hashes)
+            .await
+            .unwrap();
+        assert_eq!(files.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_scrape_counts_both_ip_families() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let info_hash: InfoHash = INFOHASH.parse().unwrap();
+        storage.insert_torrent(&info_hash, None).await.unwrap();
+
+        let peers: [(&str, PeerAddr, PeerType); 3] = [
+            (
+                "01234567890123456789",
+                (Ipv4Addr::LOCALHOST, Port(6881)).into(),
+                PeerType::Seeder,
+            ),
+            (
+                "98765432109876543210",
+                (Ipv6Addr::LOCALHOST, Port(6881)).into(),
+                PeerType::Seeder,
+            ),
+            (
+                "abcdefghijabcdefghij",
+                (Ipv6Addr::LOCALHOST, Port(6882)).into(),
+                PeerType::Leecher,
+            ),
+        ];
+
+        for (peer_id, addr, peer_type) in peers {
+            let peer_id: PeerId = peer_id.as_bytes().try_into().unwrap();
+            let peer = Peer::new_test(addr);
+
+            storage
+                .put_peer_in_swarm(&info_hash, &PeerIdKey::new(&peer_id, None), peer, peer_type)
+                .await
+                .unwrap();
+        }
+
+        let cache = ScrapeCache::builder().build(ScrapeLoader::new(storage.clone(), None));
+        let info_hashes = vec![info_hash];
+
+        for cache in [None, Some(&cache)] {
+            let files = get_stats(&storage, cache, None, info_hashes.clone())
+                .await
+                .unwrap();
+            assert_eq!(files[0].1.seeders, 2);
+            assert_eq!(files[0].1.incomplete, 1);
+        }
+    }
+}